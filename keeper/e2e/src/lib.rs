@@ -0,0 +1,159 @@
+//! In-process bank for running the radar_lend keeper end to end.
+//!
+//! `BankChain` implements the keeper's `Chain` over a `solana-program-test` bank running the
+//! radar_lend program natively, so tests can drive the program and the keeper against the
+//! same state.
+
+use std::cell::RefCell;
+
+use anchor_lang::solana_program::entrypoint::ProgramResult;
+use anchor_lang::solana_program::program_pack::{IsInitialized, Pack};
+use anyhow::{anyhow, bail, Result};
+use radar_keeper::Chain;
+use solana_program_test::{processor, BanksClient, ProgramTest, ProgramTestContext};
+use solana_sdk::account::{Account, AccountSharedData};
+use solana_sdk::account_info::AccountInfo;
+use solana_sdk::clock::Clock;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signature, Signer};
+use solana_sdk::transaction::Transaction;
+use tokio::runtime::Runtime;
+
+/// Runs radar_lend's Anchor entrypoint. `processor!` needs a function over any account
+/// lifetime, which the entrypoint isn't, so the accounts are leaked for the life of the test.
+fn process(program_id: &Pubkey, accounts: &[AccountInfo], data: &[u8]) -> ProgramResult {
+    let accounts = Box::leak(Box::new(accounts.to_vec()));
+    radar_lend::entry(program_id, accounts, data)
+}
+
+/// A `ProgramTest` with radar_lend loaded, to add accounts to before starting a `BankChain`.
+pub fn program_test() -> ProgramTest {
+    ProgramTest::new("radar_lend", radar_lend::ID, processor!(process))
+}
+
+/// A bank running radar_lend, seen through the keeper's `Chain`.
+///
+/// Banks can't list a program's accounts, so `program_accounts` only scans the addresses
+/// passed to `watch`.
+pub struct BankChain {
+    runtime: Runtime,
+    context: RefCell<ProgramTestContext>,
+    watched: RefCell<Vec<Pubkey>>,
+}
+
+impl BankChain {
+    /// Starts the bank.
+    pub fn start(program_test: ProgramTest) -> Result<Self> {
+        let runtime = Runtime::new()?;
+        let context = runtime.block_on(program_test.start_with_context());
+        Ok(Self {
+            runtime,
+            context: RefCell::new(context),
+            watched: RefCell::new(Vec::new()),
+        })
+    }
+
+    /// Adds `address` to the accounts `program_accounts` scans.
+    pub fn watch(&self, address: Pubkey) {
+        self.watched.borrow_mut().push(address);
+    }
+
+    /// Moves the clock to `unix_timestamp`, on a new slot.
+    pub fn set_unix_timestamp(&self, unix_timestamp: i64) -> Result<()> {
+        let mut context = self.context.borrow_mut();
+        let mut clock: Clock = self.runtime.block_on(context.banks_client.get_sysvar())?;
+        context.warp_to_slot(clock.slot + 1).map_err(|err| anyhow!("warp: {err:?}"))?;
+        clock.slot += 1;
+        clock.unix_timestamp = unix_timestamp;
+        context.set_sysvar(&clock);
+        Ok(())
+    }
+
+    /// Adds `value` packed into an account owned by `owner`, such as an SPL token account.
+    pub fn set_packed<T: Pack>(&self, address: Pubkey, value: T, owner: &Pubkey) -> Result<()> {
+        let mut data = vec![0; T::LEN];
+        value.pack_into_slice(&mut data);
+        let rent = self.runtime.block_on(self.banks().get_rent())?;
+        let account = Account {
+            lamports: rent.minimum_balance(T::LEN),
+            data,
+            owner: *owner,
+            executable: false,
+            rent_epoch: 0,
+        };
+        self.context.borrow_mut().set_account(&address, &AccountSharedData::from(account));
+        Ok(())
+    }
+
+    /// Returns the account at `address` unpacked, such as an SPL token account.
+    pub fn packed<T: Pack + IsInitialized>(&self, address: &Pubkey) -> Result<T> {
+        let account = self.account(address)?.ok_or_else(|| anyhow!("account {address} not found"))?;
+        Ok(T::unpack(&account.data)?)
+    }
+
+    /// Returns the lamports held at `address`.
+    pub fn lamports(&self, address: &Pubkey) -> Result<u64> {
+        Ok(self.runtime.block_on(self.banks().get_balance(*address))?)
+    }
+
+    fn banks(&self) -> BanksClient {
+        self.context.borrow().banks_client.clone()
+    }
+}
+
+impl Chain for BankChain {
+    fn account(&self, address: &Pubkey) -> Result<Option<Account>> {
+        Ok(self.runtime.block_on(self.banks().get_account(*address))?)
+    }
+
+    fn program_accounts(
+        &self,
+        program_id: &Pubkey,
+        filters: &[(usize, Vec<u8>)],
+    ) -> Result<Vec<(Pubkey, Account)>> {
+        let mut accounts = Vec::new();
+        for address in self.watched.borrow().iter() {
+            let Some(account) = self.account(address)? else {
+                continue;
+            };
+            let matches = filters
+                .iter()
+                .all(|(offset, bytes)| account.data.get(*offset..offset + bytes.len()) == Some(&bytes[..]));
+            if account.owner == *program_id && matches {
+                accounts.push((*address, account));
+            }
+        }
+        Ok(accounts)
+    }
+
+    fn unix_timestamp(&self) -> Result<i64> {
+        let clock: Clock = self.runtime.block_on(self.banks().get_sysvar())?;
+        Ok(clock.unix_timestamp)
+    }
+
+    fn simulate_return_data(&self, instruction: Instruction, payer: &Pubkey) -> Result<Vec<u8>> {
+        let mut banks = self.banks();
+        let mut transaction = Transaction::new_with_payer(&[instruction], Some(payer));
+        transaction.message.recent_blockhash = self.runtime.block_on(banks.get_latest_blockhash())?;
+        let simulation = self.runtime.block_on(banks.simulate_transaction(transaction))?;
+        if let Some(Err(err)) = simulation.result {
+            bail!("simulation failed: {err}");
+        }
+        let return_data = simulation
+            .simulation_details
+            .and_then(|details| details.return_data)
+            .ok_or_else(|| anyhow!("simulation set no return data"))?;
+        Ok(return_data.data)
+    }
+
+    fn send(&self, instructions: &[Instruction], signer: &Keypair) -> Result<Signature> {
+        let mut banks = self.banks();
+        let blockhash = self.runtime.block_on(banks.get_latest_blockhash())?;
+        let transaction =
+            Transaction::new_signed_with_payer(instructions, Some(&signer.pubkey()), &[signer], blockhash);
+        let signature = transaction.signatures[0];
+        self.runtime.block_on(banks.process_transaction(transaction))?;
+        Ok(signature)
+    }
+}
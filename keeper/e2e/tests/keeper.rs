@@ -0,0 +1,323 @@
+use anchor_lang::solana_program::program_option::COption;
+use anchor_lang::{AccountDeserialize, InstructionData, ToAccountMetas};
+use anchor_spl::associated_token::get_associated_token_address;
+use anchor_spl::token::spl_token;
+use radar_keeper::{ActionLog, Chain, Keeper, KeeperConfig};
+use radar_keeper_e2e::BankChain;
+use radar_lend::auction::{AuctionConfig, LiquidationMode};
+use radar_lend::oracle::OracleSource;
+use radar_lend::{Loan, LoanTerm};
+use serde_json::Value;
+use solana_sdk::account::Account;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::native_token::LAMPORTS_PER_SOL;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+
+/// Fee the bank charges per signature.
+const SIGNATURE_FEE: u64 = 5_000;
+
+/// A market holding 1,000 USDC, priced by the mock oracle, with one loan of 1 USDC against
+/// 0.02 SOL at the 8% tier.
+struct Market {
+    admin: Keypair,
+    keeper: Keypair, // Pays for the keeper's transactions, holding 10 USDC
+    address: Pubkey,
+    usdc_mint: Pubkey,
+    loan: Pubkey,
+}
+
+impl Market {
+    /// Starts a bank and sets up the market on it.
+    fn start() -> (BankChain, Self) {
+        let admin = Keypair::new();
+        let borrower = Keypair::new();
+        let keeper = Keypair::new();
+        let (address, _) = Pubkey::find_program_address(&[b"shrub", admin.pubkey().as_ref()], &radar_lend::ID);
+        // Markets number their loans from 1
+        let (loan, _) =
+            Pubkey::find_program_address(&[b"loan", address.as_ref(), &1u64.to_le_bytes()], &radar_lend::ID);
+        let market = Self {
+            admin,
+            keeper,
+            address,
+            usdc_mint: Pubkey::new_unique(),
+            loan,
+        };
+
+        let mut program_test = radar_keeper_e2e::program_test();
+        for keypair in [&market.admin, &borrower, &market.keeper] {
+            program_test.add_account(
+                keypair.pubkey(),
+                Account {
+                    lamports: 10 * LAMPORTS_PER_SOL,
+                    ..Account::default()
+                },
+            );
+        }
+        let chain = BankChain::start(program_test).unwrap();
+
+        // Create the USDC mint and token accounts
+        chain
+            .set_packed(
+                market.usdc_mint,
+                spl_token::state::Mint {
+                    mint_authority: COption::Some(market.admin.pubkey()),
+                    supply: 1_010_000_000,
+                    decimals: 6,
+                    is_initialized: true,
+                    freeze_authority: COption::None,
+                },
+                &spl_token::ID,
+            )
+            .unwrap();
+        for (owner, amount) in [(&market.admin, 1_000_000_000), (&borrower, 0), (&market.keeper, 10_000_000)] {
+            chain
+                .set_packed(
+                    get_associated_token_address(&owner.pubkey(), &market.usdc_mint),
+                    spl_token::state::Account {
+                        mint: market.usdc_mint,
+                        owner: owner.pubkey(),
+                        amount,
+                        state: spl_token::state::AccountState::Initialized,
+                        ..spl_token::state::Account::default()
+                    },
+                    &spl_token::ID,
+                )
+                .unwrap();
+        }
+
+        market.send_as_admin(
+            &chain,
+            instruction(
+                radar_lend::accounts::Initialize {
+                    admin: market.admin.pubkey(),
+                    pda_account: address,
+                    market_config: market.market_config(),
+                    shrub_usdc_account: market.pool(),
+                    usdc_mint: market.usdc_mint,
+                    system_program: solana_sdk::system_program::ID,
+                    token_program: spl_token::ID,
+                    associated_token_program: anchor_spl::associated_token::ID,
+                    rent: solana_sdk::sysvar::rent::ID,
+                },
+                radar_lend::instruction::Initialize {},
+            ),
+        );
+        market.send_as_admin(
+            &chain,
+            instruction(
+                radar_lend::accounts::DepositUsdc {
+                    admin: market.admin.pubkey(),
+                    pda_account: address,
+                    admin_usdc_account: get_associated_token_address(&market.admin.pubkey(), &market.usdc_mint),
+                    shrub_usdc_account: market.pool(),
+                    token_program: spl_token::ID,
+                },
+                radar_lend::instruction::DepositUsdc { amount: 1_000_000_000 },
+            ),
+        );
+        market.set_mock_price(&chain, 100_000_000);
+        market.send_as_admin(
+            &chain,
+            instruction(
+                radar_lend::accounts::SetOracle {
+                    admin: market.admin.pubkey(),
+                    pda_account: address,
+                    oracle_program: radar_lend::ID,
+                    price_feed: market.mock_price(),
+                },
+                radar_lend::instruction::SetOracle {
+                    source: OracleSource::Mock,
+                    max_price_age: 300,
+                },
+            ),
+        );
+
+        let take_loan = instruction(
+            radar_lend::accounts::TakeLoan {
+                pda_account: address,
+                market_config: market.market_config(),
+                admin: market.admin.pubkey(),
+                user: borrower.pubkey(),
+                loan,
+                borrower_index: Pubkey::find_program_address(
+                    &[b"borrower", address.as_ref(), borrower.pubkey().as_ref()],
+                    &radar_lend::ID,
+                )
+                .0,
+                price: radar_lend::accounts::PriceAccounts {
+                    oracle_program: radar_lend::ID,
+                    price_feed: market.mock_price(),
+                    secondary_oracle_program: None,
+                    secondary_price_feed: None,
+                    price_history: None,
+                },
+                user_usdc_account: get_associated_token_address(&borrower.pubkey(), &market.usdc_mint),
+                shrub_usdc_account: market.pool(),
+                usdc_mint: market.usdc_mint,
+                system_program: solana_sdk::system_program::ID,
+                token_program: spl_token::ID,
+                associated_token_program: anchor_spl::associated_token::ID,
+            },
+            radar_lend::instruction::TakeLoan {
+                principal: 1_000_000,
+                apy: 800,
+                collateral: 20_000_000,
+                term: LoanTerm::OneMonth,
+            },
+        );
+        chain.send(&[take_loan], &borrower).unwrap();
+        chain.watch(loan);
+
+        (chain, market)
+    }
+
+    fn market_config(&self) -> Pubkey {
+        Pubkey::find_program_address(&[b"market_config", self.address.as_ref()], &radar_lend::ID).0
+    }
+
+    fn mock_price(&self) -> Pubkey {
+        Pubkey::find_program_address(&[b"mock_price", self.address.as_ref()], &radar_lend::ID).0
+    }
+
+    fn pool(&self) -> Pubkey {
+        get_associated_token_address(&self.address, &self.usdc_mint)
+    }
+
+    fn send_as_admin(&self, chain: &BankChain, instruction: Instruction) {
+        chain.send(&[instruction], &self.admin).unwrap();
+    }
+
+    fn set_mock_price(&self, chain: &BankChain, price: u64) {
+        self.send_as_admin(
+            chain,
+            instruction(
+                radar_lend::accounts::SetMockPrice {
+                    admin: self.admin.pubkey(),
+                    pda_account: self.address,
+                    mock_price: self.mock_price(),
+                    system_program: solana_sdk::system_program::ID,
+                },
+                radar_lend::instruction::SetMockPrice { price, conf: 0 },
+            ),
+        );
+    }
+
+    fn set_liquidation_mode(&self, chain: &BankChain, liquidation_mode: LiquidationMode, auction_config: AuctionConfig) {
+        self.send_as_admin(
+            chain,
+            instruction(
+                radar_lend::accounts::SetLiquidationMode {
+                    admin: self.admin.pubkey(),
+                    pda_account: self.address,
+                },
+                radar_lend::instruction::SetLiquidationMode {
+                    liquidation_mode,
+                    auction_config,
+                },
+            ),
+        );
+    }
+
+    /// The keeper's USDC balance and lamports.
+    fn keeper_balances(&self, chain: &BankChain) -> (u64, u64) {
+        let usdc_account = get_associated_token_address(&self.keeper.pubkey(), &self.usdc_mint);
+        let usdc = chain.packed::<spl_token::state::Account>(&usdc_account).unwrap().amount;
+        (usdc, chain.lamports(&self.keeper.pubkey()).unwrap())
+    }
+
+    /// A keeper watching the market on `chain`, acting on any profit.
+    fn keeper(&self, chain: BankChain) -> Keeper<BankChain, Vec<u8>> {
+        let config = KeeperConfig {
+            market: self.address,
+            usdc_mint: self.usdc_mint,
+            min_profit: 0,
+            dry_run: false,
+        };
+        let payer = Keypair::from_bytes(&self.keeper.to_bytes()).unwrap();
+        Keeper::new(chain, payer, config, ActionLog::new(Vec::new()))
+    }
+}
+
+fn instruction(accounts: impl ToAccountMetas, data: impl InstructionData) -> Instruction {
+    Instruction {
+        program_id: radar_lend::ID,
+        accounts: accounts.to_account_metas(None),
+        data: data.data(),
+    }
+}
+
+fn parse_log(log: Vec<u8>) -> Vec<Value> {
+    String::from_utf8(log)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect()
+}
+
+#[test]
+fn liquidates_an_unhealthy_loan_on_chain() {
+    let (chain, market) = Market::start();
+    // At $65/SOL the collateral is worth $1.30, an LTV of ~77% against the 70% threshold
+    market.set_mock_price(&chain, 65_000_000);
+    let before = market.keeper_balances(&chain);
+    let mut keeper = market.keeper(chain);
+
+    assert_eq!(keeper.run_once().unwrap(), 1);
+
+    // The close factor caps repayment at half the debt; the liquidator receives it plus the
+    // 5% bonus in SOL at $65
+    let account = keeper.chain().account(&market.loan).unwrap().unwrap();
+    let loan = Loan::try_deserialize(&mut &account.data[..]).unwrap();
+    assert_eq!(loan.principal, 500_000);
+    assert_eq!(loan.collateral, 20_000_000 - 8_076_923);
+    let after = market.keeper_balances(keeper.chain());
+    assert_eq!(before.0 - after.0, 500_000);
+    assert_eq!(after.1 - before.1, 8_076_923 - SIGNATURE_FEE);
+
+    let log = parse_log(keeper.into_log());
+    assert_eq!(log[1]["event"], "liquidate");
+    assert_eq!(log[1]["status"], "sent");
+}
+
+#[test]
+fn starts_and_fills_an_auction_on_chain() {
+    let (chain, market) = Market::start();
+    let auction_config = AuctionConfig {
+        start_discount_bps: 0,
+        max_discount_bps: 2_000,
+        duration: 1_000,
+        penalty_bps: 500,
+    };
+    market.set_liquidation_mode(&chain, LiquidationMode::DutchAuction, auction_config);
+    market.set_mock_price(&chain, 65_000_000);
+    let (auction, _) = Pubkey::find_program_address(&[b"auction", market.loan.as_ref()], &radar_lend::ID);
+    let mut keeper = market.keeper(chain);
+
+    // The first pass starts the auction at $65/SOL
+    assert_eq!(keeper.run_once().unwrap(), 1);
+    assert!(keeper.chain().account(&auction).unwrap().is_some());
+
+    // Halfway through, the discount is 10%: $58.50/SOL against the oracle's $65
+    let now = keeper.chain().unix_timestamp().unwrap();
+    keeper.chain().set_unix_timestamp(now + 500).unwrap();
+    market.set_mock_price(keeper.chain(), 65_000_000);
+    let before = market.keeper_balances(keeper.chain());
+
+    assert_eq!(keeper.run_once().unwrap(), 1);
+
+    // The 1,050,000 target buys 0.017948718 SOL; the auction settles and closes the loan,
+    // refunding the auction account's rent to the keeper that started it
+    assert!(keeper.chain().account(&market.loan).unwrap().is_none());
+    assert!(keeper.chain().account(&auction).unwrap().is_none());
+    let after = market.keeper_balances(keeper.chain());
+    assert_eq!(before.0 - after.0, 1_050_001);
+    assert!(after.1 > before.1 + 17_948_718 - SIGNATURE_FEE);
+
+    let log = parse_log(keeper.into_log());
+    assert_eq!(log[1]["event"], "start_auction");
+    assert_eq!(log[3]["event"], "fill_auction");
+    assert_eq!(log[3]["status"], "sent");
+    assert_eq!(log[3]["seized"], 17_948_718);
+}
@@ -0,0 +1,94 @@
+use anyhow::{anyhow, bail, Result};
+use base64::Engine;
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::rpc_client::RpcClient;
+use solana_client::rpc_config::{
+    RpcAccountInfoConfig, RpcProgramAccountsConfig, RpcSimulateTransactionConfig,
+};
+use solana_client::rpc_filter::{Memcmp, RpcFilterType};
+use solana_sdk::account::{from_account, Account};
+use solana_sdk::clock::Clock;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signature, Signer};
+use solana_sdk::sysvar;
+use solana_sdk::transaction::Transaction;
+
+/// The keeper's view of the cluster.
+///
+/// Implemented for `RpcClient` to run against a validator, and by in-memory banks in tests.
+pub trait Chain {
+    /// Returns the account at `address`, if it exists.
+    fn account(&self, address: &Pubkey) -> Result<Option<Account>>;
+
+    /// Returns the accounts owned by `program_id` whose data contains each `(offset, bytes)`.
+    fn program_accounts(
+        &self,
+        program_id: &Pubkey,
+        filters: &[(usize, Vec<u8>)],
+    ) -> Result<Vec<(Pubkey, Account)>>;
+
+    /// Returns the cluster's current unix timestamp.
+    fn unix_timestamp(&self) -> Result<i64>;
+
+    /// Simulates `instruction` paid for by `payer` and returns the data it set with `set_return_data`.
+    fn simulate_return_data(&self, instruction: Instruction, payer: &Pubkey) -> Result<Vec<u8>>;
+
+    /// Signs and sends a transaction, returning its signature once confirmed.
+    fn send(&self, instructions: &[Instruction], signer: &Keypair) -> Result<Signature>;
+}
+
+impl Chain for RpcClient {
+    fn account(&self, address: &Pubkey) -> Result<Option<Account>> {
+        Ok(self.get_account_with_commitment(address, self.commitment())?.value)
+    }
+
+    fn program_accounts(
+        &self,
+        program_id: &Pubkey,
+        filters: &[(usize, Vec<u8>)],
+    ) -> Result<Vec<(Pubkey, Account)>> {
+        let config = RpcProgramAccountsConfig {
+            filters: Some(
+                filters
+                    .iter()
+                    .map(|(offset, bytes)| RpcFilterType::Memcmp(Memcmp::new_base58_encoded(*offset, bytes)))
+                    .collect(),
+            ),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                ..RpcAccountInfoConfig::default()
+            },
+            ..RpcProgramAccountsConfig::default()
+        };
+        Ok(self.get_program_accounts_with_config(program_id, config)?)
+    }
+
+    fn unix_timestamp(&self) -> Result<i64> {
+        let account = self.get_account(&sysvar::clock::ID)?;
+        let clock: Clock = from_account(&account).ok_or_else(|| anyhow!("invalid clock sysvar"))?;
+        Ok(clock.unix_timestamp)
+    }
+
+    fn simulate_return_data(&self, instruction: Instruction, payer: &Pubkey) -> Result<Vec<u8>> {
+        let transaction = Transaction::new_with_payer(&[instruction], Some(payer));
+        let config = RpcSimulateTransactionConfig {
+            sig_verify: false,
+            replace_recent_blockhash: true,
+            ..RpcSimulateTransactionConfig::default()
+        };
+        let result = self.simulate_transaction_with_config(&transaction, config)?.value;
+        if let Some(err) = result.err {
+            bail!("simulation failed: {err} {:?}", result.logs.unwrap_or_default());
+        }
+        let return_data = result.return_data.ok_or_else(|| anyhow!("simulation set no return data"))?;
+        Ok(base64::engine::general_purpose::STANDARD.decode(return_data.data.0)?)
+    }
+
+    fn send(&self, instructions: &[Instruction], signer: &Keypair) -> Result<Signature> {
+        let blockhash = self.get_latest_blockhash()?;
+        let transaction =
+            Transaction::new_signed_with_payer(instructions, Some(&signer.pubkey()), &[signer], blockhash);
+        Ok(self.send_and_confirm_transaction(&transaction)?)
+    }
+}
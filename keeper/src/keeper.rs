@@ -0,0 +1,412 @@
+use std::io::Write;
+
+use anchor_lang::{AccountDeserialize, Discriminator, InstructionData, ToAccountMetas};
+use anchor_spl::associated_token::get_associated_token_address;
+use anyhow::{anyhow, Result};
+use radar_lend::auction::{Auction, LiquidationMode};
+use radar_lend::oracle::PriceMode;
+use radar_lend::{DataAccount, Loan};
+use radar_math::Rounding;
+use serde_json::json;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signer};
+
+use crate::chain::Chain;
+use crate::log::ActionLog;
+use crate::price;
+
+/// Offset of `Loan::market` in a loan account: discriminator (8) + id (8).
+const LOAN_MARKET_OFFSET: usize = 8 + 8;
+
+/// What the keeper acts on and how.
+#[derive(Clone, Debug)]
+pub struct KeeperConfig {
+    pub market: Pubkey,    // Market PDA to watch
+    pub usdc_mint: Pubkey, // USDC mint of the market
+    pub min_profit: u64,   // Minimum expected profit of a liquidation or fill, in micro-USDC
+    pub dry_run: bool,     // Log planned actions without sending them
+}
+
+/// Scans a market's loans and liquidates, or starts auctions for, those that are unhealthy or
+/// expired, auctioning the collateral of expired loans that are unprofitable to liquidate, and
+/// bids in running auctions that sell collateral below the oracle price.
+pub struct Keeper<C: Chain, W: Write> {
+    chain: C,
+    payer: Keypair,
+    config: KeeperConfig,
+    log: ActionLog<W>,
+}
+
+/// A transaction the keeper decided to send for a loan.
+struct Action {
+    event: &'static str,
+    instruction: Instruction,
+    repay: Option<u64>,
+    seized: Option<u64>,
+    expected_profit: Option<i128>,
+}
+
+impl<C: Chain, W: Write> Keeper<C, W> {
+    pub fn new(chain: C, payer: Keypair, config: KeeperConfig, log: ActionLog<W>) -> Self {
+        Self { chain, payer, config, log }
+    }
+
+    pub fn chain(&self) -> &C {
+        &self.chain
+    }
+
+    /// Returns the writer the action log was written to.
+    pub fn into_log(self) -> W {
+        self.log.into_inner()
+    }
+
+    /// Runs a single pass over the market, returning the number of loans acted on.
+    ///
+    /// A price that cannot be read is logged without error, so a polling keeper keeps running
+    /// through oracle outages.
+    pub fn run_once(&mut self) -> Result<usize> {
+        let now = self.chain.unix_timestamp()?;
+        let market = self.market()?;
+        let sol_price = match price::collateral_price(
+            &self.chain,
+            &radar_lend::ID,
+            &self.config.market,
+            &market,
+            &self.payer.pubkey(),
+            now,
+        ) {
+            Ok(price) => Some(price),
+            Err(err) => {
+                self.log.record(json!({
+                    "timestamp": now,
+                    "event": "error",
+                    "error": format!("price unavailable: {err}"),
+                }))?;
+                None
+            }
+        };
+
+        let loans = self.loans()?;
+        self.log.record(json!({
+            "timestamp": now,
+            "event": "scan",
+            "loans": loans.len(),
+            "sol_price": sol_price,
+        }))?;
+
+        let borrow_index = market.borrow_index_at(now)?;
+        let mut acted = 0;
+        for (address, loan) in loans {
+            let debt = loan.total_owed(now, borrow_index)?;
+            let expired = loan.is_expired(now, market.grace_period);
+            let liquidatable = match sol_price {
+                Some(price) => loan.is_liquidatable(debt, price)?,
+                None => false,
+            };
+            if !loan.in_auction && !expired && !liquidatable {
+                continue;
+            }
+
+            let entry = json!({
+                "timestamp": now,
+                "loan": address.to_string(),
+                "loan_id": loan.id,
+                "borrower": loan.borrower.to_string(),
+                "debt": debt,
+                "collateral": loan.collateral,
+                "sol_price": sol_price,
+            });
+            // Every action prices the collateral, so without a price there is nothing to do
+            let Some(sol_price) = sol_price else {
+                self.record(entry, json!({ "event": "skip", "reason": "price unavailable" }))?;
+                continue;
+            };
+
+            let action = if loan.in_auction {
+                self.plan_fill(&market, &address, &loan, sol_price, now)
+            } else {
+                self.plan(&market, &address, &loan, debt, sol_price, expired)
+            };
+            let action = match action {
+                Ok(action) => action,
+                Err(err) => {
+                    self.record(entry, json!({ "event": "error", "error": err.to_string() }))?;
+                    continue;
+                }
+            };
+
+            let mut result = json!({
+                "event": action.event,
+                "repay": action.repay,
+                "seized": action.seized,
+                "expected_profit": action.expected_profit.map(|profit| profit as i64),
+                "dry_run": self.config.dry_run,
+            });
+            let min_profit = self.config.min_profit as i128;
+            if action.expected_profit.is_some_and(|profit| profit < min_profit) {
+                result["event"] = json!("skip");
+                result["reason"] = json!("below minimum profit");
+                self.record(entry, result)?;
+                continue;
+            }
+
+            if self.config.dry_run {
+                result["status"] = json!("planned");
+            } else {
+                match self.chain.send(&[action.instruction], &self.payer) {
+                    Ok(signature) => {
+                        result["status"] = json!("sent");
+                        result["signature"] = json!(signature.to_string());
+                    }
+                    Err(err) => {
+                        result["status"] = json!("failed");
+                        result["error"] = json!(err.to_string());
+                    }
+                }
+            }
+            self.record(entry, result)?;
+            acted += 1;
+        }
+
+        Ok(acted)
+    }
+
+    /// Loads the watched market.
+    fn market(&self) -> Result<DataAccount> {
+        let account = self
+            .chain
+            .account(&self.config.market)?
+            .ok_or_else(|| anyhow!("market {} not found", self.config.market))?;
+        Ok(DataAccount::try_deserialize(&mut &account.data[..])?)
+    }
+
+    /// Loads the market's open loans, ordered by id.
+    fn loans(&self) -> Result<Vec<(Pubkey, Loan)>> {
+        let filters = [
+            (0, Loan::DISCRIMINATOR.to_vec()),
+            (LOAN_MARKET_OFFSET, self.config.market.to_bytes().to_vec()),
+        ];
+        let mut loans = self
+            .chain
+            .program_accounts(&radar_lend::ID, &filters)?
+            .into_iter()
+            .map(|(address, account)| Ok((address, Loan::try_deserialize(&mut &account.data[..])?)))
+            .collect::<Result<Vec<_>>>()?;
+        loans.sort_by_key(|(_, loan)| loan.id);
+        Ok(loans)
+    }
+
+    /// Builds the transaction for an unhealthy or expired loan according to the market's
+    /// liquidation mode. Expired loans that are unprofitable to liquidate are expired instead,
+    /// which auctions their collateral.
+    fn plan(
+        &self,
+        market: &DataAccount,
+        address: &Pubkey,
+        loan: &Loan,
+        debt: u64,
+        sol_price: u64,
+        expired: bool,
+    ) -> Result<Action> {
+        match market.liquidation_mode {
+            LiquidationMode::FixedBonus => {
+                let (repay, seized) =
+                    market.liquidation_quote(debt, loan.collateral, sol_price, u64::MAX, expired)?;
+                let seized_value = radar_math::collateral_value(seized, sol_price, Rounding::Down)
+                    .map_err(|err| anyhow!("collateral value: {err:?}"))?;
+                let expected_profit = seized_value as i128 - repay as i128;
+                if expired && expected_profit < self.config.min_profit as i128 {
+                    return Ok(Action {
+                        event: "expire",
+                        instruction: self.expire_instruction(market, address, loan),
+                        repay: None,
+                        seized: None,
+                        expected_profit: None,
+                    });
+                }
+                Ok(Action {
+                    event: "liquidate",
+                    instruction: self.liquidate_instruction(market, address, loan, repay),
+                    repay: Some(repay),
+                    seized: Some(seized),
+                    expected_profit: Some(expected_profit),
+                })
+            }
+            LiquidationMode::DutchAuction => Ok(Action {
+                event: "start_auction",
+                instruction: self.start_auction_instruction(market, address, loan),
+                repay: None,
+                seized: None,
+                expected_profit: None,
+            }),
+        }
+    }
+
+    /// Bids for as much of an auctioned loan's collateral as the rest of the auction's target
+    /// buys at the current auction price. The profit is the collateral's value at the oracle
+    /// price less what it costs.
+    fn plan_fill(
+        &self,
+        market: &DataAccount,
+        address: &Pubkey,
+        loan: &Loan,
+        sol_price: u64,
+        now: i64,
+    ) -> Result<Action> {
+        let auction = self.auction(address)?;
+        let price = auction.price(now);
+        let lamports = auction.collateral_remaining.min(auction.collateral_to_target(price)?);
+        let cost = Auction::cost(lamports, price)?;
+        if lamports == 0 || cost == 0 {
+            return Err(anyhow!("auction has nothing left to sell"));
+        }
+        let value = radar_math::collateral_value(lamports, sol_price, Rounding::Down)
+            .map_err(|err| anyhow!("collateral value: {err:?}"))?;
+        Ok(Action {
+            event: "fill_auction",
+            instruction: self.fill_auction_instruction(market, address, loan, &auction, lamports),
+            repay: Some(cost),
+            seized: Some(lamports),
+            expected_profit: Some(value as i128 - cost as i128),
+        })
+    }
+
+    /// Loads the auction of the loan at `address`.
+    fn auction(&self, address: &Pubkey) -> Result<Auction> {
+        let account = self
+            .chain
+            .account(&auction_address(address))?
+            .ok_or_else(|| anyhow!("auction of loan {address} not found"))?;
+        Ok(Auction::try_deserialize(&mut &account.data[..])?)
+    }
+
+    fn liquidate_instruction(&self, market: &DataAccount, address: &Pubkey, loan: &Loan, repay: u64) -> Instruction {
+        let borrower_index = self.borrower_index(loan);
+        let accounts = radar_lend::accounts::LiquidateLoan {
+            pda_account: self.config.market,
+            admin: market.admin,
+            liquidator: self.payer.pubkey(),
+            loan: *address,
+            borrower: loan.borrower,
+            borrower_index,
+            price: self.price_accounts(market),
+            liquidator_usdc_account: get_associated_token_address(&self.payer.pubkey(), &self.config.usdc_mint),
+            shrub_usdc_account: get_associated_token_address(&self.config.market, &self.config.usdc_mint),
+            token_program: anchor_spl::token::ID,
+        };
+        Instruction {
+            program_id: radar_lend::ID,
+            accounts: accounts.to_account_metas(None),
+            data: radar_lend::instruction::LiquidateLoan {
+                loan_id: loan.id,
+                repay_amount: repay,
+            }
+            .data(),
+        }
+    }
+
+    fn start_auction_instruction(&self, market: &DataAccount, address: &Pubkey, loan: &Loan) -> Instruction {
+        let accounts = radar_lend::accounts::StartAuction {
+            pda_account: self.config.market,
+            admin: market.admin,
+            starter: self.payer.pubkey(),
+            loan: *address,
+            auction: auction_address(address),
+            price: self.price_accounts(market),
+            system_program: solana_sdk::system_program::ID,
+        };
+        Instruction {
+            program_id: radar_lend::ID,
+            accounts: accounts.to_account_metas(None),
+            data: radar_lend::instruction::StartAuction { loan_id: loan.id }.data(),
+        }
+    }
+
+    fn fill_auction_instruction(
+        &self,
+        market: &DataAccount,
+        address: &Pubkey,
+        loan: &Loan,
+        auction: &Auction,
+        lamports: u64,
+    ) -> Instruction {
+        let accounts = radar_lend::accounts::FillAuction {
+            pda_account: self.config.market,
+            admin: market.admin,
+            bidder: self.payer.pubkey(),
+            loan: *address,
+            auction: auction_address(address),
+            borrower: loan.borrower,
+            starter: auction.starter,
+            borrower_index: self.borrower_index(loan),
+            bidder_usdc_account: get_associated_token_address(&self.payer.pubkey(), &self.config.usdc_mint),
+            shrub_usdc_account: get_associated_token_address(&self.config.market, &self.config.usdc_mint),
+            borrower_usdc_account: get_associated_token_address(&loan.borrower, &self.config.usdc_mint),
+            token_program: anchor_spl::token::ID,
+        };
+        Instruction {
+            program_id: radar_lend::ID,
+            accounts: accounts.to_account_metas(None),
+            data: radar_lend::instruction::FillAuction {
+                loan_id: loan.id,
+                collateral_amount: lamports,
+            }
+            .data(),
+        }
+    }
+
+    fn expire_instruction(&self, market: &DataAccount, address: &Pubkey, loan: &Loan) -> Instruction {
+        let accounts = radar_lend::accounts::ExpireLoan {
+            pda_account: self.config.market,
+            admin: market.admin,
+            starter: self.payer.pubkey(),
+            loan: *address,
+            auction: auction_address(address),
+            price: self.price_accounts(market),
+            system_program: solana_sdk::system_program::ID,
+        };
+        Instruction {
+            program_id: radar_lend::ID,
+            accounts: accounts.to_account_metas(None),
+            data: radar_lend::instruction::ExpireLoan { loan_id: loan.id }.data(),
+        }
+    }
+
+    /// The borrower's position index in the watched market.
+    fn borrower_index(&self, loan: &Loan) -> Pubkey {
+        Pubkey::find_program_address(
+            &[b"borrower", self.config.market.as_ref(), loan.borrower.as_ref()],
+            &radar_lend::ID,
+        )
+        .0
+    }
+
+    /// The oracle accounts pricing the market's collateral: the secondary oracle only if one is
+    /// configured, and the price history only if the market's price mode needs it.
+    fn price_accounts(&self, market: &DataAccount) -> radar_lend::accounts::PriceAccounts {
+        let secondary = market.secondary_oracle.feed != Pubkey::default();
+        radar_lend::accounts::PriceAccounts {
+            oracle_program: market.oracle.program,
+            price_feed: market.oracle.feed,
+            secondary_oracle_program: secondary.then_some(market.secondary_oracle.program),
+            secondary_price_feed: secondary.then_some(market.secondary_oracle.feed),
+            price_history: (market.price_mode != PriceMode::Spot).then(|| {
+                Pubkey::find_program_address(&[b"price_history", self.config.market.as_ref()], &radar_lend::ID).0
+            }),
+        }
+    }
+
+    /// Logs a loan's details merged with the outcome of acting on it.
+    fn record(&mut self, mut entry: serde_json::Value, result: serde_json::Value) -> Result<()> {
+        if let (Some(entry), Some(result)) = (entry.as_object_mut(), result.as_object()) {
+            entry.extend(result.iter().filter(|(_, v)| !v.is_null()).map(|(k, v)| (k.clone(), v.clone())));
+        }
+        self.log.record(entry)
+    }
+}
+
+/// The auction account of the loan at `address`.
+fn auction_address(address: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"auction", address.as_ref()], &radar_lend::ID).0
+}
@@ -0,0 +1,15 @@
+//! Keeper bot for radar_lend markets.
+//!
+//! Each pass loads every open loan of a market, reprices its collateral the same way the
+//! program does, and liquidates (or starts an auction for) loans past their liquidation
+//! threshold, bidding in running auctions when it profits. Actions are written to a JSON-lines
+//! log.
+
+pub mod chain;
+pub mod keeper;
+pub mod log;
+pub mod price;
+
+pub use chain::Chain;
+pub use keeper::{Keeper, KeeperConfig};
+pub use log::ActionLog;
@@ -0,0 +1,27 @@
+use std::io::Write;
+
+use anyhow::Result;
+use serde_json::Value;
+
+/// Append-only log of keeper actions, written as one JSON object per line.
+pub struct ActionLog<W: Write> {
+    out: W,
+}
+
+impl<W: Write> ActionLog<W> {
+    pub fn new(out: W) -> Self {
+        Self { out }
+    }
+
+    /// Writes `entry` as a single line and flushes it, so the log survives a crash.
+    pub fn record(&mut self, entry: Value) -> Result<()> {
+        writeln!(self.out, "{entry}")?;
+        self.out.flush()?;
+        Ok(())
+    }
+
+    /// Returns the underlying writer.
+    pub fn into_inner(self) -> W {
+        self.out
+    }
+}
@@ -0,0 +1,86 @@
+use std::fs::OpenOptions;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::{anyhow, Result};
+use clap::Parser;
+use radar_keeper::{ActionLog, Keeper, KeeperConfig};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::read_keypair_file;
+
+/// Liquidates unhealthy radar_lend loans.
+#[derive(Parser)]
+#[command(version)]
+struct Args {
+    /// RPC endpoint of the cluster.
+    #[arg(long, default_value = "http://127.0.0.1:8899")]
+    rpc_url: String,
+
+    /// Market PDA to watch.
+    #[arg(long)]
+    market: Pubkey,
+
+    /// USDC mint of the market.
+    #[arg(long)]
+    usdc_mint: Pubkey,
+
+    /// Keypair paying for and signing transactions [default: ~/.config/solana/id.json].
+    #[arg(long)]
+    keypair: Option<PathBuf>,
+
+    /// Minimum expected profit of a liquidation or auction fill, in micro-USDC.
+    #[arg(long, default_value_t = 0)]
+    min_profit: u64,
+
+    /// Log the actions that would be taken without sending transactions.
+    #[arg(long)]
+    dry_run: bool,
+
+    /// File to append the JSON action log to [default: stdout].
+    #[arg(long)]
+    log: Option<PathBuf>,
+
+    /// Seconds between passes. Runs a single pass if not set.
+    #[arg(long)]
+    interval: Option<u64>,
+}
+
+fn main() -> Result<()> {
+    let args = Args::parse();
+
+    let keypair_path = match args.keypair {
+        Some(path) => path,
+        None => PathBuf::from(std::env::var("HOME")?).join(".config/solana/id.json"),
+    };
+    let payer = read_keypair_file(&keypair_path)
+        .map_err(|err| anyhow!("failed to read keypair {}: {err}", keypair_path.display()))?;
+
+    let out: Box<dyn Write> = match &args.log {
+        Some(path) => Box::new(OpenOptions::new().create(true).append(true).open(path)?),
+        None => Box::new(io::stdout()),
+    };
+
+    let chain = RpcClient::new_with_commitment(args.rpc_url, CommitmentConfig::confirmed());
+    let config = KeeperConfig {
+        market: args.market,
+        usdc_mint: args.usdc_mint,
+        min_profit: args.min_profit,
+        dry_run: args.dry_run,
+    };
+    let mut keeper = Keeper::new(chain, payer, config, ActionLog::new(out));
+
+    let Some(interval) = args.interval else {
+        keeper.run_once()?;
+        return Ok(());
+    };
+    loop {
+        if let Err(err) = keeper.run_once() {
+            eprintln!("keeper pass failed: {err:#}");
+        }
+        thread::sleep(Duration::from_secs(interval));
+    }
+}
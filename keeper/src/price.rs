@@ -0,0 +1,100 @@
+use anchor_lang::AccountDeserialize;
+use anyhow::Result;
+use radar_lend::oracle::{self, OracleConfig, OracleSource, Price, PriceHistory, PriceMode};
+use radar_lend::DataAccount;
+use solana_sdk::instruction::{AccountMeta, Instruction};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::chain::Chain;
+
+/// Discriminator of the Chainlink store's `query` instruction.
+const CHAINLINK_QUERY_DISCRIMINATOR: [u8; 8] = [0x27, 0xfb, 0x82, 0x9f, 0x2e, 0x88, 0xa4, 0xa9];
+
+/// Borsh tags of the Chainlink store's `Query` variants the keeper uses.
+const CHAINLINK_QUERY_DECIMALS: u8 = 1;
+const CHAINLINK_QUERY_LATEST_ROUND_DATA: u8 = 4;
+
+/// Reads the price the program would value collateral at: the primary and secondary oracles
+/// combined by the market's price guard, with the market's price mode applied. Fails while the
+/// oracles disagree, as the program then refuses to liquidate.
+pub fn collateral_price<C: Chain>(
+    chain: &C,
+    program_id: &Pubkey,
+    market_address: &Pubkey,
+    market: &DataAccount,
+    payer: &Pubkey,
+    now: i64,
+) -> Result<u64> {
+    let primary = read_oracle(chain, &market.oracle, payer, now)?;
+    let secondary = if market.secondary_oracle.feed == Pubkey::default() {
+        None
+    } else {
+        Some(read_oracle(chain, &market.secondary_oracle, payer, now)?)
+    };
+    let spot = market.price_guard.combine(primary, secondary)?.undisputed()?;
+
+    let price_history = if market.price_mode == PriceMode::Spot {
+        None
+    } else {
+        let (address, _) =
+            Pubkey::find_program_address(&[b"price_history", market_address.as_ref()], program_id);
+        match chain.account(&address)? {
+            Some(account) => Some(PriceHistory::try_deserialize(&mut &account.data[..])?),
+            None => None,
+        }
+    };
+
+    Ok(market
+        .price_mode
+        .select(spot, price_history.as_ref(), now, market.twap_window)?)
+}
+
+/// Reads one oracle. Chain errors are returned as `Err`; an unusable price (missing, invalid or
+/// stale) is returned as `Ok(Err(..))` so it can fall back to the secondary as it does on chain.
+fn read_oracle<C: Chain>(
+    chain: &C,
+    oracle: &OracleConfig,
+    payer: &Pubkey,
+    now: i64,
+) -> Result<anchor_lang::Result<Price>> {
+    if oracle.feed == Pubkey::default() {
+        return Ok(Err(radar_lend::ErrorCode::PriceFeedNotConfigured.into()));
+    }
+
+    let price = match oracle.source {
+        OracleSource::Chainlink => {
+            let decimals = query_chainlink(chain, oracle, payer, CHAINLINK_QUERY_DECIMALS)?;
+            let round = query_chainlink(chain, oracle, payer, CHAINLINK_QUERY_LATEST_ROUND_DATA)?;
+            match (decimals.first(), round.get(..32)) {
+                // Round: round_id u32, slot u64, timestamp u32, answer i128
+                (Some(&decimals), Some(round)) => oracle::chainlink_price(
+                    i128::from_le_bytes(round[16..32].try_into()?),
+                    decimals,
+                    u32::from_le_bytes(round[12..16].try_into()?),
+                ),
+                _ => Err(radar_lend::ErrorCode::InvalidPriceFeed.into()),
+            }
+        }
+        OracleSource::Pyth | OracleSource::Mock => match chain.account(&oracle.feed)? {
+            Some(account) if account.owner == oracle.program => match oracle.source {
+                OracleSource::Pyth => oracle::parse_pyth(&account.data),
+                _ => oracle::parse_mock(&account.data),
+            },
+            _ => Err(radar_lend::ErrorCode::InvalidPriceFeed.into()),
+        },
+    };
+
+    Ok(price.and_then(|price| oracle.check_price(price, now)))
+}
+
+/// Simulates a Chainlink store `query` and returns the raw answer.
+fn query_chainlink<C: Chain>(chain: &C, oracle: &OracleConfig, payer: &Pubkey, scope: u8) -> Result<Vec<u8>> {
+    let mut data = CHAINLINK_QUERY_DISCRIMINATOR.to_vec();
+    data.push(scope);
+    let instruction = Instruction {
+        program_id: oracle.program,
+        accounts: vec![AccountMeta::new_readonly(oracle.feed, false)],
+        data,
+    };
+    chain.simulate_return_data(instruction, payer)
+}
@@ -0,0 +1,456 @@
+use std::cell::RefCell;
+use std::collections::HashMap;
+
+use anchor_lang::{AccountDeserialize, AccountSerialize, Discriminator};
+use anyhow::{bail, Result};
+use radar_keeper::{ActionLog, Chain, Keeper, KeeperConfig};
+use radar_lend::auction::{Auction, AuctionConfig, LiquidationMode};
+use radar_lend::oracle::{MockPrice, OracleConfig, OracleSource, PriceGuard, PriceMode};
+use radar_lend::rate::{RateMode, RateModel};
+use radar_lend::{DataAccount, EarlyRepayment, Loan, LoanTerm};
+use serde_json::Value;
+use solana_sdk::account::Account;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::signature::{Keypair, Signature};
+
+const NOW: i64 = 1_700_000_000;
+const GRACE_PERIOD: u32 = 259_200;
+
+/// In-memory chain holding the market, its mock price and loans. Sent transactions are recorded.
+struct FakeChain {
+    accounts: HashMap<Pubkey, Account>,
+    sent: RefCell<Vec<Vec<Instruction>>>,
+}
+
+impl FakeChain {
+    fn get<T: AccountDeserialize>(&self, address: &Pubkey) -> T {
+        T::try_deserialize(&mut &self.accounts[address].data[..]).unwrap()
+    }
+
+    fn insert<T: AccountSerialize>(&mut self, address: Pubkey, value: &T) {
+        let mut data = Vec::new();
+        value.try_serialize(&mut data).unwrap();
+        self.accounts.insert(
+            address,
+            Account {
+                lamports: 1_000_000,
+                data,
+                owner: radar_lend::ID,
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+    }
+}
+
+impl Chain for FakeChain {
+    fn account(&self, address: &Pubkey) -> Result<Option<Account>> {
+        Ok(self.accounts.get(address).cloned())
+    }
+
+    fn program_accounts(
+        &self,
+        program_id: &Pubkey,
+        filters: &[(usize, Vec<u8>)],
+    ) -> Result<Vec<(Pubkey, Account)>> {
+        Ok(self
+            .accounts
+            .iter()
+            .filter(|(_, account)| account.owner == *program_id)
+            .filter(|(_, account)| {
+                filters
+                    .iter()
+                    .all(|(offset, bytes)| account.data.get(*offset..offset + bytes.len()) == Some(&bytes[..]))
+            })
+            .map(|(address, account)| (*address, account.clone()))
+            .collect())
+    }
+
+    fn unix_timestamp(&self) -> Result<i64> {
+        Ok(NOW)
+    }
+
+    fn simulate_return_data(&self, _instruction: Instruction, _payer: &Pubkey) -> Result<Vec<u8>> {
+        bail!("simulation is not supported")
+    }
+
+    fn send(&self, instructions: &[Instruction], _signer: &Keypair) -> Result<Signature> {
+        self.sent.borrow_mut().push(instructions.to_vec());
+        Ok(Signature::new_unique())
+    }
+}
+
+struct Setup {
+    chain: FakeChain,
+    market: Pubkey,
+    loan: Pubkey,
+}
+
+/// A market priced by a mock feed with one loan of 1 USDC against 0.02 SOL at a 70% threshold.
+fn setup(sol_price: u64, publish_time: i64, liquidation_mode: LiquidationMode) -> Setup {
+    let admin = Pubkey::new_unique();
+    let (market, bump) = Pubkey::find_program_address(&[b"shrub", admin.as_ref()], &radar_lend::ID);
+    let feed = Pubkey::new_unique();
+    let mut chain = FakeChain {
+        accounts: HashMap::new(),
+        sent: RefCell::new(Vec::new()),
+    };
+
+    chain.insert(
+        market,
+        &DataAccount {
+            admin,
+            bump,
+            oracle: OracleConfig {
+                source: OracleSource::Mock,
+                program: radar_lend::ID,
+                feed,
+                max_price_age: 60,
+            },
+            secondary_oracle: OracleConfig::default(),
+            price_guard: PriceGuard {
+                max_conf_bps: 200,
+                max_deviation_bps: 500,
+                ..PriceGuard::default()
+            },
+            price_mode: PriceMode::Spot,
+            twap_window: 1_800,
+            next_loan_id: 1,
+            liquidation_bonus_bps: 500,
+            close_factor_bps: 5_000,
+            liquidation_mode,
+            auction_config: AuctionConfig::default(),
+            grace_period: GRACE_PERIOD,
+            rate_mode: RateMode::Fixed,
+            rate_model: RateModel::default(),
+            borrow_index: radar_math::WAD,
+            borrow_rate: 0,
+            last_index_update: NOW,
+            total_principal: 1_000_000,
+            usdc_mint: Pubkey::new_unique(),
+        },
+    );
+    chain.insert(
+        feed,
+        &MockPrice {
+            market,
+            price: sol_price,
+            conf: 0,
+            publish_time,
+        },
+    );
+
+    let loan = insert_loan(&mut chain, market, 0, false, NOW + LoanTerm::OneMonth.duration());
+    Setup { chain, market, loan }
+}
+
+fn insert_loan(chain: &mut FakeChain, market: Pubkey, id: u64, in_auction: bool, matures_at: i64) -> Pubkey {
+    let (address, bump) =
+        Pubkey::find_program_address(&[b"loan", market.as_ref(), &id.to_le_bytes()], &radar_lend::ID);
+    chain.insert(
+        address,
+        &Loan {
+            id,
+            market,
+            principal: 1_000_000,
+            apy: 800,
+            rate_mode: RateMode::Fixed,
+            ltv: 5_000,
+            liquidation_threshold: 7_000,
+            origination_price: 100_000_000,
+            tier_version: 0,
+            early_repayment: EarlyRepayment::None,
+            collateral: 20_000_000,
+            created_at: NOW,
+            term: LoanTerm::OneMonth,
+            matures_at,
+            accrued_interest: 0,
+            last_accrual: NOW,
+            borrow_index: radar_math::WAD,
+            borrower: Pubkey::new_unique(),
+            bump,
+            in_auction,
+        },
+    );
+    address
+}
+
+/// Puts the loan at `address` up for auction, started `elapsed` seconds ago at $100/SOL to raise
+/// 1 USDC plus the 5% penalty. Returns the auction's starter.
+fn insert_auction(chain: &mut FakeChain, market: Pubkey, address: Pubkey, elapsed: i64) -> Pubkey {
+    let loan: Loan = chain.get(&address);
+    let (auction, bump) = Pubkey::find_program_address(&[b"auction", address.as_ref()], &radar_lend::ID);
+    let starter = Pubkey::new_unique();
+    chain.insert(
+        auction,
+        &Auction {
+            market,
+            loan_id: loan.id,
+            borrower: loan.borrower,
+            starter,
+            started_at: NOW - elapsed,
+            start_price: 100_000_000,
+            config: AuctionConfig::default(),
+            debt: 1_000_000,
+            penalty: 50_000,
+            collateral_remaining: loan.collateral,
+            usdc_raised: 0,
+            bump,
+        },
+    );
+    starter
+}
+
+/// Runs one keeper pass and returns the number of loans acted on, the transactions sent and the
+/// parsed log.
+fn run(setup: Setup, min_profit: u64, dry_run: bool) -> (usize, Vec<Vec<Instruction>>, Vec<Value>) {
+    let config = KeeperConfig {
+        market: setup.market,
+        usdc_mint: Pubkey::new_unique(),
+        min_profit,
+        dry_run,
+    };
+    let mut keeper = Keeper::new(setup.chain, Keypair::new(), config, ActionLog::new(Vec::new()));
+    let acted = keeper.run_once().unwrap();
+    let sent = keeper.chain().sent.take();
+    let log = keeper.into_log();
+    let entries = String::from_utf8(log)
+        .unwrap()
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap())
+        .collect();
+    (acted, sent, entries)
+}
+
+#[test]
+fn leaves_healthy_loans_alone() {
+    let (acted, sent, log) = run(setup(100_000_000, NOW, LiquidationMode::FixedBonus), 0, false);
+
+    assert_eq!(acted, 0);
+    assert!(sent.is_empty());
+    assert_eq!(log.len(), 1);
+    assert_eq!(log[0]["event"], "scan");
+    assert_eq!(log[0]["loans"], 1);
+    assert_eq!(log[0]["sol_price"], 100_000_000);
+}
+
+#[test]
+fn dry_run_logs_without_sending() {
+    let (acted, sent, log) = run(setup(65_000_000, NOW, LiquidationMode::FixedBonus), 0, true);
+
+    assert_eq!(acted, 1);
+    assert!(sent.is_empty());
+    assert_eq!(log.len(), 2);
+    let entry = &log[1];
+    assert_eq!(entry["event"], "liquidate");
+    assert_eq!(entry["status"], "planned");
+    assert_eq!(entry["dry_run"], true);
+    assert_eq!(entry["debt"], 1_000_000);
+    // The close factor caps repayment at half the debt; the bonus is 5%
+    assert_eq!(entry["repay"], 500_000);
+    assert_eq!(entry["seized"], 8_076_923);
+    assert_eq!(entry["expected_profit"], 24_999);
+}
+
+#[test]
+fn liquidates_unhealthy_loans() {
+    let mut setup = setup(65_000_000, NOW, LiquidationMode::FixedBonus);
+    // Loans belonging to other markets are ignored
+    insert_loan(&mut setup.chain, Pubkey::new_unique(), 2, false, NOW);
+    let loan = setup.loan;
+
+    let (acted, sent, log) = run(setup, 0, false);
+
+    assert_eq!(acted, 1);
+    assert_eq!(sent.len(), 1);
+    let instruction = &sent[0][0];
+    assert_eq!(instruction.program_id, radar_lend::ID);
+    assert_eq!(instruction.data[..8], radar_lend::instruction::LiquidateLoan::DISCRIMINATOR);
+    assert_eq!(instruction.accounts[3].pubkey, loan);
+    assert_eq!(log[0]["loans"], 1);
+    assert_eq!(log[1]["status"], "sent");
+    assert!(log[1]["signature"].is_string());
+}
+
+#[test]
+fn skips_liquidations_below_min_profit() {
+    let (acted, sent, log) = run(setup(65_000_000, NOW, LiquidationMode::FixedBonus), 30_000, false);
+
+    assert_eq!(acted, 0);
+    assert!(sent.is_empty());
+    assert_eq!(log[1]["event"], "skip");
+    assert_eq!(log[1]["expected_profit"], 24_999);
+}
+
+#[test]
+fn starts_auctions_in_auction_markets() {
+    let setup = setup(65_000_000, NOW, LiquidationMode::DutchAuction);
+    let loan = setup.loan;
+
+    let (acted, sent, log) = run(setup, 30_000, false);
+
+    assert_eq!(acted, 1);
+    let instruction = &sent[0][0];
+    assert_eq!(instruction.data[..8], radar_lend::instruction::StartAuction::DISCRIMINATOR);
+    assert_eq!(instruction.accounts[3].pubkey, loan);
+    let (auction, _) = Pubkey::find_program_address(&[b"auction", loan.as_ref()], &radar_lend::ID);
+    assert_eq!(instruction.accounts[4].pubkey, auction);
+    assert_eq!(log[1]["event"], "start_auction");
+    assert_eq!(log[1]["status"], "sent");
+}
+
+#[test]
+fn logs_stale_prices_without_acting() {
+    let (acted, sent, log) = run(setup(40_000_000, NOW - 120, LiquidationMode::FixedBonus), 0, false);
+
+    assert_eq!(acted, 0);
+    assert!(sent.is_empty());
+    assert_eq!(log.len(), 2);
+    assert_eq!(log[0]["event"], "error");
+    assert_eq!(log[1]["event"], "scan");
+    assert!(log[1]["sol_price"].is_null());
+}
+
+#[test]
+fn liquidates_expired_loans_in_full() {
+    let mut setup = setup(100_000_000, NOW, LiquidationMode::FixedBonus);
+    // A healthy loan past maturity but within the grace period is left alone
+    insert_loan(&mut setup.chain, setup.market, 1, false, NOW - GRACE_PERIOD as i64);
+    let expired = insert_loan(&mut setup.chain, setup.market, 2, false, NOW - GRACE_PERIOD as i64 - 1);
+
+    let (acted, sent, log) = run(setup, 0, false);
+
+    assert_eq!(acted, 1);
+    let instruction = &sent[0][0];
+    assert_eq!(instruction.data[..8], radar_lend::instruction::LiquidateLoan::DISCRIMINATOR);
+    assert_eq!(instruction.accounts[3].pubkey, expired);
+    assert_eq!(log[1]["event"], "liquidate");
+    assert_eq!(log[1]["loan_id"], 2);
+    // The close factor does not apply to expired loans
+    assert_eq!(log[1]["repay"], 1_000_000);
+}
+
+#[test]
+fn auctions_expired_loans_unprofitable_to_liquidate() {
+    let mut setup = setup(100_000_000, NOW, LiquidationMode::FixedBonus);
+    let expired = insert_loan(&mut setup.chain, setup.market, 1, false, NOW - GRACE_PERIOD as i64 - 1);
+
+    // Liquidating the whole debt at the 5% bonus would make 50,000
+    let (acted, sent, log) = run(setup, 50_001, false);
+
+    assert_eq!(acted, 1);
+    let instruction = &sent[0][0];
+    assert_eq!(instruction.data[..8], radar_lend::instruction::ExpireLoan::DISCRIMINATOR);
+    assert_eq!(instruction.accounts[3].pubkey, expired);
+    let (auction, _) = Pubkey::find_program_address(&[b"auction", expired.as_ref()], &radar_lend::ID);
+    assert_eq!(instruction.accounts[4].pubkey, auction);
+    assert_eq!(log[1]["event"], "expire");
+    assert_eq!(log[1]["status"], "sent");
+}
+
+#[test]
+fn skips_expired_loans_without_a_price() {
+    let mut setup = setup(100_000_000, NOW - 120, LiquidationMode::FixedBonus);
+    insert_loan(&mut setup.chain, setup.market, 1, false, NOW - GRACE_PERIOD as i64 - 1);
+
+    let (acted, sent, log) = run(setup, 0, false);
+
+    assert_eq!(acted, 0);
+    assert!(sent.is_empty());
+    assert_eq!(log[0]["event"], "error");
+    assert_eq!(log[2]["event"], "skip");
+    assert_eq!(log[2]["reason"], "price unavailable");
+}
+
+#[test]
+fn skips_liquidations_while_the_oracles_disagree() {
+    let mut setup = setup(65_000_000, NOW, LiquidationMode::FixedBonus);
+    let secondary = Pubkey::new_unique();
+    let mut market: DataAccount = setup.chain.get(&setup.market);
+    market.secondary_oracle = OracleConfig {
+        source: OracleSource::Mock,
+        program: radar_lend::ID,
+        feed: secondary,
+        max_price_age: 60,
+    };
+    setup.chain.insert(setup.market, &market);
+    setup.chain.insert(
+        secondary,
+        &MockPrice {
+            market: setup.market,
+            price: 100_000_000,
+            conf: 0,
+            publish_time: NOW,
+        },
+    );
+
+    let (acted, sent, log) = run(setup, 0, false);
+
+    assert_eq!(acted, 0);
+    assert!(sent.is_empty());
+    assert_eq!(log[0]["event"], "error");
+    assert_eq!(log[1]["loans"], 1);
+    assert_eq!(log.len(), 2);
+}
+
+#[test]
+fn liquidates_variable_rate_loans_at_their_indexed_debt() {
+    // At $85 the loan can carry 1.19 USDC of debt before it is liquidatable
+    let mut setup = setup(85_000_000, NOW, LiquidationMode::FixedBonus);
+    let mut market: DataAccount = setup.chain.get(&setup.market);
+    market.rate_mode = RateMode::Variable;
+    market.borrow_rate = 2_000;
+    market.last_index_update = NOW - 31_536_000;
+    setup.chain.insert(setup.market, &market);
+    let mut loan: Loan = setup.chain.get(&setup.loan);
+    loan.rate_mode = RateMode::Variable;
+    setup.chain.insert(setup.loan, &loan);
+
+    let (acted, sent, log) = run(setup, 0, false);
+
+    // A year at 20% grows the index, and the debt with it, to 1.2 USDC
+    assert_eq!(acted, 1);
+    assert_eq!(sent[0][0].data[..8], radar_lend::instruction::LiquidateLoan::DISCRIMINATOR);
+    assert_eq!(log[1]["debt"], 1_200_000);
+    assert_eq!(log[1]["repay"], 600_000);
+}
+
+#[test]
+fn fills_auctions_below_the_oracle_price() {
+    let mut setup = setup(100_000_000, NOW, LiquidationMode::DutchAuction);
+    let loan = insert_loan(&mut setup.chain, setup.market, 1, true, NOW + LoanTerm::OneMonth.duration());
+    // Halfway through the hour-long auction, the discount is 10%: $90/SOL
+    let starter = insert_auction(&mut setup.chain, setup.market, loan, 1_800);
+
+    let (acted, sent, log) = run(setup, 100_000, false);
+
+    assert_eq!(acted, 1);
+    let instruction = &sent[0][0];
+    assert_eq!(instruction.data[..8], radar_lend::instruction::FillAuction::DISCRIMINATOR);
+    assert_eq!(instruction.accounts[3].pubkey, loan);
+    let (auction, _) = Pubkey::find_program_address(&[b"auction", loan.as_ref()], &radar_lend::ID);
+    assert_eq!(instruction.accounts[4].pubkey, auction);
+    assert_eq!(instruction.accounts[6].pubkey, starter);
+    assert_eq!(log[1]["event"], "fill_auction");
+    assert_eq!(log[1]["loan_id"], 1);
+    // 1,050,000 of target buys 0.011666667 SOL at $90, worth 1,166,666 at $100
+    assert_eq!(log[1]["seized"], 11_666_667);
+    assert_eq!(log[1]["repay"], 1_050_001);
+    assert_eq!(log[1]["expected_profit"], 116_665);
+}
+
+#[test]
+fn skips_fills_below_min_profit() {
+    let mut setup = setup(100_000_000, NOW, LiquidationMode::DutchAuction);
+    let loan = insert_loan(&mut setup.chain, setup.market, 1, true, NOW + LoanTerm::OneMonth.duration());
+    // A minute into the auction, the discount is only 0.33%
+    insert_auction(&mut setup.chain, setup.market, loan, 60);
+
+    let (acted, sent, log) = run(setup, 100_000, false);
+
+    assert_eq!(acted, 0);
+    assert!(sent.is_empty());
+    assert_eq!(log[1]["event"], "skip");
+    assert_eq!(log[1]["reason"], "below minimum profit");
+}
@@ -0,0 +1,523 @@
+//! Interest and collateral math shared by the radar_lend program, its keeper and clients.
+//!
+//! Amounts are integers: USDC in micro-USDC (6 decimals), SOL in lamports, prices in
+//! micro-USDC per SOL, and rates and ratios in basis points. Intermediate products are
+//! computed in u128; overflow and division by zero are reported rather than wrapped.
+//! Functions whose result can reasonably be rounded either way take a [`Rounding`]; the
+//! others document the direction they round in.
+#![no_std]
+
+/// Lamports in one SOL.
+pub const LAMPORTS_PER_SOL: u64 = 1_000_000_000;
+
+/// Seconds in a (365-day) year, the period APYs are quoted over.
+pub const SECONDS_IN_YEAR: u64 = 31_536_000;
+
+/// Basis points in one.
+pub const BPS: u64 = 10_000;
+
+/// Fixed-point scale used for compounding growth factors and cumulative indexes.
+pub const WAD: u128 = 1_000_000_000_000_000_000;
+
+/// Direction to round a division in.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Rounding {
+    /// Towards zero.
+    Down,
+    /// Away from zero.
+    Up,
+}
+
+/// Errors returned by the math functions.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum MathError {
+    /// An intermediate value or the result does not fit its type.
+    Overflow,
+    /// A divisor (price, LTV or period) is zero.
+    DivisionByZero,
+}
+
+pub type Result<T> = core::result::Result<T, MathError>;
+
+/// Computes `a * b / denominator`, rounded as requested.
+pub fn mul_div(a: u128, b: u128, denominator: u128, rounding: Rounding) -> Result<u128> {
+    if denominator == 0 {
+        return Err(MathError::DivisionByZero);
+    }
+    let product = a.checked_mul(b).ok_or(MathError::Overflow)?;
+    Ok(match rounding {
+        Rounding::Down => product / denominator,
+        Rounding::Up => product.div_ceil(denominator),
+    })
+}
+
+/// Narrows a u128 to a u64.
+fn to_u64(value: u128) -> Result<u64> {
+    u64::try_from(value).map_err(|_| MathError::Overflow)
+}
+
+/// Collateral in lamports required to borrow `principal` at `ltv_bps` when SOL is worth `sol_price`:
+/// (principal * LAMPORTS_PER_SOL * 10_000) / (ltv * sol_price)
+pub fn required_collateral(principal: u64, ltv_bps: u16, sol_price: u64, rounding: Rounding) -> Result<u64> {
+    let required = mul_div(
+        principal as u128 * LAMPORTS_PER_SOL as u128,
+        BPS as u128,
+        ltv_bps as u128 * sol_price as u128,
+        rounding,
+    )?;
+    to_u64(required)
+}
+
+/// Largest principal that `collateral` lamports can secure at `ltv_bps` when SOL is worth
+/// `sol_price`: the largest principal whose `required_collateral`, rounded as given, is at most
+/// `collateral`. Saturates at `u64::MAX`; returns 0 when the LTV or price is zero.
+pub fn max_principal(collateral: u64, ltv_bps: u16, sol_price: u64, rounding: Rounding) -> u64 {
+    let scale = LAMPORTS_PER_SOL as u128 * BPS as u128;
+    let weight = ltv_bps as u128 * sol_price as u128;
+    if weight == 0 {
+        return 0;
+    }
+
+    let max = match rounding {
+        // floor(principal * scale / weight) <= collateral  <=>  principal * scale < (collateral + 1) * weight
+        Rounding::Down => (collateral as u128 + 1)
+            .checked_mul(weight)
+            .map(|bound| (bound - 1) / scale),
+        // ceil(principal * scale / weight) <= collateral  <=>  principal * scale <= collateral * weight
+        Rounding::Up => (collateral as u128).checked_mul(weight).map(|bound| bound / scale),
+    };
+    // The bound only overflows when the answer is far above u64::MAX
+    max.map_or(u64::MAX, |max| u64::try_from(max).unwrap_or(u64::MAX))
+}
+
+/// Simple interest on `principal` at `apy_bps` over `duration` seconds:
+/// principal * (apy / 10_000) * (duration / SECONDS_IN_YEAR)
+pub fn simple_interest(principal: u64, apy_bps: u16, duration: u64, rounding: Rounding) -> Result<u64> {
+    let interest = mul_div(
+        principal as u128 * apy_bps as u128,
+        duration as u128,
+        BPS as u128 * SECONDS_IN_YEAR as u128,
+        rounding,
+    )?;
+    to_u64(interest)
+}
+
+/// Interest on `principal` at `apy_bps` over `duration` seconds, compounded every `period`
+/// seconds. A final partial period accrues simple interest. Each step of the calculation is
+/// rounded in the requested direction, so the result bounds the exact value from that side.
+pub fn compound_interest(
+    principal: u64,
+    apy_bps: u16,
+    duration: u64,
+    period: u64,
+    rounding: Rounding,
+) -> Result<u64> {
+    if period == 0 {
+        return Err(MathError::DivisionByZero);
+    }
+
+    // Growth factor over `seconds`, in WAD: 1 + apy * seconds / (10_000 * SECONDS_IN_YEAR)
+    let growth = |seconds: u64| -> Result<u128> {
+        let rate = mul_div(
+            WAD * apy_bps as u128,
+            seconds as u128,
+            BPS as u128 * SECONDS_IN_YEAR as u128,
+            rounding,
+        )?;
+        WAD.checked_add(rate).ok_or(MathError::Overflow)
+    };
+
+    let compounded = wad_pow(growth(period)?, duration / period, rounding)?;
+    let total_growth = mul_div(compounded, growth(duration % period)?, WAD, rounding)?;
+    let total = mul_div(principal as u128, total_growth, WAD, rounding)?;
+    to_u64(total - principal as u128)
+}
+
+/// Raises a WAD fixed-point `base` to `exp` by repeated squaring.
+fn wad_pow(mut base: u128, mut exp: u64, rounding: Rounding) -> Result<u128> {
+    let mut result = WAD;
+    while exp > 0 {
+        if exp & 1 == 1 {
+            result = mul_div(result, base, WAD, rounding)?;
+        }
+        exp >>= 1;
+        if exp > 0 {
+            base = mul_div(base, base, WAD, rounding)?;
+        }
+    }
+    Ok(result)
+}
+
+/// Utilization of a pool in basis points: borrowed / (available + borrowed), rounded down.
+/// Zero when nothing is borrowed.
+pub fn utilization_bps(borrowed: u64, available: u64) -> u16 {
+    if borrowed == 0 {
+        return 0;
+    }
+    let total = borrowed as u128 + available as u128;
+    (borrowed as u128 * BPS as u128 / total) as u16
+}
+
+/// Borrow rate in basis points on a kinked curve over `utilization_bps`: the rate rises from
+/// `base_bps` by `slope1_bps` as utilization reaches `kink_bps`, then by a further `slope2_bps`
+/// as utilization reaches 100%. Rounded down; utilization above 100% is treated as 100%.
+pub fn kinked_rate_bps(
+    utilization_bps: u16,
+    base_bps: u16,
+    slope1_bps: u16,
+    kink_bps: u16,
+    slope2_bps: u16,
+) -> Result<u32> {
+    if kink_bps == 0 {
+        return Err(MathError::DivisionByZero);
+    }
+    let utilization = (utilization_bps as u64).min(BPS);
+    let kink = kink_bps as u64;
+    let rate = if utilization <= kink {
+        base_bps as u64 + slope1_bps as u64 * utilization / kink
+    } else {
+        // Above the kink, utilization is below 100% only if the kink is too
+        base_bps as u64 + slope1_bps as u64 + slope2_bps as u64 * (utilization - kink) / (BPS - kink)
+    };
+    Ok(rate as u32)
+}
+
+/// Grows a WAD-scaled cumulative `index` by simple interest at `rate_bps` over `duration`
+/// seconds: index * (1 + (rate / 10_000) * (duration / SECONDS_IN_YEAR)). Growing an index in
+/// steps compounds across them.
+pub fn grow_index(index: u128, rate_bps: u32, duration: u64, rounding: Rounding) -> Result<u128> {
+    let growth = mul_div(
+        index,
+        rate_bps as u128 * duration as u128,
+        BPS as u128 * SECONDS_IN_YEAR as u128,
+        rounding,
+    )?;
+    index.checked_add(growth).ok_or(MathError::Overflow)
+}
+
+/// Scales `amount` by the growth of a cumulative index from `from_index` to `to_index`:
+/// amount * to_index / from_index
+pub fn scale_by_index(amount: u64, from_index: u128, to_index: u128, rounding: Rounding) -> Result<u64> {
+    to_u64(mul_div(amount as u128, to_index, from_index, rounding)?)
+}
+
+/// Value in micro-USDC of `collateral` lamports when SOL is worth `sol_price`:
+/// collateral * sol_price / LAMPORTS_PER_SOL
+pub fn collateral_value(collateral: u64, sol_price: u64, rounding: Rounding) -> Result<u64> {
+    let value = mul_div(collateral as u128, sol_price as u128, LAMPORTS_PER_SOL as u128, rounding)?;
+    to_u64(value)
+}
+
+/// Lamports worth `value` micro-USDC plus a bonus of `bonus_bps` when SOL is worth `sol_price`:
+/// (value * (10_000 + bonus) * LAMPORTS_PER_SOL) / (10_000 * sol_price)
+pub fn collateral_for_value(value: u64, bonus_bps: u16, sol_price: u64, rounding: Rounding) -> Result<u64> {
+    let lamports = mul_div(
+        value as u128 * (BPS as u128 + bonus_bps as u128),
+        LAMPORTS_PER_SOL as u128,
+        BPS as u128 * sol_price as u128,
+        rounding,
+    )?;
+    to_u64(lamports)
+}
+
+/// Whether `debt` exceeds `liquidation_threshold_bps` of the value of `collateral` lamports when
+/// SOL is worth `sol_price`. Exact: no rounding is involved.
+pub fn is_liquidatable(debt: u64, collateral: u64, sol_price: u64, liquidation_threshold_bps: u16) -> Result<bool> {
+    // debt / (collateral * sol_price / LAMPORTS_PER_SOL) > liquidation_threshold / 10_000
+    let weighted_value = (collateral as u128)
+        .checked_mul(sol_price as u128)
+        .and_then(|val| val.checked_mul(liquidation_threshold_bps as u128))
+        .ok_or(MathError::Overflow)?;
+    let debt_value = debt as u128 * LAMPORTS_PER_SOL as u128 * BPS as u128;
+    Ok(debt_value > weighted_value)
+}
+
+/// Health factor in basis points: the value of `collateral` at `liquidation_threshold_bps`
+/// over `debt`. Rounded down, so a loan is liquidatable exactly when its health factor is
+/// below 10_000. Saturates at `u64::MAX`, which is also returned when there is no debt.
+pub fn health_factor_bps(
+    debt: u64,
+    collateral: u64,
+    sol_price: u64,
+    liquidation_threshold_bps: u16,
+) -> Result<u64> {
+    if debt == 0 {
+        return Ok(u64::MAX);
+    }
+    // (collateral * sol_price * liquidation_threshold) / (debt * LAMPORTS_PER_SOL)
+    let weighted_value = (collateral as u128)
+        .checked_mul(sol_price as u128)
+        .and_then(|val| val.checked_mul(liquidation_threshold_bps as u128))
+        .ok_or(MathError::Overflow)?;
+    let health = weighted_value / (debt as u128 * LAMPORTS_PER_SOL as u128);
+    Ok(u64::try_from(health).unwrap_or(u64::MAX))
+}
+
+/// SOL price, in micro-USDC, below which a loan of `debt` against `collateral` lamports can be
+/// liquidated. Rounded up, so a loan is liquidatable exactly when the price is below it.
+/// Saturates at `u64::MAX`, which is also returned when there is no collateral or threshold.
+pub fn liquidation_price(debt: u64, collateral: u64, liquidation_threshold_bps: u16) -> u64 {
+    // (debt * LAMPORTS_PER_SOL * 10_000) / (collateral * liquidation_threshold)
+    let weight = collateral as u128 * liquidation_threshold_bps as u128;
+    if weight == 0 {
+        return u64::MAX;
+    }
+    let debt_value = debt as u128 * LAMPORTS_PER_SOL as u128 * BPS as u128;
+    u64::try_from(debt_value.div_ceil(weight)).unwrap_or(u64::MAX)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SOL_PRICE: u64 = 100_000_000; // $100
+
+    #[test]
+    fn mul_div_rounds_as_requested() {
+        assert_eq!(mul_div(10, 10, 3, Rounding::Down), Ok(33));
+        assert_eq!(mul_div(10, 10, 3, Rounding::Up), Ok(34));
+        assert_eq!(mul_div(10, 9, 3, Rounding::Up), Ok(30));
+        assert_eq!(mul_div(0, 10, 3, Rounding::Up), Ok(0));
+    }
+
+    #[test]
+    fn mul_div_reports_errors() {
+        assert_eq!(mul_div(1, 1, 0, Rounding::Down), Err(MathError::DivisionByZero));
+        assert_eq!(mul_div(u128::MAX, 2, 1, Rounding::Down), Err(MathError::Overflow));
+    }
+
+    #[test]
+    fn required_collateral_at_tier_ltvs() {
+        // 1 USDC at 33% LTV with SOL at $100
+        assert_eq!(required_collateral(1_000_000, 3_300, SOL_PRICE, Rounding::Down), Ok(30_303_030));
+        assert_eq!(required_collateral(1_000_000, 3_300, SOL_PRICE, Rounding::Up), Ok(30_303_031));
+        // 1,000 USDC at 50% LTV is 20 SOL
+        assert_eq!(
+            required_collateral(1_000_000_000, 5_000, SOL_PRICE, Rounding::Down),
+            Ok(20 * LAMPORTS_PER_SOL)
+        );
+        assert_eq!(
+            required_collateral(1_000_000_000, 5_000, SOL_PRICE, Rounding::Up),
+            Ok(20 * LAMPORTS_PER_SOL)
+        );
+        assert_eq!(required_collateral(0, 5_000, SOL_PRICE, Rounding::Up), Ok(0));
+    }
+
+    #[test]
+    fn required_collateral_reports_errors() {
+        assert_eq!(required_collateral(1, 0, SOL_PRICE, Rounding::Down), Err(MathError::DivisionByZero));
+        assert_eq!(required_collateral(1, 5_000, 0, Rounding::Down), Err(MathError::DivisionByZero));
+        assert_eq!(required_collateral(u64::MAX, 1, 1, Rounding::Down), Err(MathError::Overflow));
+    }
+
+    #[test]
+    fn max_principal_inverts_required_collateral() {
+        assert_eq!(max_principal(30_303_030, 3_300, SOL_PRICE, Rounding::Down), 1_000_000);
+        assert_eq!(max_principal(30_303_059, 3_300, SOL_PRICE, Rounding::Down), 1_000_000);
+        assert_eq!(max_principal(30_303_060, 3_300, SOL_PRICE, Rounding::Down), 1_000_001);
+        assert_eq!(max_principal(30_303_030, 3_300, SOL_PRICE, Rounding::Up), 999_999);
+        assert_eq!(max_principal(30_303_031, 3_300, SOL_PRICE, Rounding::Up), 1_000_000);
+        assert_eq!(max_principal(20 * LAMPORTS_PER_SOL, 5_000, SOL_PRICE, Rounding::Up), 1_000_000_000);
+    }
+
+    #[test]
+    fn max_principal_edge_cases() {
+        assert_eq!(max_principal(LAMPORTS_PER_SOL, 0, SOL_PRICE, Rounding::Down), 0);
+        assert_eq!(max_principal(LAMPORTS_PER_SOL, 5_000, 0, Rounding::Down), 0);
+        assert_eq!(max_principal(0, 5_000, SOL_PRICE, Rounding::Up), 0);
+        assert_eq!(max_principal(u64::MAX, u16::MAX, u64::MAX, Rounding::Down), u64::MAX);
+    }
+
+    #[test]
+    fn simple_interest_over_a_year() {
+        // 8% on 1,000 USDC for a year
+        assert_eq!(simple_interest(1_000_000_000, 800, SECONDS_IN_YEAR, Rounding::Down), Ok(80_000_000));
+        assert_eq!(simple_interest(1_000_000_000, 800, SECONDS_IN_YEAR, Rounding::Up), Ok(80_000_000));
+        // Half a year
+        assert_eq!(simple_interest(1_000_000_000, 800, SECONDS_IN_YEAR / 2, Rounding::Down), Ok(40_000_000));
+    }
+
+    #[test]
+    fn simple_interest_rounding() {
+        // 1 USDC at 5% for one second is 0.0016 micro-USDC
+        assert_eq!(simple_interest(1_000_000, 500, 1, Rounding::Down), Ok(0));
+        assert_eq!(simple_interest(1_000_000, 500, 1, Rounding::Up), Ok(1));
+        assert_eq!(simple_interest(1_000_000, 0, SECONDS_IN_YEAR, Rounding::Up), Ok(0));
+        assert_eq!(simple_interest(1_000_000, 500, 0, Rounding::Up), Ok(0));
+    }
+
+    #[test]
+    fn simple_interest_reports_overflow() {
+        assert_eq!(
+            simple_interest(u64::MAX, u16::MAX, u64::MAX, Rounding::Down),
+            Err(MathError::Overflow)
+        );
+        // The product fits but the interest does not fit a u64
+        assert_eq!(
+            simple_interest(u64::MAX, 10_000, 2 * SECONDS_IN_YEAR, Rounding::Down),
+            Err(MathError::Overflow)
+        );
+    }
+
+    #[test]
+    fn compound_interest_over_a_year() {
+        // Compounded once a year, compounding is simple interest
+        assert_eq!(
+            compound_interest(1_000_000_000, 800, SECONDS_IN_YEAR, SECONDS_IN_YEAR, Rounding::Down),
+            Ok(80_000_000)
+        );
+        // Compounded twice a year: 1.04^2 - 1 = 8.16%
+        assert_eq!(
+            compound_interest(1_000_000_000, 800, SECONDS_IN_YEAR, SECONDS_IN_YEAR / 2, Rounding::Down),
+            Ok(81_600_000)
+        );
+        // Two years compounded yearly: 1.08^2 - 1 = 16.64%
+        assert_eq!(
+            compound_interest(1_000_000_000, 800, 2 * SECONDS_IN_YEAR, SECONDS_IN_YEAR, Rounding::Down),
+            Ok(166_400_000)
+        );
+        // A year and a half compounded yearly: 1.08 * 1.04 - 1 = 12.32%
+        assert_eq!(
+            compound_interest(1_000_000_000, 800, 3 * SECONDS_IN_YEAR / 2, SECONDS_IN_YEAR, Rounding::Down),
+            Ok(123_200_000)
+        );
+    }
+
+    #[test]
+    fn compound_interest_compounded_daily() {
+        // (1 + 0.08 / 365)^365 - 1 = 8.3277572...%
+        let down = compound_interest(1_000_000_000, 800, SECONDS_IN_YEAR, 86_400, Rounding::Down).unwrap();
+        let up = compound_interest(1_000_000_000, 800, SECONDS_IN_YEAR, 86_400, Rounding::Up).unwrap();
+        assert_eq!(down, 83_277_571);
+        assert_eq!(up, 83_277_572);
+    }
+
+    #[test]
+    fn compound_interest_edge_cases() {
+        assert_eq!(compound_interest(1_000_000, 800, 0, 86_400, Rounding::Up), Ok(0));
+        assert_eq!(compound_interest(1_000_000, 0, SECONDS_IN_YEAR, 86_400, Rounding::Up), Ok(0));
+        assert_eq!(compound_interest(0, 800, SECONDS_IN_YEAR, 86_400, Rounding::Up), Ok(0));
+        assert_eq!(
+            compound_interest(1_000_000, 800, SECONDS_IN_YEAR, 0, Rounding::Down),
+            Err(MathError::DivisionByZero)
+        );
+        // 100% compounded every second for a century does not fit
+        assert_eq!(
+            compound_interest(1_000_000, 10_000, 100 * SECONDS_IN_YEAR, 1, Rounding::Down),
+            Err(MathError::Overflow)
+        );
+    }
+
+    #[test]
+    fn utilization_of_a_pool() {
+        assert_eq!(utilization_bps(0, 0), 0);
+        assert_eq!(utilization_bps(0, 1_000_000), 0);
+        assert_eq!(utilization_bps(800_000, 200_000), 8_000);
+        assert_eq!(utilization_bps(1_000_000, 0), 10_000);
+        // 1 of 3 is 33.33%
+        assert_eq!(utilization_bps(1, 2), 3_333);
+        assert_eq!(utilization_bps(u64::MAX, u64::MAX), 5_000);
+    }
+
+    #[test]
+    fn kinked_rate_along_the_curve() {
+        // 2% base, +4% up to an 80% kink, then +75% up to 100% utilization
+        let rate = |utilization| kinked_rate_bps(utilization, 200, 400, 8_000, 7_500);
+        assert_eq!(rate(0), Ok(200));
+        assert_eq!(rate(4_000), Ok(400));
+        assert_eq!(rate(8_000), Ok(600));
+        assert_eq!(rate(9_000), Ok(4_350));
+        assert_eq!(rate(10_000), Ok(8_100));
+        assert_eq!(rate(u16::MAX), Ok(8_100));
+    }
+
+    #[test]
+    fn kinked_rate_edge_cases() {
+        // A kink at 100% leaves the second slope unused
+        assert_eq!(kinked_rate_bps(10_000, 0, 400, 10_000, 7_500), Ok(400));
+        assert_eq!(
+            kinked_rate_bps(10_000, u16::MAX, u16::MAX, 1, u16::MAX),
+            Ok(3 * u16::MAX as u32)
+        );
+        assert_eq!(kinked_rate_bps(5_000, 0, 400, 0, 7_500), Err(MathError::DivisionByZero));
+    }
+
+    #[test]
+    fn grow_index_over_a_year() {
+        assert_eq!(grow_index(WAD, 800, SECONDS_IN_YEAR, Rounding::Down), Ok(WAD + WAD * 8 / 100));
+        // Two half-year steps compound: 1.04^2 = 1.0816
+        let half = grow_index(WAD, 800, SECONDS_IN_YEAR / 2, Rounding::Down).unwrap();
+        assert_eq!(
+            grow_index(half, 800, SECONDS_IN_YEAR / 2, Rounding::Down),
+            Ok(WAD + WAD * 816 / 10_000)
+        );
+        assert_eq!(grow_index(WAD, 800, 0, Rounding::Up), Ok(WAD));
+        assert_eq!(grow_index(WAD, 0, SECONDS_IN_YEAR, Rounding::Up), Ok(WAD));
+        assert_eq!(grow_index(u128::MAX, 800, 1, Rounding::Down), Err(MathError::Overflow));
+    }
+
+    #[test]
+    fn scale_by_index_rounding() {
+        let grown = WAD + WAD * 8 / 100;
+        assert_eq!(scale_by_index(1_000_000, WAD, grown, Rounding::Down), Ok(1_080_000));
+        assert_eq!(scale_by_index(1_000_000, WAD, WAD, Rounding::Down), Ok(1_000_000));
+        // 1 micro-USDC grown by 8% is 1.08
+        assert_eq!(scale_by_index(1, WAD, grown, Rounding::Down), Ok(1));
+        assert_eq!(scale_by_index(1, WAD, grown, Rounding::Up), Ok(2));
+        assert_eq!(scale_by_index(1, 0, WAD, Rounding::Down), Err(MathError::DivisionByZero));
+        assert_eq!(scale_by_index(u64::MAX, WAD, 2 * WAD, Rounding::Down), Err(MathError::Overflow));
+    }
+
+    #[test]
+    fn collateral_value_rounding() {
+        assert_eq!(collateral_value(LAMPORTS_PER_SOL, SOL_PRICE, Rounding::Down), Ok(SOL_PRICE));
+        // 1 lamport at $100 is 0.1 micro-USDC
+        assert_eq!(collateral_value(1, SOL_PRICE, Rounding::Down), Ok(0));
+        assert_eq!(collateral_value(1, SOL_PRICE, Rounding::Up), Ok(1));
+        assert_eq!(collateral_value(u64::MAX, u64::MAX, Rounding::Down), Err(MathError::Overflow));
+    }
+
+    #[test]
+    fn collateral_for_value_with_bonus() {
+        // $500 plus a 5% bonus at $65
+        assert_eq!(collateral_for_value(500_000, 500, 65_000_000, Rounding::Down), Ok(8_076_923));
+        assert_eq!(collateral_for_value(500_000, 500, 65_000_000, Rounding::Up), Ok(8_076_924));
+        assert_eq!(collateral_for_value(100_000_000, 0, SOL_PRICE, Rounding::Up), Ok(LAMPORTS_PER_SOL));
+        assert_eq!(collateral_for_value(1, 500, 0, Rounding::Up), Err(MathError::DivisionByZero));
+        assert_eq!(collateral_for_value(u64::MAX, 500, 1, Rounding::Up), Err(MathError::Overflow));
+    }
+
+    #[test]
+    fn health_of_a_loan() {
+        // 1 USDC against 3.3 SOL at $100 with a 53% liquidation threshold
+        assert_eq!(health_factor_bps(1_000_000, 3_300_000_000, SOL_PRICE, 5_300), Ok(1_749_000));
+        assert_eq!(liquidation_price(1_000_000, 3_300_000_000, 5_300), 571_756);
+        assert_eq!(is_liquidatable(1_000_000, 3_300_000_000, SOL_PRICE, 5_300), Ok(false));
+        assert_eq!(is_liquidatable(1_000_000, 3_300_000_000, 571_756, 5_300), Ok(false));
+        assert_eq!(is_liquidatable(1_000_000, 3_300_000_000, 571_755, 5_300), Ok(true));
+    }
+
+    #[test]
+    fn health_at_the_threshold() {
+        // 70 USDC against 1 SOL at $100 with a 70% threshold is exactly at the threshold
+        assert_eq!(health_factor_bps(70_000_000, LAMPORTS_PER_SOL, SOL_PRICE, 7_000), Ok(10_000));
+        assert_eq!(is_liquidatable(70_000_000, LAMPORTS_PER_SOL, SOL_PRICE, 7_000), Ok(false));
+        assert_eq!(liquidation_price(70_000_000, LAMPORTS_PER_SOL, 7_000), SOL_PRICE);
+        assert_eq!(health_factor_bps(70_000_001, LAMPORTS_PER_SOL, SOL_PRICE, 7_000), Ok(9_999));
+        assert_eq!(is_liquidatable(70_000_001, LAMPORTS_PER_SOL, SOL_PRICE, 7_000), Ok(true));
+    }
+
+    #[test]
+    fn health_edge_cases() {
+        assert_eq!(health_factor_bps(0, LAMPORTS_PER_SOL, SOL_PRICE, 7_000), Ok(u64::MAX));
+        assert_eq!(health_factor_bps(1, u64::MAX, 1_000_000_000, 7_000), Ok(u64::MAX));
+        assert_eq!(health_factor_bps(1, u64::MAX, u64::MAX, 7_000), Err(MathError::Overflow));
+        assert_eq!(is_liquidatable(1, u64::MAX, u64::MAX, 7_000), Err(MathError::Overflow));
+        assert_eq!(is_liquidatable(0, 0, SOL_PRICE, 7_000), Ok(false));
+        assert_eq!(is_liquidatable(1, 0, SOL_PRICE, 7_000), Ok(true));
+        assert_eq!(liquidation_price(0, LAMPORTS_PER_SOL, 7_000), 0);
+        assert_eq!(liquidation_price(1, 0, 7_000), u64::MAX);
+        assert_eq!(liquidation_price(1, LAMPORTS_PER_SOL, 0), u64::MAX);
+        assert_eq!(liquidation_price(u64::MAX, 1, 1), u64::MAX);
+    }
+}
@@ -0,0 +1,209 @@
+use proptest::prelude::*;
+use radar_math::*;
+
+/// Prices between $1 and $1,000,000, in micro-USDC.
+fn price() -> impl Strategy<Value = u64> {
+    1_000_000u64..1_000_000_000_000
+}
+
+/// Amounts up to a billion USDC, or a million SOL for collateral.
+fn amount() -> impl Strategy<Value = u64> {
+    0u64..1_000_000_000_000_000
+}
+
+/// LTVs between 10% and 100%.
+fn ltv() -> impl Strategy<Value = u16> {
+    1_000u16..=10_000
+}
+
+/// Non-zero ratios up to 100%.
+fn bps() -> impl Strategy<Value = u16> {
+    1u16..=10_000
+}
+
+fn rounding() -> impl Strategy<Value = Rounding> {
+    prop_oneof![Just(Rounding::Down), Just(Rounding::Up)]
+}
+
+proptest! {
+    #[test]
+    fn mul_div_up_is_down_or_one_more(a in any::<u64>(), b in any::<u64>(), d in 1u128..u128::MAX) {
+        let down = mul_div(a as u128, b as u128, d, Rounding::Down).unwrap();
+        let up = mul_div(a as u128, b as u128, d, Rounding::Up).unwrap();
+        prop_assert!(up == down || up == down + 1);
+        prop_assert_eq!(up == down, (a as u128 * b as u128).is_multiple_of(d));
+    }
+
+    #[test]
+    fn required_collateral_rounds_by_at_most_one(principal in amount(), ltv in ltv(), price in price()) {
+        let down = required_collateral(principal, ltv, price, Rounding::Down).unwrap();
+        let up = required_collateral(principal, ltv, price, Rounding::Up).unwrap();
+        prop_assert!(up == down || up == down + 1);
+    }
+
+    #[test]
+    fn required_collateral_is_monotonic(
+        principal in amount(),
+        extra in 0u64..1_000_000,
+        ltv in ltv(),
+        price in price(),
+        rounding in rounding(),
+    ) {
+        let less = required_collateral(principal, ltv, price, rounding).unwrap();
+        let more = required_collateral(principal + extra, ltv, price, rounding).unwrap();
+        prop_assert!(more >= less);
+    }
+
+    #[test]
+    fn max_principal_is_the_largest_allowed(
+        collateral in amount(),
+        ltv in ltv(),
+        price in price(),
+        rounding in rounding(),
+    ) {
+        let max = max_principal(collateral, ltv, price, rounding);
+        prop_assert!(required_collateral(max, ltv, price, rounding).unwrap() <= collateral);
+        if let Ok(required) = required_collateral(max + 1, ltv, price, rounding) {
+            prop_assert!(required > collateral);
+        }
+    }
+
+    #[test]
+    fn required_collateral_covers_the_principal(principal in amount(), ltv in ltv(), price in price()) {
+        // At the LTV, collateral rounded up is worth at least the principal and rounded down at most
+        let target = principal as u128 * LAMPORTS_PER_SOL as u128 * BPS as u128;
+        let up = required_collateral(principal, ltv, price, Rounding::Up).unwrap();
+        prop_assert!(up as u128 * ltv as u128 * price as u128 >= target);
+        let down = required_collateral(principal, ltv, price, Rounding::Down).unwrap();
+        prop_assert!(down as u128 * ltv as u128 * price as u128 <= target);
+    }
+
+    #[test]
+    fn simple_interest_is_additive(
+        principal in amount(),
+        apy in 0u16..=10_000,
+        first in 0u64..10 * SECONDS_IN_YEAR,
+        second in 0u64..10 * SECONDS_IN_YEAR,
+    ) {
+        let split = simple_interest(principal, apy, first, Rounding::Down).unwrap()
+            + simple_interest(principal, apy, second, Rounding::Down).unwrap();
+        let whole = simple_interest(principal, apy, first + second, Rounding::Down).unwrap();
+        // Accruing in two steps loses at most the rounding of one step
+        prop_assert!(split <= whole && whole <= split + 1);
+    }
+
+    #[test]
+    fn simple_interest_rounds_by_at_most_one(
+        principal in amount(),
+        apy in 0u16..=10_000,
+        duration in 0u64..10 * SECONDS_IN_YEAR,
+    ) {
+        let down = simple_interest(principal, apy, duration, Rounding::Down).unwrap();
+        let up = simple_interest(principal, apy, duration, Rounding::Up).unwrap();
+        prop_assert!(up == down || up == down + 1);
+    }
+
+    #[test]
+    fn compound_interest_bounds_simple_interest(
+        principal in 0u64..1_000_000_000_000,
+        apy in 0u16..=10_000,
+        duration in 0u64..2 * SECONDS_IN_YEAR,
+        period in 3_600u64..SECONDS_IN_YEAR,
+    ) {
+        let simple = simple_interest(principal, apy, duration, Rounding::Down).unwrap();
+        let down = compound_interest(principal, apy, duration, period, Rounding::Down).unwrap();
+        let up = compound_interest(principal, apy, duration, period, Rounding::Up).unwrap();
+        prop_assert!(down <= up);
+        // Compounding never earns less than simple interest, beyond rounding
+        prop_assert!(up >= simple);
+    }
+
+    #[test]
+    fn compound_interest_over_one_period_is_simple(
+        principal in 0u64..1_000_000_000_000,
+        apy in 0u16..=10_000,
+        duration in 0u64..SECONDS_IN_YEAR,
+    ) {
+        let simple = simple_interest(principal, apy, duration, Rounding::Down).unwrap();
+        let compound = compound_interest(principal, apy, duration, SECONDS_IN_YEAR, Rounding::Down).unwrap();
+        prop_assert!(compound <= simple && simple <= compound + 1);
+    }
+
+    #[test]
+    fn kinked_rate_rises_with_utilization(
+        utilization in 0u16..10_000,
+        base in any::<u16>(),
+        slope1 in any::<u16>(),
+        kink in bps(),
+        slope2 in any::<u16>(),
+    ) {
+        let rate = kinked_rate_bps(utilization, base, slope1, kink, slope2).unwrap();
+        let higher = kinked_rate_bps(utilization + 1, base, slope1, kink, slope2).unwrap();
+        prop_assert!(higher >= rate);
+        prop_assert!(rate >= base as u32);
+        prop_assert!(rate <= base as u32 + slope1 as u32 + slope2 as u32);
+    }
+
+    #[test]
+    fn grow_index_bounds_the_scaled_amount(
+        amount in amount(),
+        rate in 0u32..100_000,
+        duration in 0u64..10 * SECONDS_IN_YEAR,
+    ) {
+        // Scaling by the grown index earns simple interest at the rate, within rounding
+        let index = grow_index(WAD, rate, duration, Rounding::Down).unwrap();
+        let scaled = scale_by_index(amount, WAD, index, Rounding::Down).unwrap();
+        let interest = mul_div(
+            amount as u128 * rate as u128,
+            duration as u128,
+            BPS as u128 * SECONDS_IN_YEAR as u128,
+            Rounding::Down,
+        )
+        .unwrap();
+        prop_assert!(scaled as u128 <= amount as u128 + interest);
+        prop_assert!(scaled as u128 + 1 >= amount as u128 + interest);
+    }
+
+    #[test]
+    fn health_factor_agrees_with_is_liquidatable(
+        debt in amount(),
+        collateral in amount(),
+        price in price(),
+        threshold in bps(),
+    ) {
+        let health = health_factor_bps(debt, collateral, price, threshold).unwrap();
+        let liquidatable = is_liquidatable(debt, collateral, price, threshold).unwrap();
+        prop_assert_eq!(health < BPS, liquidatable);
+    }
+
+    #[test]
+    fn liquidation_price_agrees_with_is_liquidatable(
+        debt in amount(),
+        collateral in 1u64..1_000_000_000_000_000,
+        threshold in bps(),
+        price in price(),
+    ) {
+        let liquidation_price = liquidation_price(debt, collateral, threshold);
+        let liquidatable = is_liquidatable(debt, collateral, price, threshold).unwrap();
+        prop_assert_eq!(price < liquidation_price, liquidatable);
+        if liquidation_price > 0 && liquidation_price < u64::MAX {
+            prop_assert!(is_liquidatable(debt, collateral, liquidation_price - 1, threshold).unwrap());
+            prop_assert!(!is_liquidatable(debt, collateral, liquidation_price, threshold).unwrap());
+        }
+    }
+
+    #[test]
+    fn collateral_for_value_is_worth_the_value(
+        value in amount(),
+        bonus in 0u16..=2_000,
+        price in price(),
+    ) {
+        let lamports = collateral_for_value(value, bonus, price, Rounding::Up).unwrap();
+        let worth = collateral_value(lamports, price, Rounding::Up).unwrap() as u128;
+        prop_assert!(worth * BPS as u128 >= value as u128 * (BPS + bonus as u64) as u128);
+
+        let lamports = collateral_for_value(value, bonus, price, Rounding::Down).unwrap();
+        let worth = collateral_value(lamports, price, Rounding::Down).unwrap() as u128;
+        prop_assert!(worth * BPS as u128 <= value as u128 * (BPS + bonus as u64) as u128);
+    }
+}
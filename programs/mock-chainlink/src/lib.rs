@@ -0,0 +1,116 @@
+use anchor_lang::prelude::*;
+use anchor_lang::solana_program::program::set_return_data;
+
+declare_id!("37E3YQDdCkC5LDzvXP8xr8TdtdPRjZnmUvWHVybj3feS");
+
+/// A minimal stand-in for the Chainlink OCR2 store program, used on localnet.
+///
+/// It answers the same `query` instruction that `chainlink_solana` sends over CPI,
+/// so `radar_lend` can be pointed at this program and a mock feed account in tests.
+#[program]
+pub mod mock_chainlink {
+    use super::*;
+
+    /// Creates a mock feed with the given decimals and description.
+    pub fn create_feed(ctx: Context<CreateFeed>, decimals: u8, description: String) -> Result<()> {
+        let feed = &mut ctx.accounts.feed;
+        feed.authority = ctx.accounts.authority.key();
+        feed.decimals = decimals;
+        feed.description = description;
+        Ok(())
+    }
+
+    /// Publishes a new round to the feed.
+    pub fn set_round(ctx: Context<SetRound>, answer: i128, timestamp: u32) -> Result<()> {
+        let feed = &mut ctx.accounts.feed;
+        feed.round_id += 1;
+        feed.slot = Clock::get()?.slot;
+        feed.timestamp = timestamp;
+        feed.answer = answer;
+        Ok(())
+    }
+
+    /// Mirrors the store program's `query` instruction by writing the answer to return data.
+    pub fn query(ctx: Context<QueryFeed>, scope: Scope) -> Result<()> {
+        let feed = &ctx.accounts.feed;
+        let data = match scope {
+            Scope::Version => 2u8.try_to_vec()?,
+            Scope::Decimals => feed.decimals.try_to_vec()?,
+            Scope::Description => feed.description.try_to_vec()?,
+            Scope::RoundData { .. } | Scope::LatestRoundData => Round {
+                round_id: feed.round_id,
+                slot: feed.slot,
+                timestamp: feed.timestamp,
+                answer: feed.answer,
+            }
+            .try_to_vec()?,
+            Scope::Aggregator => feed.authority.try_to_vec()?,
+        };
+        set_return_data(&data);
+        Ok(())
+    }
+}
+
+#[derive(Accounts)]
+pub struct CreateFeed<'info> {
+    /// The account allowed to publish rounds.
+    #[account(mut)]
+    pub authority: Signer<'info>,
+
+    /// The feed account to be created.
+    #[account(init, payer = authority, space = 8 + Feed::INIT_SPACE)]
+    pub feed: Account<'info, Feed>,
+
+    /// System program.
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetRound<'info> {
+    /// The feed authority.
+    pub authority: Signer<'info>,
+
+    /// The feed being updated.
+    #[account(mut, has_one = authority)]
+    pub feed: Account<'info, Feed>,
+}
+
+#[derive(Accounts)]
+pub struct QueryFeed<'info> {
+    /// The feed being queried.
+    pub feed: Account<'info, Feed>,
+}
+
+/// Mock feed state.
+#[account]
+#[derive(InitSpace)]
+pub struct Feed {
+    pub authority: Pubkey,
+    pub decimals: u8,
+    #[max_len(32)]
+    pub description: String,
+    pub round_id: u32,
+    pub slot: u64,
+    pub timestamp: u32,
+    pub answer: i128,
+}
+
+/// Query scopes, in the same order as the store program so the borsh tags match.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub enum Scope {
+    Version,
+    Decimals,
+    Description,
+    RoundData { round_id: u32 },
+    LatestRoundData,
+    Aggregator,
+}
+
+/// Round data as returned by the store program.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
+pub struct Round {
+    pub round_id: u32,
+    pub slot: u64,
+    pub timestamp: u32,
+    pub answer: i128,
+}
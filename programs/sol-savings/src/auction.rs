@@ -0,0 +1,181 @@
+use anchor_lang::prelude::*;
+
+use radar_math::Rounding;
+
+use crate::{DataAccount, ErrorCode, Loan};
+
+/// Default discount on the oracle price when an auction starts, in bps.
+pub const DEFAULT_AUCTION_START_DISCOUNT_BPS: u16 = 0;
+
+/// Default discount an auction grows to, in bps.
+pub const DEFAULT_AUCTION_MAX_DISCOUNT_BPS: u16 = 2_000;
+
+/// Default time for an auction to reach its maximum discount, in seconds.
+pub const DEFAULT_AUCTION_DURATION: u32 = 3_600;
+
+/// Default penalty added to the debt an auction must raise, in bps of the debt.
+pub const DEFAULT_AUCTION_PENALTY_BPS: u16 = 500;
+
+/// How a market liquidates unhealthy loans.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum LiquidationMode {
+    /// Liquidators repay debt and receive collateral at a fixed bonus (`liquidate_loan`).
+    #[default]
+    FixedBonus,
+    /// Collateral is sold in a Dutch auction whose discount grows over time
+    /// (`start_auction` / `fill_auction`).
+    DutchAuction,
+}
+
+/// Dutch-auction parameters stored on the market.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Debug)]
+pub struct AuctionConfig {
+    pub start_discount_bps: u16, // Discount on the start price when the auction opens
+    pub max_discount_bps: u16,   // Discount reached once `duration` has elapsed
+    pub duration: u32,           // Seconds for the discount to grow from start to max
+    pub penalty_bps: u16,        // Penalty on the debt, raised in addition to the debt
+}
+
+impl Default for AuctionConfig {
+    fn default() -> Self {
+        Self {
+            start_discount_bps: DEFAULT_AUCTION_START_DISCOUNT_BPS,
+            max_discount_bps: DEFAULT_AUCTION_MAX_DISCOUNT_BPS,
+            duration: DEFAULT_AUCTION_DURATION,
+            penalty_bps: DEFAULT_AUCTION_PENALTY_BPS,
+        }
+    }
+}
+
+impl AuctionConfig {
+    /// Space required for the AuctionConfig:
+    /// - start_discount_bps: 2 bytes
+    /// - max_discount_bps: 2 bytes
+    /// - duration: 4 bytes
+    /// - penalty_bps: 2 bytes
+    pub const INIT_SPACE: usize = 2 + 2 + 4 + 2;
+
+    /// Ensures the discount curve is increasing, below 100% and takes a non-zero time.
+    pub fn validate(&self) -> Result<()> {
+        if self.start_discount_bps > self.max_discount_bps
+            || self.max_discount_bps >= 10_000
+            || self.duration == 0
+            || self.penalty_bps > 10_000
+        {
+            return Err(ErrorCode::InvalidAuctionConfig.into());
+        }
+        Ok(())
+    }
+}
+
+/// A Dutch auction of a loan's collateral, seeded by loan.
+///
+/// The debt and penalty are fixed when the auction starts. Bidders buy collateral at the
+/// start price less a discount that grows linearly over the auction. The auction settles
+/// once it has raised the debt plus penalty or sold all of the collateral.
+#[account]
+pub struct Auction {
+    pub market: Pubkey,            // Market PDA
+    pub loan_id: u64,              // Loan being liquidated
+    pub borrower: Pubkey,          // Borrower of the loan
+    pub starter: Pubkey,           // Account that started the auction and paid its rent
+    pub started_at: i64,           // Time the auction started
+    pub start_price: u64,          // Collateral price when the auction started, micro-USDC per SOL
+    pub config: AuctionConfig,     // Curve parameters at the start of the auction
+    pub debt: u64,                 // Principal plus interest owed when the auction started
+    pub penalty: u64,              // Penalty raised on top of the debt
+    pub collateral_remaining: u64, // Unsold collateral, in lamports
+    pub usdc_raised: u64,          // USDC paid by bidders so far
+    pub bump: u8,                  // Bump for PDA derivation
+}
+
+impl Auction {
+    /// Space required for the Auction:
+    /// - market: 32 bytes
+    /// - loan_id: 8 bytes
+    /// - borrower: 32 bytes
+    /// - starter: 32 bytes
+    /// - started_at: 8 bytes
+    /// - start_price: 8 bytes
+    /// - config: 10 bytes
+    /// - debt, penalty, collateral_remaining, usdc_raised: 8 bytes each
+    /// - bump: 1 byte
+    pub const INIT_SPACE: usize = 32 + 8 + 32 + 32 + 8 + 8 + AuctionConfig::INIT_SPACE + 8 * 4 + 1;
+
+    /// Opens the auction of `loan`'s collateral in `market`, starting at `start_price`, to raise
+    /// `debt` plus the market's auction penalty. A loan without collateral has nothing to sell.
+    pub fn open(
+        &mut self,
+        market: &Account<DataAccount>,
+        loan: &Loan,
+        starter: Pubkey,
+        start_price: u64,
+        debt: u64,
+        now: i64,
+    ) -> Result<()> {
+        if loan.collateral == 0 {
+            return Err(ErrorCode::NoCollateralToAuction.into());
+        }
+        let config = market.auction_config;
+        self.market = market.key();
+        self.loan_id = loan.id;
+        self.borrower = loan.borrower;
+        self.starter = starter;
+        self.started_at = now;
+        self.start_price = start_price;
+        self.config = config;
+        self.debt = debt;
+        self.penalty = ((debt as u128) * config.penalty_bps as u128 / 10_000) as u64;
+        self.collateral_remaining = loan.collateral;
+        self.usdc_raised = 0;
+        Ok(())
+    }
+
+    /// Restarts the discount curve from `start_price` at `now` with the market's current auction
+    /// parameters. The debt, penalty and what has been sold so far are kept.
+    pub fn restart(&mut self, market: &Account<DataAccount>, start_price: u64, now: i64) {
+        self.started_at = now;
+        self.start_price = start_price;
+        self.config = market.auction_config;
+    }
+
+    /// Whether the discount has reached its maximum.
+    pub fn has_ended(&self, now: i64) -> bool {
+        now >= self.started_at.saturating_add(self.config.duration as i64)
+    }
+
+    /// USDC the auction needs to raise: the debt plus the penalty.
+    pub fn target(&self) -> u64 {
+        self.debt.saturating_add(self.penalty)
+    }
+
+    /// Discount at `now`, growing linearly from the start to the maximum discount.
+    pub fn discount_bps(&self, now: i64) -> u16 {
+        let elapsed = now.saturating_sub(self.started_at).clamp(0, self.config.duration as i64) as u64;
+        let range = (self.config.max_discount_bps - self.config.start_discount_bps) as u64;
+        self.config.start_discount_bps + (range * elapsed / self.config.duration as u64) as u16
+    }
+
+    /// Auction price at `now`, in micro-USDC per SOL.
+    pub fn price(&self, now: i64) -> u64 {
+        ((self.start_price as u128) * (10_000 - self.discount_bps(now) as u128) / 10_000) as u64
+    }
+
+    /// USDC owed for `lamports` of collateral at `price`, rounded up in the market's favour.
+    pub fn cost(lamports: u64, price: u64) -> Result<u64> {
+        radar_math::collateral_value(lamports, price, Rounding::Up)
+            .map_err(|_| ErrorCode::LiquidationCalculationFailed.into())
+    }
+
+    /// Lamports that raise the rest of the target at `price`, rounded up so the fill covers it.
+    pub fn collateral_to_target(&self, price: u64) -> Result<u64> {
+        let remaining = self.target().saturating_sub(self.usdc_raised);
+        radar_math::collateral_for_value(remaining, 0, price, Rounding::Up)
+            .map_err(|_| ErrorCode::LiquidationCalculationFailed.into())
+    }
+
+    /// Whether the auction has raised its target or run out of collateral.
+    pub fn is_complete(&self) -> bool {
+        self.usdc_raised >= self.target() || self.collateral_remaining == 0
+    }
+}
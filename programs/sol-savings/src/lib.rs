@@ -2,60 +2,368 @@ use anchor_lang::prelude::*;
 use anchor_spl::associated_token::AssociatedToken;
 use anchor_spl::token::{self, Mint, Token, TokenAccount};
 
+pub mod auction;
+pub mod oracle;
+pub mod rate;
+
+use auction::{Auction, AuctionConfig, LiquidationMode};
+use oracle::{
+    Aggregation, MockPrice, OracleConfig, OracleSource, Price, PriceGuard, PriceHistory, PriceMode,
+    DEFAULT_MAX_CONF_BPS, DEFAULT_MAX_DEVIATION_BPS, DEFAULT_MAX_PRICE_AGE, DEFAULT_TWAP_WINDOW,
+    PRICE_HISTORY_CAPACITY, PYTH_RECEIVER_PROGRAM_ID,
+};
+use rate::{RateMode, RateModel};
+
+use radar_math::Rounding;
+
 declare_id!("4aXgVPzHdoVsKSZWS4op4oHTHHqrFHkkpV93NshquE6L");
 
 #[program]
 pub mod radar_lend {
     use super::*;
 
-    const SOL_PRICE_USD: u64 = 100_000_000; // 100 USDC per SOL
-    const LAMPORTS_PER_SOL: u64 = 1_000_000_000;
-    const SECONDS_IN_YEAR: i64 = 31_536_000;
-
     /// Initializes the Shrub PDA and its associated USDC token account.
     pub fn initialize(ctx: Context<Initialize>) -> Result<()> {
         let account_data = &mut ctx.accounts.pda_account;
         account_data.admin = *ctx.accounts.admin.key;
+        account_data.usdc_mint = ctx.accounts.usdc_mint.key();
         account_data.bump = ctx.bumps.pda_account; // KEEPING THIS LINE AS YOU SPECIFIED
-        account_data.loans = Vec::new(); // Initialize the loans vector
+        account_data.next_loan_id = 1;
+        account_data.oracle.max_price_age = DEFAULT_MAX_PRICE_AGE;
+        account_data.price_guard = PriceGuard {
+            aggregation: Aggregation::PrimaryWithFallback,
+            max_conf_bps: DEFAULT_MAX_CONF_BPS,
+            max_deviation_bps: DEFAULT_MAX_DEVIATION_BPS,
+        };
+        account_data.price_mode = PriceMode::Spot;
+        account_data.twap_window = DEFAULT_TWAP_WINDOW;
+        account_data.liquidation_bonus_bps = DEFAULT_LIQUIDATION_BONUS_BPS;
+        account_data.close_factor_bps = DEFAULT_CLOSE_FACTOR_BPS;
+        account_data.liquidation_mode = LiquidationMode::FixedBonus;
+        account_data.auction_config = AuctionConfig::default();
+        account_data.grace_period = DEFAULT_GRACE_PERIOD;
+        account_data.rate_mode = RateMode::Fixed;
+        account_data.rate_model = RateModel::default();
+        account_data.borrow_index = radar_math::WAD;
+        account_data.borrow_rate = account_data.rate_model.borrow_rate(0)?;
+        account_data.last_index_update = Clock::get()?.unix_timestamp;
         msg!("Initialized PDA with admin: {}", account_data.admin);
         msg!("PDA bump: {}", account_data.bump);
+
+        // Seed the market config with the default APY/LTV tiers
+        let market_config = &mut ctx.accounts.market_config;
+        market_config.market = account_data.key();
+        market_config.bump = ctx.bumps.market_config;
+        market_config.tiers = DEFAULT_TIERS.to_vec();
+        Ok(())
+    }
+
+    /// Allows the admin to add an APY/LTV tier to the market. The tier starts with the default
+    /// early-repayment rules, which `set_early_repayment` can change.
+    pub fn add_tier(ctx: Context<ManageTiers>, apy: u16, ltv: u16, liquidation_threshold: u16) -> Result<()> {
+        let market_config = &mut ctx.accounts.market_config;
+        if market_config.tiers.iter().any(|tier| tier.apy == apy) {
+            return Err(ErrorCode::TierAlreadyExists.into());
+        }
+        if market_config.tiers.len() >= MAX_TIERS {
+            return Err(ErrorCode::TooManyTiers.into());
+        }
+        validate_tier(ltv, liquidation_threshold)?;
+
+        market_config.tiers.push(Tier::new(apy, ltv, liquidation_threshold));
+        market_config.version += 1;
+
+        emit!(TierAdded {
+            market: market_config.market,
+            apy,
+            ltv,
+            liquidation_threshold,
+            version: market_config.version,
+        });
+        Ok(())
+    }
+
+    /// Allows the admin to change the LTV and liquidation threshold of a tier.
+    pub fn update_tier(ctx: Context<ManageTiers>, apy: u16, ltv: u16, liquidation_threshold: u16) -> Result<()> {
+        validate_tier(ltv, liquidation_threshold)?;
+
+        let market_config = &mut ctx.accounts.market_config;
+        let tier = market_config
+            .tiers
+            .iter_mut()
+            .find(|tier| tier.apy == apy)
+            .ok_or(ErrorCode::InvalidAPY)?;
+        tier.ltv = ltv;
+        tier.liquidation_threshold = liquidation_threshold;
+        market_config.version += 1;
+
+        emit!(TierUpdated {
+            market: market_config.market,
+            apy,
+            ltv,
+            liquidation_threshold,
+            version: market_config.version,
+        });
+        Ok(())
+    }
+
+    /// Allows the admin to stop new loans from being opened in a tier.
+    pub fn disable_tier(ctx: Context<ManageTiers>, apy: u16) -> Result<()> {
+        let market_config = &mut ctx.accounts.market_config;
+        let tier = market_config
+            .tiers
+            .iter_mut()
+            .find(|tier| tier.apy == apy)
+            .ok_or(ErrorCode::InvalidAPY)?;
+        tier.enabled = false;
+        market_config.version += 1;
+
+        emit!(TierDisabled {
+            market: market_config.market,
+            apy,
+            version: market_config.version,
+        });
+        Ok(())
+    }
+
+    /// Allows the admin to reopen a disabled tier to new loans.
+    pub fn enable_tier(ctx: Context<ManageTiers>, apy: u16) -> Result<()> {
+        let market_config = &mut ctx.accounts.market_config;
+        let tier = market_config
+            .tiers
+            .iter_mut()
+            .find(|tier| tier.apy == apy)
+            .ok_or(ErrorCode::InvalidAPY)?;
+        tier.enabled = true;
+        market_config.version += 1;
+
+        emit!(TierEnabled {
+            market: market_config.market,
+            apy,
+            version: market_config.version,
+        });
+        Ok(())
+    }
+
+    /// Allows the admin to set the early-repayment rules of a tier. Loans keep the rules they
+    /// were opened with.
+    pub fn set_early_repayment(
+        ctx: Context<ManageTiers>,
+        apy: u16,
+        early_repayment: EarlyRepayment,
+    ) -> Result<()> {
+        early_repayment.validate()?;
+
+        let market_config = &mut ctx.accounts.market_config;
+        let tier = market_config
+            .tiers
+            .iter_mut()
+            .find(|tier| tier.apy == apy)
+            .ok_or(ErrorCode::InvalidAPY)?;
+        tier.early_repayment = early_repayment;
+        market_config.version += 1;
+
+        emit!(EarlyRepaymentUpdated {
+            market: market_config.market,
+            apy,
+            early_repayment,
+            version: market_config.version,
+        });
+        Ok(())
+    }
+
+    /// Allows the admin to choose the primary price source used to value SOL collateral.
+    pub fn set_oracle(ctx: Context<SetOracle>, source: OracleSource, max_price_age: u32) -> Result<()> {
+        let oracle = oracle_config(ctx.accounts, source, max_price_age)?;
+        ctx.accounts.pda_account.oracle = oracle;
+        msg!("Primary oracle set to {:?} feed {} (max age {}s)", source, oracle.feed, max_price_age);
+        Ok(())
+    }
+
+    /// Allows the admin to set a secondary price source that is cross-checked against the primary.
+    pub fn set_secondary_oracle(
+        ctx: Context<SetOracle>,
+        source: OracleSource,
+        max_price_age: u32,
+    ) -> Result<()> {
+        let oracle = oracle_config(ctx.accounts, source, max_price_age)?;
+        ctx.accounts.pda_account.secondary_oracle = oracle;
+        msg!("Secondary oracle set to {:?} feed {} (max age {}s)", source, oracle.feed, max_price_age);
+        Ok(())
+    }
+
+    /// Allows the admin to remove the secondary price source.
+    pub fn remove_secondary_oracle(ctx: Context<RemoveSecondaryOracle>) -> Result<()> {
+        ctx.accounts.pda_account.secondary_oracle = OracleConfig::default();
+        msg!("Secondary oracle removed");
+        Ok(())
+    }
+
+    /// Allows the admin to configure how oracle readings are combined and validated.
+    pub fn set_price_guard(
+        ctx: Context<SetPriceGuard>,
+        aggregation: Aggregation,
+        max_conf_bps: u16,
+        max_deviation_bps: u16,
+    ) -> Result<()> {
+        ctx.accounts.pda_account.price_guard = PriceGuard {
+            aggregation,
+            max_conf_bps,
+            max_deviation_bps,
+        };
+        msg!(
+            "Price guard set to {:?} (max conf {} bps, max deviation {} bps)",
+            aggregation,
+            max_conf_bps,
+            max_deviation_bps
+        );
+        Ok(())
+    }
+
+    /// Allows the admin to choose between spot, TWAP and min(spot, TWAP) collateral pricing.
+    pub fn set_price_mode(ctx: Context<SetPriceMode>, price_mode: PriceMode, twap_window: u32) -> Result<()> {
+        if twap_window == 0 {
+            return Err(ErrorCode::InvalidTwapWindow.into());
+        }
+        let account_data = &mut ctx.accounts.pda_account;
+        account_data.price_mode = price_mode;
+        account_data.twap_window = twap_window;
+        msg!("Price mode set to {:?} (TWAP window {}s)", price_mode, twap_window);
+        Ok(())
+    }
+
+    /// Allows the admin to set the bonus paid to liquidators, in basis points of the repaid debt.
+    pub fn set_liquidation_bonus(ctx: Context<SetLiquidationBonus>, liquidation_bonus_bps: u16) -> Result<()> {
+        if liquidation_bonus_bps > MAX_LIQUIDATION_BONUS_BPS {
+            return Err(ErrorCode::InvalidLiquidationBonus.into());
+        }
+        ctx.accounts.pda_account.liquidation_bonus_bps = liquidation_bonus_bps;
+        msg!("Liquidation bonus set to {} bps", liquidation_bonus_bps);
+        Ok(())
+    }
+
+    /// Allows the admin to set the share of a loan's debt a single liquidation can repay, in basis points.
+    pub fn set_close_factor(ctx: Context<SetCloseFactor>, close_factor_bps: u16) -> Result<()> {
+        if close_factor_bps == 0 || close_factor_bps > 10_000 {
+            return Err(ErrorCode::InvalidCloseFactor.into());
+        }
+        ctx.accounts.pda_account.close_factor_bps = close_factor_bps;
+        msg!("Close factor set to {} bps", close_factor_bps);
+        Ok(())
+    }
+
+    /// Allows the admin to choose between fixed-bonus and Dutch-auction liquidation.
+    pub fn set_liquidation_mode(
+        ctx: Context<SetLiquidationMode>,
+        liquidation_mode: LiquidationMode,
+        auction_config: AuctionConfig,
+    ) -> Result<()> {
+        auction_config.validate()?;
+        let account_data = &mut ctx.accounts.pda_account;
+        account_data.liquidation_mode = liquidation_mode;
+        account_data.auction_config = auction_config;
+        msg!("Liquidation mode set to {:?} ({:?})", liquidation_mode, auction_config);
+        Ok(())
+    }
+
+    /// Allows the admin to set how long after maturity a loan can still be repaid, in seconds.
+    pub fn set_grace_period(ctx: Context<SetGracePeriod>, grace_period: u32) -> Result<()> {
+        if grace_period > MAX_GRACE_PERIOD {
+            return Err(ErrorCode::InvalidGracePeriod.into());
+        }
+        ctx.accounts.pda_account.grace_period = grace_period;
+        msg!("Grace period set to {} seconds", grace_period);
+        Ok(())
+    }
+
+    /// Allows the admin to choose between fixed-tier and variable-rate interest for new loans and
+    /// to set the curve the variable borrow rate follows. The borrow index is brought up to date
+    /// at the old rate before the new curve takes effect; open loans keep their rate mode.
+    pub fn set_rate_model(
+        ctx: Context<SetRateModel>,
+        rate_mode: RateMode,
+        rate_model: RateModel,
+    ) -> Result<()> {
+        rate_model.validate()?;
+        let account_data = &mut ctx.accounts.pda_account;
+        account_data.update_borrow_index(Clock::get()?.unix_timestamp)?;
+        account_data.rate_mode = rate_mode;
+        account_data.rate_model = rate_model;
+        account_data.refresh_borrow_rate(&mut ctx.accounts.shrub_usdc_account)?;
+        msg!(
+            "Rate mode set to {:?} ({:?}), borrow rate {} bps",
+            rate_mode,
+            rate_model,
+            account_data.borrow_rate
+        );
+        Ok(())
+    }
+
+    /// Records the current oracle price in the market's price history. Anyone can crank.
+    pub fn crank_price(ctx: Context<CrankPrice>) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+        let market = &ctx.accounts.pda_account;
+        let spot = market.read_sol_price(
+            &ctx.accounts.oracle_program,
+            &ctx.accounts.price_feed,
+            ctx.accounts.secondary_oracle_program.as_ref(),
+            ctx.accounts.secondary_price_feed.as_ref(),
+        )?;
+
+        // Space observations so the full buffer's 31 gaps always span a TWAP window, leaving
+        // an observation at or before the window's start once the oldest is overwritten
+        let min_interval =
+            market.twap_window.div_ceil(PRICE_HISTORY_CAPACITY as u32 - 1).max(1) as i64;
+
+        let price_history = &mut ctx.accounts.price_history;
+        price_history.market = market.key();
+        price_history.record(spot.price, current_time, min_interval)?;
+
+        emit!(PriceCranked {
+            market: market.key(),
+            price: spot.price,
+            timestamp: current_time,
+        });
+
         Ok(())
     }
 
-    /// Allows users to take a loan by specifying principal, APY, and collateral.
+    /// Allows the admin to publish a price to the market's mock price account.
+    pub fn set_mock_price(ctx: Context<SetMockPrice>, price: u64, conf: u64) -> Result<()> {
+        let mock_price = &mut ctx.accounts.mock_price;
+        mock_price.market = ctx.accounts.pda_account.key();
+        mock_price.price = price;
+        mock_price.conf = conf;
+        mock_price.publish_time = Clock::get()?.unix_timestamp;
+        msg!("Mock price set to {} (conf {})", price, conf);
+        Ok(())
+    }
+
+    /// Allows users to take a loan by specifying principal, APY, and collateral. A borrower can
+    /// hold up to `MAX_OPEN_LOANS` open loans in a market.
     pub fn take_loan(
         ctx: Context<TakeLoan>,
         principal: u64,  // Amount of USDC to borrow (in micro units, i.e., 6 decimals)
         apy: u16,        // Annual Percentage Yield in basis points (bps)
         collateral: u64, // Amount of SOL to collateralize (in lamports)
+        term: LoanTerm,  // Term of the loan, which sets its maturity
     ) -> Result<()> {
-        // Define allowed APY:LTV pairs (APY in bps, LTV in bps)
-        let allowed_pairs = vec![
-            (800u16, 5000u64), // 50% LTV
-            (500u16, 3300u64), // 33% LTV
-            (100u16, 2500u64), // 25% LTV
-            (0u16, 2000u64),   // 20% LTV
-        ];
-
-        // Find the LTV corresponding to the provided APY
-        let ltv = allowed_pairs
-            .iter()
-            .find(|&&(allowed_apy, _)| allowed_apy == apy)
-            .map(|&(_, ltv)| ltv)
+        // Find the enabled tier for the provided APY
+        let tier = *ctx
+            .accounts
+            .market_config
+            .tier(apy)
             .ok_or(ErrorCode::InvalidAPY)?;
+        let ltv = tier.ltv;
 
-        // Convert SOL price to micro-USDC (6 decimals) representation.
-        // Calculate required collateral in lamports using integer arithmetic:
-        // Formula: required_collateral_lamports = (principal * LAMPORTS_PER_SOL * 10_000) / (ltv * SOL_PRICE_USD)
-        let required_collateral_lamports = (principal as u128)
-            .checked_mul(LAMPORTS_PER_SOL as u128)
-            .and_then(|val| val.checked_mul(10_000))
-            .and_then(|val| val.checked_div((ltv as u128).checked_mul(SOL_PRICE_USD as u128).unwrap()))
-            .ok_or(ErrorCode::InsufficientCollateral)?;
+        // Read the SOL price in micro-USDC (6 decimals) from the market's oracles, refusing to
+        // lend while they disagree.
+        let sol_price = ctx.accounts.price.price(&ctx.accounts.pda_account)?.undisputed()?;
 
-        let required_collateral_lamports_u64 = required_collateral_lamports as u64;
+        // Calculate required collateral in lamports, rounded down
+        let required_collateral_lamports_u64 = required_collateral(principal, ltv, sol_price)?;
 
+        msg!("SOL price (micro-USDC): {}", sol_price);
         msg!("Provided collateral: {}", collateral);
         msg!("Required collateral (lamports): {}", required_collateral_lamports_u64);
 
@@ -98,19 +406,59 @@ pub mod radar_lend {
             principal,
         )?;
 
-        // Record the loan details
-        let loan_id = ctx.accounts.pda_account.loans.len() as u64 + 1;
-        let loan = Loan {
-            id: loan_id,
-            principal,
-            apy,
-            collateral,
-            created_at: Clock::get()?.unix_timestamp,
-            borrower: ctx.accounts.user.key(),  // Track borrower
-            repaid: false,
-        };
-        let account_data = &mut ctx.accounts.pda_account;
-        account_data.loans.push(loan);
+        // Assign the next loan id. Ids are never handed out twice, even once loans are closed.
+        let loan_id = ctx.accounts.pda_account.next_loan_id;
+        ctx.accounts.pda_account.next_loan_id = loan_id
+            .checked_add(1)
+            .ok_or(ErrorCode::LoanIdOverflow)?;
+
+        // Bring the borrow index up to date; variable-rate loans grow with it from here
+        let borrow_index = ctx
+            .accounts
+            .pda_account
+            .update_borrow_index(Clock::get()?.unix_timestamp)?;
+
+        // Record the loan details in the loan's own account
+        let loan = &mut ctx.accounts.loan;
+        if loan.id != 0 {
+            return Err(ErrorCode::LoanIdReused.into());
+        }
+        loan.id = loan_id;
+        loan.market = ctx.accounts.pda_account.key();
+        loan.principal = principal;
+        loan.apy = apy;
+        loan.rate_mode = ctx.accounts.pda_account.rate_mode;
+        // Snapshot the terms so later tier changes don't affect this loan
+        loan.ltv = tier.ltv;
+        loan.liquidation_threshold = tier.liquidation_threshold;
+        loan.origination_price = sol_price;
+        loan.tier_version = ctx.accounts.market_config.version;
+        loan.early_repayment = tier.early_repayment;
+        loan.collateral = collateral;
+        loan.created_at = Clock::get()?.unix_timestamp;
+        loan.term = term;
+        loan.matures_at = loan
+            .created_at
+            .checked_add(term.duration())
+            .ok_or(ErrorCode::InvalidLoanDuration)?;
+        loan.last_accrual = loan.created_at;
+        loan.borrow_index = borrow_index;
+        loan.borrower = ctx.accounts.user.key(); // Track borrower
+        loan.bump = ctx.bumps.loan;
+
+        // Track the loan in the borrower's position index
+        let borrower_index = &mut ctx.accounts.borrower_index;
+        if borrower_index.borrower == Pubkey::default() {
+            borrower_index.market = ctx.accounts.pda_account.key();
+            borrower_index.borrower = ctx.accounts.user.key();
+            borrower_index.bump = ctx.bumps.borrower_index;
+        }
+        borrower_index.open_loan(loan)?;
+
+        // Add the principal to the market total and reprice the variable rate
+        let market = &mut ctx.accounts.pda_account;
+        market.adjust_total_principal(principal as i128)?;
+        market.refresh_borrow_rate(&mut ctx.accounts.shrub_usdc_account)?;
 
         // Emit a LoanTaken event
         emit!(LoanTaken {
@@ -118,7 +466,10 @@ pub mod radar_lend {
             borrower: ctx.accounts.user.key(),
             principal,
             apy,
+            rate_mode: loan.rate_mode,
             collateral,
+            term,
+            matures_at: loan.matures_at,
         });
 
         Ok(())
@@ -127,50 +478,31 @@ pub mod radar_lend {
     /// Allows users to repay their loans, receiving back their collateral.
     pub fn repay_loan(ctx: Context<RepayLoan>, loan_id: u64) -> Result<()> {
         let current_time = Clock::get()?.unix_timestamp;
-
-        // Find the loan index by loan_id
-        let loan_index = ctx
-            .accounts
-            .pda_account
-            .loans
-            .iter()
-            .position(|loan| loan.id == loan_id)
-            .ok_or(ErrorCode::LoanNotFound)?;
-
-        let pda_account_bump = ctx.accounts.pda_account.bump;
-        let pda_account_key = ctx.accounts.pda_account.key();
-        let pda_account_info = ctx.accounts.pda_account.to_account_info();
-        // Make a mutable reference to the loan
-        let loan = &mut ctx.accounts.pda_account.loans[loan_index];
-
-        // Ensure the loan is not already repaid
-        if loan.repaid {
-            return Err(ErrorCode::LoanAlreadyRepaid.into());
-        }
+        let borrow_index = ctx.accounts.pda_account.update_borrow_index(current_time)?;
+        let loan = &mut ctx.accounts.loan;
 
         // Ensure the user is the borrower
         if loan.borrower != ctx.accounts.user.key() {
             return Err(ErrorCode::Unauthorized.into());
         }
+        if loan.in_auction {
+            return Err(ErrorCode::LoanInAuction.into());
+        }
+        // Past maturity and the grace period, the collateral can only be liquidated or auctioned
+        if loan.is_expired(current_time, ctx.accounts.pda_account.grace_period) {
+            return Err(ErrorCode::LoanExpired.into());
+        }
 
-        // Calculate interest: principal * (apy / 10000) * (duration / SECONDS_IN_YEAR)
-        let duration = (current_time - loan.created_at) as u128;
-        // if duration < 0 {
-        //     return Err(ErrorCode::InvalidLoanDuration.into());
-        // }
-
-        let interest = (loan.principal as u128)
-            .checked_mul(loan.apy as u128)
-            .and_then(|val| val.checked_mul(duration))
-            .and_then(|val| val.checked_div(10_000 * SECONDS_IN_YEAR as u128))
-            .ok_or(ErrorCode::InterestCalculationFailed)?;
-
-        let total_repayment = (loan.principal as u128)
+        // Calculate interest at the loan's fixed APY or the market's variable rate, plus any fee
+        // for repaying early
+        let interest = loan.interest(current_time, borrow_index)?;
+        let early_repayment_fee = loan.early_repayment_fee(current_time)?;
+        let total_repayment_u64 = loan
+            .principal
             .checked_add(interest)
+            .and_then(|total| total.checked_add(early_repayment_fee))
             .ok_or(ErrorCode::InterestCalculationFailed)?;
 
-        let total_repayment_u64 = total_repayment as u64;
-
         // Transfer USDC from the user to the Shrub's USDC account
         token::transfer(
             CpiContext::new(
@@ -184,232 +516,2597 @@ pub mod radar_lend {
             total_repayment_u64,
         )?;
 
-        // Transfer SOL collateral back to the user using PDA's signature
-        let binding = ctx.accounts.admin.key();
-        let seeds = &[b"shrub", binding.as_ref(), &[pda_account_bump]];
-        let signer_seeds = &[&seeds[..]];
-
-        msg!("&pda_account_key: {}", &pda_account_key);
-        msg!("accounts.user.key(): {}", &ctx.accounts.user.key());
+        // Transfer SOL collateral back to the user. The PDA is owned by this program,
+        // so lamports are moved directly rather than through the system program.
         msg!("loan.collateral: {}", loan.collateral);
+        ctx.accounts.pda_account.sub_lamports(loan.collateral)?;
+        ctx.accounts.user.add_lamports(loan.collateral)?;
 
-        let transfer_sol_ix = anchor_lang::solana_program::system_instruction::transfer(
-            &pda_account_key,
-            &ctx.accounts.user.key(),
-            loan.collateral,
-        );
-        anchor_lang::solana_program::program::invoke_signed(
-            &transfer_sol_ix,
-            &[
-                pda_account_info,
-                ctx.accounts.user.to_account_info(),
-                ctx.accounts.system_program.to_account_info(),
-            ],
-            signer_seeds,
-        )?;
-
-        // Mark the loan as repaid
-        loan.repaid = true;
+        // Remove the loan from the borrower's index; the loan account itself is closed
+        // and its rent refunded to the borrower when the instruction completes.
+        ctx.accounts.borrower_index.close_loan(loan)?;
+        let market = &mut ctx.accounts.pda_account;
+        market.adjust_total_principal(-(loan.principal as i128))?;
+        market.refresh_borrow_rate(&mut ctx.accounts.shrub_usdc_account)?;
 
         // Emit a LoanRepaid event
         emit!(LoanRepaid {
         loan_id,
         borrower: ctx.accounts.user.key(),
         principal: loan.principal,
-        interest: total_repayment_u64 - loan.principal,
+        interest,
+        early_repayment_fee,
         collateral: loan.collateral,
+        matures_at: loan.matures_at,
     });
 
+        // Emit the final loan record so indexers keep the history of closed loans
+        emit!(LoanClosed {
+            loan: (**loan).clone(),
+            closed_at: current_time,
+        });
+
         Ok(())
     }
 
-    /// Allows the admin to deposit USDC into the shrub's USDC account.
-    pub fn deposit_usdc(ctx: Context<DepositUsdc>, amount: u64) -> Result<()> {
-        msg!("Starting deposit_usdc instruction");
+    /// Allows borrowers to pay down part of a loan's debt. Accrued interest is paid before
+    /// principal, and collateral is released in proportion to the principal repaid, capped so
+    /// the remaining collateral still covers the remaining debt at the loan's LTV. The
+    /// early-repayment fee is charged on top, pro-rated by the principal repaid. While the price
+    /// is unavailable or the oracles disagree, the payment is still accepted but no collateral
+    /// is released. Paying off the whole debt goes through `repay_loan`.
+    pub fn repay_partial(ctx: Context<RepayPartial>, loan_id: u64, amount: u64) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+        let market = &ctx.accounts.pda_account;
+        let sol_price = match ctx.accounts.price.price(market).and_then(|price| price.undisputed()) {
+            Ok(sol_price) => Some(sol_price),
+            Err(err) => {
+                msg!("No usable price, releasing no collateral: {:?}", err);
+                None
+            }
+        };
+        let grace_period = market.grace_period;
+        let borrow_index = ctx.accounts.pda_account.update_borrow_index(current_time)?;
+        let loan = &mut ctx.accounts.loan;
+
+        // Ensure the user is the borrower
+        if loan.borrower != ctx.accounts.user.key() {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+        if loan.in_auction {
+            return Err(ErrorCode::LoanInAuction.into());
+        }
+        if loan.is_expired(current_time, grace_period) {
+            return Err(ErrorCode::LoanExpired.into());
+        }
 
+        loan.accrue(current_time, borrow_index)?;
+        let debt = loan.total_owed(current_time, borrow_index)?;
+        if amount == 0 || amount >= debt {
+            return Err(ErrorCode::InvalidRepaymentAmount.into());
+        }
+
+        // Interest is paid off before principal
+        let principal_before = loan.principal;
+        let full_fee = loan.early_repayment_fee(current_time)?;
+        let interest_repaid = amount.min(loan.accrued_interest);
+        let principal_repaid = loan.apply_repayment(amount)?;
+
+        // Charge the share of the early-repayment fee for the principal repaid, rounded up so
+        // splitting a repayment never costs less than repaying at once
+        let early_repayment_fee = radar_math::mul_div(
+            full_fee as u128,
+            principal_repaid as u128,
+            principal_before as u128,
+            Rounding::Up,
+        )
+        .map_err(|_| ErrorCode::InterestCalculationFailed)? as u64;
+        let total_repayment = amount
+            .checked_add(early_repayment_fee)
+            .ok_or(ErrorCode::InterestCalculationFailed)?;
+
+        // The other modes scale with the principal; a flat fee keeps only the share still owed
+        if let EarlyRepayment::FlatFee { fee, .. } = &mut loan.early_repayment {
+            *fee = fee.saturating_sub(early_repayment_fee);
+        }
+
+        // Release collateral in proportion to the principal repaid, keeping what the remaining
+        // debt requires at the loan's LTV
+        let proportional = if principal_repaid == 0 || sol_price.is_none() {
+            0
+        } else {
+            radar_math::mul_div(
+                loan.collateral as u128,
+                principal_repaid as u128,
+                principal_before as u128,
+                Rounding::Down,
+            )
+            .map_err(|_| ErrorCode::PositionCalculationFailed)? as u64
+        };
+        let collateral_released = match sol_price {
+            Some(sol_price) => {
+                let required = required_collateral(
+                    loan.total_owed(current_time, borrow_index)?,
+                    loan.ltv,
+                    sol_price,
+                )?;
+                proportional.min(loan.collateral.saturating_sub(required))
+            }
+            None => 0,
+        };
+        loan.collateral -= collateral_released;
+
+        // Transfer USDC from the user to the Shrub's USDC account
         token::transfer(
             CpiContext::new(
                 ctx.accounts.token_program.to_account_info(),
                 token::Transfer {
-                    from: ctx.accounts.admin_usdc_account.to_account_info(),
+                    from: ctx.accounts.user_usdc_account.to_account_info(),
                     to: ctx.accounts.shrub_usdc_account.to_account_info(),
-                    authority: ctx.accounts.admin.to_account_info(),
+                    authority: ctx.accounts.user.to_account_info(),
                 },
             ),
-            amount,
+            total_repayment,
         )?;
 
-        msg!("Admin deposited {} USDC to Shrub's account", amount);
+        // Release the collateral from the PDA to the borrower
+        ctx.accounts.pda_account.sub_lamports(collateral_released)?;
+        ctx.accounts.user.add_lamports(collateral_released)?;
+
+        ctx.accounts
+            .borrower_index
+            .adjust(-(principal_repaid as i128), -(collateral_released as i128))?;
+        let market = &mut ctx.accounts.pda_account;
+        market.adjust_total_principal(-(principal_repaid as i128))?;
+        market.refresh_borrow_rate(&mut ctx.accounts.shrub_usdc_account)?;
+
+        let loan = &ctx.accounts.loan;
+        emit!(LoanPartiallyRepaid {
+            loan_id,
+            borrower: loan.borrower,
+            amount,
+            interest_repaid,
+            principal_repaid,
+            early_repayment_fee,
+            collateral_released,
+            remaining_principal: loan.principal,
+            remaining_collateral: loan.collateral,
+        });
+
         Ok(())
     }
-}
 
-#[derive(Accounts)]
-pub struct Initialize<'info> {
-    /// The admin who initializes the PDA.
-    #[account(mut)]
-    pub admin: Signer<'info>,
+    /// Adds SOL collateral to an open loan. Anyone can top up a loan, so the borrower or a bot
+    /// acting for them can keep it healthy.
+    pub fn add_collateral(ctx: Context<AddCollateral>, loan_id: u64, lamports: u64) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+        let loan = &ctx.accounts.loan;
+        if lamports == 0 {
+            return Err(ErrorCode::InvalidCollateralAmount.into());
+        }
+        if loan.in_auction {
+            return Err(ErrorCode::LoanInAuction.into());
+        }
+        // Collateral added to an expired loan would only be auctioned
+        if loan.is_expired(current_time, ctx.accounts.pda_account.grace_period) {
+            return Err(ErrorCode::LoanExpired.into());
+        }
+        ctx.accounts.pda_account.update_borrow_index(current_time)?;
 
-    /// The PDA account to be initialized.
-    #[account(
-        init,
-        seeds = [b"shrub", admin.key().as_ref()],
-        bump,
-        payer = admin,
-        space = 8 + DataAccount::INIT_SPACE,
-    )]
-    pub pda_account: Account<'info, DataAccount>,
+        // Transfer SOL from the depositor to the PDA
+        let transfer_sol_ix = anchor_lang::solana_program::system_instruction::transfer(
+            &ctx.accounts.depositor.key(),
+            &ctx.accounts.pda_account.key(),
+            lamports,
+        );
+        anchor_lang::solana_program::program::invoke(
+            &transfer_sol_ix,
+            &[
+                ctx.accounts.depositor.to_account_info(),
+                ctx.accounts.pda_account.to_account_info(),
+                ctx.accounts.system_program.to_account_info(),
+            ],
+        )?;
 
-    /// The Shrub PDA's associated USDC token account.
-    #[account(
-        init_if_needed,
-        payer = admin,
-        associated_token::mint = usdc_mint,
-        associated_token::authority = pda_account,
-    )]
-    pub shrub_usdc_account: Account<'info, TokenAccount>,
+        let loan = &mut ctx.accounts.loan;
+        loan.collateral = loan
+            .collateral
+            .checked_add(lamports)
+            .ok_or(ErrorCode::PositionCalculationFailed)?;
+        ctx.accounts.borrower_index.adjust(0, lamports as i128)?;
 
-    /// The USDC mint.
-    pub usdc_mint: Account<'info, Mint>,
+        emit!(CollateralAdded {
+            loan_id,
+            borrower: loan.borrower,
+            depositor: ctx.accounts.depositor.key(),
+            amount: lamports,
+            total_collateral: loan.collateral,
+        });
 
-    /// System program.
-    pub system_program: Program<'info, System>,
+        Ok(())
+    }
 
-    /// Token program.
-    pub token_program: Program<'info, Token>,
+    /// Allows borrowers to withdraw collateral from a loan, as long as the remaining collateral
+    /// still covers the debt at the loan's origination LTV and the current price.
+    pub fn withdraw_collateral(ctx: Context<WithdrawCollateral>, loan_id: u64, lamports: u64) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+        let market = &ctx.accounts.pda_account;
+        let sol_price = ctx.accounts.price.price(market)?.undisputed()?;
+        let grace_period = market.grace_period;
+        let borrow_index = ctx.accounts.pda_account.update_borrow_index(current_time)?;
+        let loan = &mut ctx.accounts.loan;
 
-    /// Associated token program.
-    pub associated_token_program: Program<'info, AssociatedToken>,
+        // Ensure the user is the borrower
+        if loan.borrower != ctx.accounts.user.key() {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+        if lamports == 0 {
+            return Err(ErrorCode::InvalidCollateralAmount.into());
+        }
+        if loan.in_auction {
+            return Err(ErrorCode::LoanInAuction.into());
+        }
+        if loan.is_expired(current_time, grace_period) {
+            return Err(ErrorCode::LoanExpired.into());
+        }
 
-    /// Rent sysvar.
-    pub rent: Sysvar<'info, Rent>,
-}
+        // The remaining collateral must still cover the debt at the origination LTV
+        let required = required_collateral(
+            loan.total_owed(current_time, borrow_index)?,
+            loan.ltv,
+            sol_price,
+        )?;
+        let remaining = loan
+            .collateral
+            .checked_sub(lamports)
+            .ok_or(ErrorCode::InsufficientCollateral)?;
+        if remaining < required {
+            return Err(ErrorCode::InsufficientCollateral.into());
+        }
+        loan.collateral = remaining;
 
-#[derive(Accounts)]
-pub struct TakeLoan<'info> {
-    /// The PDA account.
-    #[account(
-        mut,
-        has_one = admin,
-        seeds = [b"shrub", admin.key().as_ref()],
-        bump = pda_account.bump
-    )]
+        // Release the collateral from the PDA to the borrower
+        ctx.accounts.pda_account.sub_lamports(lamports)?;
+        ctx.accounts.user.add_lamports(lamports)?;
+        ctx.accounts.borrower_index.adjust(0, -(lamports as i128))?;
+
+        emit!(CollateralWithdrawn {
+            loan_id,
+            borrower: ctx.accounts.user.key(),
+            amount: lamports,
+            total_collateral: remaining,
+            sol_price,
+        });
+
+        Ok(())
+    }
+
+    /// Allows borrowers to borrow more USDC against an existing loan. Interest accrued so far
+    /// is settled first, and the collateral must cover the new debt at the lower of the loan's
+    /// LTV and its tier's current LTV.
+    pub fn increase_principal(ctx: Context<IncreasePrincipal>, loan_id: u64, amount: u64) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+        let market = &ctx.accounts.pda_account;
+        let sol_price = ctx.accounts.price.price(market)?.undisputed()?;
+        let grace_period = market.grace_period;
+        let borrow_index = ctx.accounts.pda_account.update_borrow_index(current_time)?;
+        let loan = &mut ctx.accounts.loan;
+
+        // Ensure the user is the borrower
+        if loan.borrower != ctx.accounts.user.key() {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+        if amount == 0 {
+            return Err(ErrorCode::InvalidBorrowAmount.into());
+        }
+        if loan.in_auction {
+            return Err(ErrorCode::LoanInAuction.into());
+        }
+        if loan.is_expired(current_time, grace_period) {
+            return Err(ErrorCode::LoanExpired.into());
+        }
+        // No new borrowing at a tier the admin has disabled, nor above an LTV it has since lowered
+        let tier = ctx
+            .accounts
+            .market_config
+            .tier(loan.apy)
+            .ok_or(ErrorCode::InvalidAPY)?;
+        let ltv = loan.ltv.min(tier.ltv);
+
+        // Settle interest at the current principal before it changes
+        loan.accrue(current_time, borrow_index)?;
+        let principal = loan
+            .principal
+            .checked_add(amount)
+            .ok_or(ErrorCode::PositionCalculationFailed)?;
+        let debt = principal
+            .checked_add(loan.accrued_interest)
+            .ok_or(ErrorCode::PositionCalculationFailed)?;
+        if loan.collateral < required_collateral(debt, ltv, sol_price)? {
+            return Err(ErrorCode::InsufficientCollateral.into());
+        }
+        loan.principal = principal;
+        ctx.accounts.borrower_index.adjust(amount as i128, 0)?;
+        ctx.accounts.pda_account.adjust_total_principal(amount as i128)?;
+
+        // Transfer USDC from the Shrub's USDC account to the user's USDC account
+        // Since the PDA is the authority, we need to sign with PDA's seeds
+        let binding = ctx.accounts.admin.key();
+        let seeds = &[b"shrub", binding.as_ref(), &[ctx.accounts.pda_account.bump]];
+        let signer_seeds = &[&seeds[..]];
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: ctx.accounts.shrub_usdc_account.to_account_info(),
+                    to: ctx.accounts.user_usdc_account.to_account_info(),
+                    authority: ctx.accounts.pda_account.to_account_info(),
+                },
+            )
+                .with_signer(signer_seeds),
+            amount,
+        )?;
+        ctx.accounts
+            .pda_account
+            .refresh_borrow_rate(&mut ctx.accounts.shrub_usdc_account)?;
+
+        let loan = &ctx.accounts.loan;
+        emit!(PrincipalIncreased {
+            loan_id,
+            borrower: loan.borrower,
+            amount,
+            interest_settled: loan.accrued_interest,
+            principal: loan.principal,
+            collateral: loan.collateral,
+            sol_price,
+        });
+
+        Ok(())
+    }
+
+    /// Moves a loan to another enabled tier in one step. Interest accrued under the old APY is
+    /// settled first, then the loan takes the new tier's terms. If the new tier needs more
+    /// collateral the borrower deposits the shortfall; if it needs less, the collateral freed
+    /// by the lower requirement is released, keeping any buffer the borrower held.
+    pub fn refinance_loan(ctx: Context<RefinanceLoan>, loan_id: u64, new_apy: u16) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+        let tier = *ctx
+            .accounts
+            .market_config
+            .tier(new_apy)
+            .ok_or(ErrorCode::InvalidAPY)?;
+        let market = &ctx.accounts.pda_account;
+        let sol_price = ctx.accounts.price.price(market)?.undisputed()?;
+        let grace_period = market.grace_period;
+        let borrow_index = ctx.accounts.pda_account.update_borrow_index(current_time)?;
+        let loan = &mut ctx.accounts.loan;
+
+        // Ensure the user is the borrower
+        if loan.borrower != ctx.accounts.user.key() {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+        if loan.in_auction {
+            return Err(ErrorCode::LoanInAuction.into());
+        }
+        if loan.is_expired(current_time, grace_period) {
+            return Err(ErrorCode::LoanExpired.into());
+        }
+
+        // Settle interest under the old APY before switching
+        loan.accrue(current_time, borrow_index)?;
+        let debt = loan.total_owed(current_time, borrow_index)?;
+        let old_required = required_collateral(debt, loan.ltv, sol_price)?;
+        let new_required = required_collateral(debt, tier.ltv, sol_price)?;
+        let collateral_added = new_required.saturating_sub(loan.collateral);
+        let collateral_released = old_required
+            .saturating_sub(new_required)
+            .min(loan.collateral.saturating_sub(new_required));
+
+        let old_apy = loan.apy;
+        loan.apy = tier.apy;
+        loan.ltv = tier.ltv;
+        loan.liquidation_threshold = tier.liquidation_threshold;
+        loan.origination_price = sol_price;
+        loan.tier_version = ctx.accounts.market_config.version;
+        loan.early_repayment = tier.early_repayment;
+        loan.collateral = loan
+            .collateral
+            .checked_add(collateral_added)
+            .and_then(|collateral| collateral.checked_sub(collateral_released))
+            .ok_or(ErrorCode::PositionCalculationFailed)?;
+
+        if collateral_added > 0 {
+            // Transfer the shortfall from the user to the PDA
+            let transfer_sol_ix = anchor_lang::solana_program::system_instruction::transfer(
+                &ctx.accounts.user.key(),
+                &ctx.accounts.pda_account.key(),
+                collateral_added,
+            );
+            anchor_lang::solana_program::program::invoke(
+                &transfer_sol_ix,
+                &[
+                    ctx.accounts.user.to_account_info(),
+                    ctx.accounts.pda_account.to_account_info(),
+                    ctx.accounts.system_program.to_account_info(),
+                ],
+            )?;
+        }
+        if collateral_released > 0 {
+            ctx.accounts.pda_account.sub_lamports(collateral_released)?;
+            ctx.accounts.user.add_lamports(collateral_released)?;
+        }
+        ctx.accounts
+            .borrower_index
+            .adjust(0, collateral_added as i128 - collateral_released as i128)?;
+
+        let loan = &ctx.accounts.loan;
+        emit!(LoanRefinanced {
+            loan_id,
+            borrower: loan.borrower,
+            old_apy,
+            new_apy: loan.apy,
+            ltv: loan.ltv,
+            liquidation_threshold: loan.liquidation_threshold,
+            interest_settled: loan.accrued_interest,
+            collateral_added,
+            collateral_released,
+            collateral: loan.collateral,
+            sol_price,
+        });
+
+        Ok(())
+    }
+
+    /// Rolls a loan over into a new term instead of repaying it, from `EXTENSION_WINDOW` before
+    /// maturity until the grace period ends. Accrued interest is capitalized into the principal
+    /// and the maturity moves forward by the new term, as long as the loan's tier is still enabled
+    /// and the collateral covers the new principal at the current price and the lower of the
+    /// loan's LTV and its tier's current LTV. The loan otherwise keeps its origination terms.
+    pub fn extend_loan(ctx: Context<ExtendLoan>, loan_id: u64, new_term: LoanTerm) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+        let market = &ctx.accounts.pda_account;
+        let sol_price = ctx.accounts.price.price(market)?.undisputed()?;
+        let grace_period = market.grace_period;
+        let borrow_index = ctx.accounts.pda_account.update_borrow_index(current_time)?;
+        let loan = &mut ctx.accounts.loan;
+
+        // Ensure the user is the borrower
+        if loan.borrower != ctx.accounts.user.key() {
+            return Err(ErrorCode::Unauthorized.into());
+        }
+        if loan.in_auction {
+            return Err(ErrorCode::LoanInAuction.into());
+        }
+        if loan.is_expired(current_time, grace_period) {
+            return Err(ErrorCode::LoanExpired.into());
+        }
+        // Only near maturity, so terms can't be stacked far into the future
+        if current_time < loan.matures_at.saturating_sub(EXTENSION_WINDOW) {
+            return Err(ErrorCode::ExtensionTooEarly.into());
+        }
+        // Rolling over borrows for another term, so not at a tier the admin has disabled nor
+        // above an LTV it has since lowered
+        let tier = ctx
+            .accounts
+            .market_config
+            .tier(loan.apy)
+            .ok_or(ErrorCode::InvalidAPY)?;
+        let ltv = loan.ltv.min(tier.ltv);
+
+        let interest_capitalized = loan.capitalize_interest(current_time, borrow_index)?;
+        if loan.collateral < required_collateral(loan.principal, ltv, sol_price)? {
+            return Err(ErrorCode::InsufficientCollateral.into());
+        }
+
+        let previous_maturity = loan.matures_at;
+        loan.term = new_term;
+        loan.matures_at = previous_maturity
+            .checked_add(new_term.duration())
+            .ok_or(ErrorCode::InvalidLoanDuration)?;
+        ctx.accounts
+            .borrower_index
+            .adjust(interest_capitalized as i128, 0)?;
+        let market = &mut ctx.accounts.pda_account;
+        market.adjust_total_principal(interest_capitalized as i128)?;
+        market.refresh_borrow_rate(&mut ctx.accounts.shrub_usdc_account)?;
+
+        let loan = &ctx.accounts.loan;
+        emit!(LoanExtended {
+            loan_id,
+            borrower: loan.borrower,
+            term: new_term,
+            previous_maturity,
+            matures_at: loan.matures_at,
+            interest_capitalized,
+            principal: loan.principal,
+            collateral: loan.collateral,
+            sol_price,
+        });
+
+        Ok(())
+    }
+
+    /// Liquidates an undercollateralized loan. Anyone can liquidate: the liquidator repays
+    /// up to `repay_amount` of the loan's debt in USDC and receives collateral worth the
+    /// repaid amount plus the market's liquidation bonus.
+    ///
+    /// Loans past maturity and the grace period can be liquidated whatever their health.
+    /// Repayment is capped by the market's close factor, unless the loan has expired or the
+    /// collateral no longer covers the debt plus bonus, in which case the whole debt can be
+    /// repaid. A partially liquidated loan stays open with reduced principal and collateral; a
+    /// fully liquidated loan is closed and any remaining collateral returned to the borrower. A
+    /// liquidation that seizes all of the collateral also closes the loan, writing off any debt
+    /// left unpaid.
+    pub fn liquidate_loan(ctx: Context<LiquidateLoan>, loan_id: u64, repay_amount: u64) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+        let market = &ctx.accounts.pda_account;
+        if market.liquidation_mode != LiquidationMode::FixedBonus || ctx.accounts.loan.in_auction {
+            return Err(ErrorCode::WrongLiquidationMode.into());
+        }
+        let sol_price = ctx.accounts.price.price(market)?.undisputed()?;
+        let grace_period = market.grace_period;
+        let borrow_index = ctx.accounts.pda_account.update_borrow_index(current_time)?;
+        let loan = &mut ctx.accounts.loan;
+        loan.accrue(current_time, borrow_index)?;
+        let debt = loan.total_owed(current_time, borrow_index)?;
+
+        // The loan is liquidatable once debt / collateral value exceeds its liquidation threshold,
+        // or once it has expired
+        let expired = loan.is_expired(current_time, grace_period);
+        if !expired && !loan.is_liquidatable(debt, sol_price)? {
+            return Err(ErrorCode::LoanHealthy.into());
+        }
+
+        let (repaid, collateral_seized) = ctx.accounts.pda_account.liquidation_quote(
+            debt,
+            loan.collateral,
+            sol_price,
+            repay_amount,
+            expired,
+        )?;
+
+        // Transfer the repaid debt in USDC from the liquidator to the Shrub's USDC account
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: ctx.accounts.liquidator_usdc_account.to_account_info(),
+                    to: ctx.accounts.shrub_usdc_account.to_account_info(),
+                    authority: ctx.accounts.liquidator.to_account_info(),
+                },
+            ),
+            repaid,
+        )?;
+
+        // The loan closes once its debt is repaid, or once its collateral is gone, writing off
+        // whatever debt the collateral couldn't cover
+        let closed = repaid == debt || collateral_seized == loan.collateral;
+        let collateral_returned = if closed {
+            // Remove the loan from the borrower's index and close it, refunding rent to the borrower
+            ctx.accounts.borrower_index.close_loan(loan)?;
+            ctx.accounts
+                .pda_account
+                .adjust_total_principal(-(loan.principal as i128))?;
+            loan.collateral - collateral_seized
+        } else {
+            // Shrink the loan; accrued interest is paid off before principal
+            let principal_repaid = loan.apply_repayment(repaid)?;
+            loan.collateral -= collateral_seized;
+            ctx.accounts
+                .borrower_index
+                .adjust(-(principal_repaid as i128), -(collateral_seized as i128))?;
+            ctx.accounts
+                .pda_account
+                .adjust_total_principal(-(principal_repaid as i128))?;
+            0
+        };
+
+        // Release the collateral from the PDA to the liquidator and the borrower
+        ctx.accounts.pda_account.sub_lamports(collateral_seized + collateral_returned)?;
+        ctx.accounts.liquidator.add_lamports(collateral_seized)?;
+        ctx.accounts.borrower.add_lamports(collateral_returned)?;
+        ctx.accounts
+            .pda_account
+            .refresh_borrow_rate(&mut ctx.accounts.shrub_usdc_account)?;
+
+        let loan = &ctx.accounts.loan;
+        emit!(LoanLiquidated {
+            loan_id,
+            borrower: loan.borrower,
+            liquidator: ctx.accounts.liquidator.key(),
+            debt_repaid: repaid,
+            collateral_seized,
+            collateral_returned,
+            remaining_principal: if closed { 0 } else { loan.principal },
+            remaining_collateral: if closed { 0 } else { loan.collateral },
+            sol_price,
+        });
+
+        if closed {
+            emit!(LoanClosed {
+                loan: (**loan).clone(),
+                closed_at: current_time,
+            });
+            ctx.accounts.loan.close(ctx.accounts.borrower.to_account_info())?;
+        }
+
+        Ok(())
+    }
+
+    /// Starts a Dutch auction of an unhealthy or expired loan's collateral. Anyone can start an
+    /// auction in markets using auction liquidation; the debt and penalty are fixed at the start.
+    pub fn start_auction(ctx: Context<StartAuction>, loan_id: u64) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+        let market = &ctx.accounts.pda_account;
+        if market.liquidation_mode != LiquidationMode::DutchAuction {
+            return Err(ErrorCode::WrongLiquidationMode.into());
+        }
+        let sol_price = ctx.accounts.price.price(market)?.undisputed()?;
+        let grace_period = market.grace_period;
+        let borrow_index = ctx.accounts.pda_account.update_borrow_index(current_time)?;
+
+        let loan = &mut ctx.accounts.loan;
+        loan.accrue(current_time, borrow_index)?;
+        let debt = loan.total_owed(current_time, borrow_index)?;
+        if !loan.is_expired(current_time, grace_period) && !loan.is_liquidatable(debt, sol_price)? {
+            return Err(ErrorCode::LoanHealthy.into());
+        }
+        loan.in_auction = true;
+
+        let auction = &mut ctx.accounts.auction;
+        auction.open(
+            &ctx.accounts.pda_account,
+            loan,
+            ctx.accounts.starter.key(),
+            sol_price,
+            debt,
+            current_time,
+        )?;
+        auction.bump = ctx.bumps.auction;
+
+        emit!(AuctionStarted {
+            loan_id,
+            borrower: loan.borrower,
+            start_price: sol_price,
+            debt,
+            penalty: auction.penalty,
+            collateral: loan.collateral,
+            started_at: current_time,
+        });
+
+        Ok(())
+    }
+
+    /// Buys up to `collateral_amount` lamports of an auctioned loan's collateral at the
+    /// current auction price, capped at what the rest of the debt plus penalty needs. Once
+    /// the auction raises the debt plus penalty, or sells all of the collateral, it settles:
+    /// USDC above the debt plus penalty and any unsold collateral go back to the borrower,
+    /// and the loan and auction accounts are closed.
+    pub fn fill_auction(ctx: Context<FillAuction>, loan_id: u64, collateral_amount: u64) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+        ctx.accounts.pda_account.update_borrow_index(current_time)?;
+        let auction = &mut ctx.accounts.auction;
+
+        let price = auction.price(current_time);
+        // Sell no more collateral than the rest of the debt and penalty needs
+        let lamports = collateral_amount
+            .min(auction.collateral_remaining)
+            .min(auction.collateral_to_target(price)?);
+        let usdc_paid = Auction::cost(lamports, price)?;
+        if lamports == 0 || usdc_paid == 0 {
+            return Err(ErrorCode::InvalidLiquidationAmount.into());
+        }
+
+        // Transfer the USDC from the bidder to the Shrub's USDC account
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: ctx.accounts.bidder_usdc_account.to_account_info(),
+                    to: ctx.accounts.shrub_usdc_account.to_account_info(),
+                    authority: ctx.accounts.bidder.to_account_info(),
+                },
+            ),
+            usdc_paid,
+        )?;
+
+        auction.collateral_remaining -= lamports;
+        auction.usdc_raised = auction
+            .usdc_raised
+            .checked_add(usdc_paid)
+            .ok_or(ErrorCode::LiquidationCalculationFailed)?;
+
+        emit!(AuctionFilled {
+            loan_id,
+            bidder: ctx.accounts.bidder.key(),
+            collateral: lamports,
+            usdc_paid,
+            price,
+            discount_bps: auction.discount_bps(current_time),
+        });
+
+        // Settle once complete: return USDC above the debt plus penalty and unsold collateral
+        // to the borrower
+        let settled = auction.is_complete();
+        let surplus = if settled {
+            auction.usdc_raised.saturating_sub(auction.target())
+        } else {
+            0
+        };
+        if surplus > 0 {
+            let binding = ctx.accounts.admin.key();
+            let seeds = &[b"shrub", binding.as_ref(), &[ctx.accounts.pda_account.bump]];
+            let signer_seeds = &[&seeds[..]];
+            token::transfer(
+                CpiContext::new(
+                    ctx.accounts.token_program.to_account_info(),
+                    token::Transfer {
+                        from: ctx.accounts.shrub_usdc_account.to_account_info(),
+                        to: ctx.accounts.borrower_usdc_account.to_account_info(),
+                        authority: ctx.accounts.pda_account.to_account_info(),
+                    },
+                )
+                .with_signer(signer_seeds),
+                surplus,
+            )?;
+        }
+
+        // Release the collateral from the PDA to the bidder. Lamports move only after the last
+        // CPI, so its balance check sees the accounts as they were
+        ctx.accounts.pda_account.sub_lamports(lamports)?;
+        ctx.accounts.bidder.add_lamports(lamports)?;
+
+        if !settled {
+            return ctx
+                .accounts
+                .pda_account
+                .refresh_borrow_rate(&mut ctx.accounts.shrub_usdc_account);
+        }
+
+        let collateral_returned = ctx.accounts.auction.collateral_remaining;
+        ctx.accounts.pda_account.sub_lamports(collateral_returned)?;
+        ctx.accounts.borrower.add_lamports(collateral_returned)?;
+
+        let auction = &ctx.accounts.auction;
+        ctx.accounts.borrower_index.close_loan(&ctx.accounts.loan)?;
+        let market = &mut ctx.accounts.pda_account;
+        market.adjust_total_principal(-(ctx.accounts.loan.principal as i128))?;
+        market.refresh_borrow_rate(&mut ctx.accounts.shrub_usdc_account)?;
+
+        emit!(AuctionSettled {
+            loan_id,
+            borrower: auction.borrower,
+            usdc_raised: auction.usdc_raised,
+            debt: auction.debt,
+            penalty: auction.penalty,
+            surplus,
+            collateral_returned,
+        });
+        emit!(LoanClosed {
+            loan: (*ctx.accounts.loan).clone(),
+            closed_at: current_time,
+        });
+
+        // Close the loan to the borrower and the auction to whoever started it
+        ctx.accounts.loan.close(ctx.accounts.borrower.to_account_info())?;
+        ctx.accounts.auction.close(ctx.accounts.starter.to_account_info())?;
+
+        Ok(())
+    }
+
+    /// Restarts an auction whose discount has reached its maximum from a fresh oracle price, so
+    /// collateral left unsold isn't stuck at a stale price. Anyone can restart an auction; the
+    /// debt, penalty and what has been sold so far carry over.
+    pub fn restart_auction(ctx: Context<RestartAuction>, loan_id: u64) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+        if !ctx.accounts.auction.has_ended(current_time) {
+            return Err(ErrorCode::AuctionNotEnded.into());
+        }
+        let sol_price = ctx.accounts.price.price(&ctx.accounts.pda_account)?.undisputed()?;
+
+        let auction = &mut ctx.accounts.auction;
+        auction.restart(&ctx.accounts.pda_account, sol_price, current_time);
+
+        emit!(AuctionRestarted {
+            loan_id,
+            borrower: auction.borrower,
+            start_price: sol_price,
+            collateral_remaining: auction.collateral_remaining,
+            usdc_raised: auction.usdc_raised,
+            started_at: current_time,
+        });
+
+        Ok(())
+    }
+
+    /// Puts the collateral of a loan past maturity and the grace period up for a Dutch auction,
+    /// whatever the market's liquidation mode, so nobody has to liquidate it at a loss. The
+    /// auction settles through `fill_auction`, repaying the market and returning what is left
+    /// to the borrower. Anyone can expire a loan, paying for the auction account and
+    /// reclaiming it once the auction settles.
+    pub fn expire_loan(ctx: Context<ExpireLoan>, loan_id: u64) -> Result<()> {
+        let current_time = Clock::get()?.unix_timestamp;
+        if !ctx
+            .accounts
+            .loan
+            .is_expired(current_time, ctx.accounts.pda_account.grace_period)
+        {
+            return Err(ErrorCode::LoanNotExpired.into());
+        }
+        let sol_price = ctx.accounts.price.price(&ctx.accounts.pda_account)?.undisputed()?;
+        let borrow_index = ctx.accounts.pda_account.update_borrow_index(current_time)?;
+
+        let loan = &mut ctx.accounts.loan;
+        loan.accrue(current_time, borrow_index)?;
+        let debt = loan.total_owed(current_time, borrow_index)?;
+        loan.in_auction = true;
+
+        let auction = &mut ctx.accounts.auction;
+        auction.open(
+            &ctx.accounts.pda_account,
+            loan,
+            ctx.accounts.starter.key(),
+            sol_price,
+            debt,
+            current_time,
+        )?;
+        auction.bump = ctx.bumps.auction;
+
+        emit!(LoanExpired {
+            loan_id,
+            borrower: loan.borrower,
+            matures_at: loan.matures_at,
+            debt,
+            collateral_auctioned: loan.collateral,
+            expired_at: current_time,
+        });
+        emit!(AuctionStarted {
+            loan_id,
+            borrower: loan.borrower,
+            start_price: sol_price,
+            debt,
+            penalty: auction.penalty,
+            collateral: loan.collateral,
+            started_at: current_time,
+        });
+
+        Ok(())
+    }
+
+    /// Allows the admin to deposit USDC into the shrub's USDC account.
+    pub fn deposit_usdc(ctx: Context<DepositUsdc>, amount: u64) -> Result<()> {
+        msg!("Starting deposit_usdc instruction");
+        ctx.accounts
+            .pda_account
+            .update_borrow_index(Clock::get()?.unix_timestamp)?;
+
+        token::transfer(
+            CpiContext::new(
+                ctx.accounts.token_program.to_account_info(),
+                token::Transfer {
+                    from: ctx.accounts.admin_usdc_account.to_account_info(),
+                    to: ctx.accounts.shrub_usdc_account.to_account_info(),
+                    authority: ctx.accounts.admin.to_account_info(),
+                },
+            ),
+            amount,
+        )?;
+        ctx.accounts
+            .pda_account
+            .refresh_borrow_rate(&mut ctx.accounts.shrub_usdc_account)?;
+
+        msg!("Admin deposited {} USDC to Shrub's account", amount);
+        Ok(())
+    }
+
+    /// Returns the collateral, in lamports, required to borrow `principal` at `apy` at the
+    /// current price. Read-only: simulate it and read the result from the return data.
+    pub fn quote_loan(ctx: Context<QuoteLoan>, principal: u64, apy: u16) -> Result<u64> {
+        let tier = ctx.accounts.market_config.tier(apy).ok_or(ErrorCode::InvalidAPY)?;
+        let sol_price = ctx.accounts.price.price(&ctx.accounts.pda_account)?.price;
+        required_collateral(principal, tier.ltv, sol_price)
+    }
+
+    /// Returns the largest principal, in micro-USDC, that `collateral` lamports can secure at
+    /// `apy` at the current price. Read-only: simulate it and read the result from the return data.
+    pub fn max_borrow(ctx: Context<QuoteLoan>, collateral: u64, apy: u16) -> Result<u64> {
+        let tier = ctx.accounts.market_config.tier(apy).ok_or(ErrorCode::InvalidAPY)?;
+        let sol_price = ctx.accounts.price.price(&ctx.accounts.pda_account)?.price;
+        Ok(radar_math::max_principal(collateral, tier.ltv, sol_price, Rounding::Down))
+    }
+
+    /// Returns a loan's current debt, collateral value, health factor and liquidation price.
+    /// Read-only: simulate it and read the result from the return data.
+    pub fn loan_health(ctx: Context<CheckLoanHealth>, loan_id: u64) -> Result<LoanHealth> {
+        let current_time = Clock::get()?.unix_timestamp;
+        let sol_price = ctx.accounts.price.price(&ctx.accounts.pda_account)?.price;
+        let borrow_index = ctx.accounts.pda_account.borrow_index_at(current_time)?;
+        let health = ctx.accounts.loan.health(current_time, borrow_index, sol_price)?;
+        msg!("Loan {} health factor (bps): {}", loan_id, health.health_factor_bps);
+        Ok(health)
+    }
+}
+
+/// Ensures a tier's LTV is positive and strictly below its liquidation threshold.
+fn validate_tier(ltv: u16, liquidation_threshold: u16) -> Result<()> {
+    if ltv == 0 || ltv >= liquidation_threshold || liquidation_threshold > 10_000 {
+        return Err(ErrorCode::InvalidTier.into());
+    }
+    Ok(())
+}
+
+/// Builds an oracle configuration from the accounts passed to `set_oracle`.
+fn oracle_config(accounts: &SetOracle, source: OracleSource, max_price_age: u32) -> Result<OracleConfig> {
+    let program = accounts.oracle_program.key();
+
+    // Pyth prices must come from the receiver program, mock prices from this program
+    let valid_program = match source {
+        OracleSource::Chainlink => true,
+        OracleSource::Pyth => program == PYTH_RECEIVER_PROGRAM_ID,
+        OracleSource::Mock => program == crate::ID,
+    };
+    if !valid_program {
+        return Err(ErrorCode::InvalidPriceFeed.into());
+    }
+
+    Ok(OracleConfig {
+        source,
+        program,
+        feed: accounts.price_feed.key(),
+        max_price_age,
+    })
+}
+
+#[derive(Accounts)]
+pub struct Initialize<'info> {
+    /// The admin who initializes the PDA.
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    /// The PDA account to be initialized.
+    #[account(
+        init,
+        seeds = [b"shrub", admin.key().as_ref()],
+        bump,
+        payer = admin,
+        space = 8 + DataAccount::INIT_SPACE,
+    )]
+    pub pda_account: Account<'info, DataAccount>,
+
+    /// The market config holding the APY/LTV tier table.
+    #[account(
+        init,
+        seeds = [b"market_config", pda_account.key().as_ref()],
+        bump,
+        payer = admin,
+        space = 8 + MarketConfig::INIT_SPACE,
+    )]
+    pub market_config: Account<'info, MarketConfig>,
+
+    /// The Shrub PDA's associated USDC token account.
+    #[account(
+        init_if_needed,
+        payer = admin,
+        associated_token::mint = usdc_mint,
+        associated_token::authority = pda_account,
+    )]
+    pub shrub_usdc_account: Account<'info, TokenAccount>,
+
+    /// The USDC mint.
+    pub usdc_mint: Account<'info, Mint>,
+
+    /// System program.
+    pub system_program: Program<'info, System>,
+
+    /// Token program.
+    pub token_program: Program<'info, Token>,
+
+    /// Associated token program.
+    pub associated_token_program: Program<'info, AssociatedToken>,
+
+    /// Rent sysvar.
+    pub rent: Sysvar<'info, Rent>,
+}
+
+#[derive(Accounts)]
+pub struct ManageTiers<'info> {
+    /// The admin managing the tiers.
+    pub admin: Signer<'info>,
+
+    /// The PDA account.
+    #[account(
+        has_one = admin,
+        seeds = [b"shrub", admin.key().as_ref()],
+        bump = pda_account.bump
+    )]
+    pub pda_account: Account<'info, DataAccount>,
+
+    /// The market config holding the tier table.
+    #[account(
+        mut,
+        seeds = [b"market_config", pda_account.key().as_ref()],
+        bump = market_config.bump
+    )]
+    pub market_config: Account<'info, MarketConfig>,
+}
+
+#[derive(Accounts)]
+pub struct SetOracle<'info> {
+    /// The admin configuring the oracle.
+    pub admin: Signer<'info>,
+
+    /// The PDA account.
+    #[account(
+        mut,
+        has_one = admin,
+        seeds = [b"shrub", admin.key().as_ref()],
+        bump = pda_account.bump
+    )]
+    pub pda_account: Account<'info, DataAccount>,
+
+    /// The program that owns or serves the price feed.
+    /// CHECK: Only its key is stored; it is validated against the source in the handler.
+    #[account(executable)]
+    pub oracle_program: AccountInfo<'info>,
+
+    /// The price feed account.
+    /// CHECK: Only its key is stored; its contents are validated on every read.
+    #[account(owner = oracle_program.key())]
+    pub price_feed: AccountInfo<'info>,
+}
+
+#[derive(Accounts)]
+pub struct RemoveSecondaryOracle<'info> {
+    /// The admin removing the oracle.
+    pub admin: Signer<'info>,
+
+    /// The PDA account.
+    #[account(
+        mut,
+        has_one = admin,
+        seeds = [b"shrub", admin.key().as_ref()],
+        bump = pda_account.bump
+    )]
+    pub pda_account: Account<'info, DataAccount>,
+}
+
+#[derive(Accounts)]
+pub struct SetPriceGuard<'info> {
+    /// The admin configuring the price guard.
+    pub admin: Signer<'info>,
+
+    /// The PDA account.
+    #[account(
+        mut,
+        has_one = admin,
+        seeds = [b"shrub", admin.key().as_ref()],
+        bump = pda_account.bump
+    )]
+    pub pda_account: Account<'info, DataAccount>,
+}
+
+#[derive(Accounts)]
+pub struct SetLiquidationBonus<'info> {
+    /// The admin configuring the liquidation bonus.
+    pub admin: Signer<'info>,
+
+    /// The PDA account.
+    #[account(
+        mut,
+        has_one = admin,
+        seeds = [b"shrub", admin.key().as_ref()],
+        bump = pda_account.bump
+    )]
+    pub pda_account: Account<'info, DataAccount>,
+}
+
+#[derive(Accounts)]
+pub struct SetCloseFactor<'info> {
+    /// The admin configuring the close factor.
+    pub admin: Signer<'info>,
+
+    /// The PDA account.
+    #[account(
+        mut,
+        has_one = admin,
+        seeds = [b"shrub", admin.key().as_ref()],
+        bump = pda_account.bump
+    )]
+    pub pda_account: Account<'info, DataAccount>,
+}
+
+#[derive(Accounts)]
+pub struct SetGracePeriod<'info> {
+    /// The admin configuring the grace period.
+    pub admin: Signer<'info>,
+
+    /// The PDA account.
+    #[account(
+        mut,
+        has_one = admin,
+        seeds = [b"shrub", admin.key().as_ref()],
+        bump = pda_account.bump
+    )]
+    pub pda_account: Account<'info, DataAccount>,
+}
+
+#[derive(Accounts)]
+pub struct SetRateModel<'info> {
+    /// The admin configuring the rate model.
+    pub admin: Signer<'info>,
+
+    /// The PDA account.
+    #[account(
+        mut,
+        has_one = admin,
+        seeds = [b"shrub", admin.key().as_ref()],
+        bump = pda_account.bump
+    )]
+    pub pda_account: Account<'info, DataAccount>,
+
+    /// The Shrub PDA's associated USDC token account, whose balance sets the utilization.
+    #[account(
+        associated_token::mint = pda_account.usdc_mint,
+        associated_token::authority = pda_account
+    )]
+    pub shrub_usdc_account: Account<'info, TokenAccount>,
+}
+
+#[derive(Accounts)]
+pub struct SetLiquidationMode<'info> {
+    /// The admin configuring the liquidation mode.
+    pub admin: Signer<'info>,
+
+    /// The PDA account.
+    #[account(
+        mut,
+        has_one = admin,
+        seeds = [b"shrub", admin.key().as_ref()],
+        bump = pda_account.bump
+    )]
+    pub pda_account: Account<'info, DataAccount>,
+}
+
+#[derive(Accounts)]
+pub struct SetPriceMode<'info> {
+    /// The admin configuring the price mode.
+    pub admin: Signer<'info>,
+
+    /// The PDA account.
+    #[account(
+        mut,
+        has_one = admin,
+        seeds = [b"shrub", admin.key().as_ref()],
+        bump = pda_account.bump
+    )]
+    pub pda_account: Account<'info, DataAccount>,
+}
+
+/// Oracle accounts that price a market's collateral.
+///
+/// The accounts are checked against the market's oracle configuration when the price is read,
+/// since a composite cannot constrain them against the parent's market account.
+#[derive(Accounts)]
+pub struct PriceAccounts<'info> {
+    /// The program that owns or serves the price feed.
+    /// CHECK: Checked against the oracle configured on the market when read.
+    pub oracle_program: AccountInfo<'info>,
+
+    /// The SOL/USD price feed.
+    /// CHECK: Checked against the oracle configured on the market when read.
+    pub price_feed: AccountInfo<'info>,
+
+    /// The program that owns or serves the secondary price feed, if one is configured.
+    /// CHECK: Checked against the secondary oracle configured on the market when read.
+    pub secondary_oracle_program: Option<AccountInfo<'info>>,
+
+    /// The secondary SOL/USD price feed, if one is configured.
+    /// CHECK: Checked against the secondary oracle configured on the market when read.
+    pub secondary_price_feed: Option<AccountInfo<'info>>,
+
+    /// The market's price history, required when pricing collateral with the TWAP.
+    pub price_history: Option<Account<'info, PriceHistory>>,
+}
+
+impl<'info> PriceAccounts<'info> {
+    /// Reads the SOL price and applies `market`'s price mode, giving the price used
+    /// to value collateral along with whether the oracles disagreed.
+    pub fn price(&self, market: &Account<'info, DataAccount>) -> Result<Price> {
+        let price_history = self.price_history.as_deref();
+        if price_history.is_some_and(|history| history.market != market.key()) {
+            return Err(ErrorCode::InvalidPriceHistory.into());
+        }
+
+        let spot = market.read_sol_price(
+            &self.oracle_program,
+            &self.price_feed,
+            self.secondary_oracle_program.as_ref(),
+            self.secondary_price_feed.as_ref(),
+        )?;
+        let now = Clock::get()?.unix_timestamp;
+        Ok(Price {
+            price: market.price_mode.select(spot.price, price_history, now, market.twap_window)?,
+            ..spot
+        })
+    }
+}
+
+#[derive(Accounts)]
+pub struct CrankPrice<'info> {
+    /// Anyone cranking the price; pays for the price history on first use.
+    #[account(mut)]
+    pub cranker: Signer<'info>,
+
+    /// The PDA account.
+    #[account(
+        has_one = admin,
+        seeds = [b"shrub", admin.key().as_ref()],
+        bump = pda_account.bump
+    )]
+    pub pda_account: Account<'info, DataAccount>,
+
+    /// The admin account (used for deriving PDA).
+    /// CHECK: This is not used for data validation; it is only used for PDA derivation.
+    pub admin: AccountInfo<'info>,
+
+    /// The market's price history.
+    #[account(
+        init_if_needed,
+        payer = cranker,
+        space = 8 + PriceHistory::INIT_SPACE,
+        seeds = [b"price_history", pda_account.key().as_ref()],
+        bump
+    )]
+    pub price_history: Account<'info, PriceHistory>,
+
+    /// The program that owns or serves the price feed.
+    /// CHECK: Must match the oracle configured on the PDA account.
+    #[account(address = pda_account.oracle.program)]
+    pub oracle_program: AccountInfo<'info>,
+
+    /// The SOL/USD price feed.
+    /// CHECK: Must match the oracle configured on the PDA account.
+    #[account(address = pda_account.oracle.feed)]
+    pub price_feed: AccountInfo<'info>,
+
+    /// The program that owns or serves the secondary price feed, if one is configured.
+    /// CHECK: Must match the secondary oracle configured on the PDA account.
+    #[account(address = pda_account.secondary_oracle.program)]
+    pub secondary_oracle_program: Option<AccountInfo<'info>>,
+
+    /// The secondary SOL/USD price feed, if one is configured.
+    /// CHECK: Must match the secondary oracle configured on the PDA account.
+    #[account(address = pda_account.secondary_oracle.feed)]
+    pub secondary_price_feed: Option<AccountInfo<'info>>,
+
+    /// System program.
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct SetMockPrice<'info> {
+    /// The admin publishing the price.
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    /// The PDA account.
+    #[account(
+        has_one = admin,
+        seeds = [b"shrub", admin.key().as_ref()],
+        bump = pda_account.bump
+    )]
+    pub pda_account: Account<'info, DataAccount>,
+
+    /// The market's mock price account.
+    #[account(
+        init_if_needed,
+        payer = admin,
+        space = 8 + MockPrice::INIT_SPACE,
+        seeds = [b"mock_price", pda_account.key().as_ref()],
+        bump
+    )]
+    pub mock_price: Account<'info, MockPrice>,
+
+    /// System program.
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct TakeLoan<'info> {
+    /// The PDA account.
+    #[account(
+        mut,
+        has_one = admin,
+        seeds = [b"shrub", admin.key().as_ref()],
+        bump = pda_account.bump
+    )]
+    pub pda_account: Account<'info, DataAccount>,
+
+    /// The market config holding the tier table.
+    #[account(
+        seeds = [b"market_config", pda_account.key().as_ref()],
+        bump = market_config.bump
+    )]
+    pub market_config: Account<'info, MarketConfig>,
+
+    /// The admin account (used for deriving PDA).
+    /// CHECK: This is not used for data validation; it is only used for PDA derivation.
+    pub admin: AccountInfo<'info>,
+
+    /// The user taking the loan.
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// The loan account being created. `init_if_needed` lets the handler reject a
+    /// reused id with `LoanIdReused` instead of a generic allocation failure.
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + Loan::INIT_SPACE,
+        seeds = [b"loan", pda_account.key().as_ref(), pda_account.next_loan_id.to_le_bytes().as_ref()],
+        bump
+    )]
+    pub loan: Account<'info, Loan>,
+
+    /// The borrower's position index.
+    #[account(
+        init_if_needed,
+        payer = user,
+        space = 8 + BorrowerIndex::INIT_SPACE,
+        seeds = [b"borrower", pda_account.key().as_ref(), user.key().as_ref()],
+        bump
+    )]
+    pub borrower_index: Account<'info, BorrowerIndex>,
+
+    /// The oracle accounts that price the collateral.
+    pub price: PriceAccounts<'info>,
+
+    /// The user's associated USDC token account.
+    #[account(mut, token::mint = pda_account.usdc_mint)]
+    pub user_usdc_account: Account<'info, TokenAccount>,
+
+    /// The Shrub PDA's associated USDC token account.
+    #[account(
+        mut,
+        associated_token::mint = pda_account.usdc_mint,
+        associated_token::authority = pda_account
+    )]
+    pub shrub_usdc_account: Account<'info, TokenAccount>,
+
+    /// The USDC mint.
+    #[account(address = pda_account.usdc_mint)]
+    pub usdc_mint: Account<'info, Mint>,
+
+    /// System program.
+    pub system_program: Program<'info, System>,
+
+    /// Token program.
+    pub token_program: Program<'info, Token>,
+
+    /// Associated token program.
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+#[instruction(loan_id: u64)]
+pub struct RepayLoan<'info> {
+    /// The PDA account.
+    #[account(
+        mut,
+        has_one = admin,
+        seeds = [b"shrub", admin.key().as_ref()],
+        bump = pda_account.bump
+    )]
+    pub pda_account: Account<'info, DataAccount>,
+
+    /// The admin account (used for deriving PDA).
+    /// CHECK: This is not used for data validation; it is only used for PDA derivation.
+    pub admin: AccountInfo<'info>,
+
+    /// The user repaying the loan.
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// The loan being repaid. Closed on repayment, refunding its rent to the borrower.
+    #[account(
+        mut,
+        close = user,
+        seeds = [b"loan", pda_account.key().as_ref(), loan_id.to_le_bytes().as_ref()],
+        bump = loan.bump
+    )]
+    pub loan: Account<'info, Loan>,
+
+    /// The borrower's position index.
+    #[account(
+        mut,
+        seeds = [b"borrower", pda_account.key().as_ref(), loan.borrower.as_ref()],
+        bump = borrower_index.bump
+    )]
+    pub borrower_index: Account<'info, BorrowerIndex>,
+
+    /// The user's associated USDC token account.
+    #[account(mut, token::mint = pda_account.usdc_mint)]
+    pub user_usdc_account: Account<'info, TokenAccount>,
+
+    /// The Shrub PDA's associated USDC token account.
+    #[account(
+        mut,
+        associated_token::mint = pda_account.usdc_mint,
+        associated_token::authority = pda_account
+    )]
+    pub shrub_usdc_account: Account<'info, TokenAccount>,
+
+    /// The USDC mint.
+    #[account(address = pda_account.usdc_mint)]
+    pub usdc_mint: Account<'info, Mint>,
+
+    /// System program.
+    pub system_program: Program<'info, System>,
+
+    /// Token program.
+    pub token_program: Program<'info, Token>,
+
+    /// Associated token program.
+    pub associated_token_program: Program<'info, AssociatedToken>,
+}
+
+#[derive(Accounts)]
+#[instruction(loan_id: u64)]
+pub struct RepayPartial<'info> {
+    /// The PDA account.
+    #[account(
+        mut,
+        has_one = admin,
+        seeds = [b"shrub", admin.key().as_ref()],
+        bump = pda_account.bump
+    )]
+    pub pda_account: Account<'info, DataAccount>,
+
+    /// The admin account (used for deriving PDA).
+    /// CHECK: This is not used for data validation; it is only used for PDA derivation.
+    pub admin: AccountInfo<'info>,
+
+    /// The borrower repaying part of the loan.
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// The loan being repaid.
+    #[account(
+        mut,
+        seeds = [b"loan", pda_account.key().as_ref(), loan_id.to_le_bytes().as_ref()],
+        bump = loan.bump
+    )]
+    pub loan: Account<'info, Loan>,
+
+    /// The borrower's position index.
+    #[account(
+        mut,
+        seeds = [b"borrower", pda_account.key().as_ref(), loan.borrower.as_ref()],
+        bump = borrower_index.bump
+    )]
+    pub borrower_index: Account<'info, BorrowerIndex>,
+
+    /// The oracle accounts that price the collateral.
+    pub price: PriceAccounts<'info>,
+
+    /// The user's associated USDC token account.
+    #[account(mut, token::mint = pda_account.usdc_mint)]
+    pub user_usdc_account: Account<'info, TokenAccount>,
+
+    /// The Shrub PDA's associated USDC token account.
+    #[account(
+        mut,
+        associated_token::mint = pda_account.usdc_mint,
+        associated_token::authority = pda_account
+    )]
+    pub shrub_usdc_account: Account<'info, TokenAccount>,
+
+    /// Token program.
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(loan_id: u64)]
+pub struct AddCollateral<'info> {
+    /// The PDA account holding the collateral.
+    #[account(
+        mut,
+        has_one = admin,
+        seeds = [b"shrub", admin.key().as_ref()],
+        bump = pda_account.bump
+    )]
+    pub pda_account: Account<'info, DataAccount>,
+
+    /// The admin account (used for deriving PDA).
+    /// CHECK: This is not used for data validation; it is only used for PDA derivation.
+    pub admin: AccountInfo<'info>,
+
+    /// Whoever is adding the collateral.
+    #[account(mut)]
+    pub depositor: Signer<'info>,
+
+    /// The loan being topped up.
+    #[account(
+        mut,
+        seeds = [b"loan", pda_account.key().as_ref(), loan_id.to_le_bytes().as_ref()],
+        bump = loan.bump
+    )]
+    pub loan: Account<'info, Loan>,
+
+    /// The borrower's position index.
+    #[account(
+        mut,
+        seeds = [b"borrower", pda_account.key().as_ref(), loan.borrower.as_ref()],
+        bump = borrower_index.bump
+    )]
+    pub borrower_index: Account<'info, BorrowerIndex>,
+
+    /// System program.
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(loan_id: u64)]
+pub struct WithdrawCollateral<'info> {
+    /// The PDA account holding the collateral.
+    #[account(
+        mut,
+        has_one = admin,
+        seeds = [b"shrub", admin.key().as_ref()],
+        bump = pda_account.bump
+    )]
+    pub pda_account: Account<'info, DataAccount>,
+
+    /// The admin account (used for deriving PDA).
+    /// CHECK: This is not used for data validation; it is only used for PDA derivation.
+    pub admin: AccountInfo<'info>,
+
+    /// The borrower withdrawing collateral.
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// The loan the collateral is withdrawn from.
+    #[account(
+        mut,
+        seeds = [b"loan", pda_account.key().as_ref(), loan_id.to_le_bytes().as_ref()],
+        bump = loan.bump
+    )]
+    pub loan: Account<'info, Loan>,
+
+    /// The borrower's position index.
+    #[account(
+        mut,
+        seeds = [b"borrower", pda_account.key().as_ref(), loan.borrower.as_ref()],
+        bump = borrower_index.bump
+    )]
+    pub borrower_index: Account<'info, BorrowerIndex>,
+
+    /// The oracle accounts that price the collateral.
+    pub price: PriceAccounts<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(loan_id: u64)]
+pub struct IncreasePrincipal<'info> {
+    /// The PDA account.
+    #[account(
+        mut,
+        has_one = admin,
+        seeds = [b"shrub", admin.key().as_ref()],
+        bump = pda_account.bump
+    )]
+    pub pda_account: Account<'info, DataAccount>,
+
+    /// The admin account (used for deriving PDA).
+    /// CHECK: This is not used for data validation; it is only used for PDA derivation.
+    pub admin: AccountInfo<'info>,
+
+    /// The borrower borrowing more.
+    pub user: Signer<'info>,
+
+    /// The loan being increased.
+    #[account(
+        mut,
+        seeds = [b"loan", pda_account.key().as_ref(), loan_id.to_le_bytes().as_ref()],
+        bump = loan.bump
+    )]
+    pub loan: Account<'info, Loan>,
+
+    /// The borrower's position index.
+    #[account(
+        mut,
+        seeds = [b"borrower", pda_account.key().as_ref(), loan.borrower.as_ref()],
+        bump = borrower_index.bump
+    )]
+    pub borrower_index: Account<'info, BorrowerIndex>,
+
+    /// The market config holding the tier table.
+    #[account(
+        seeds = [b"market_config", pda_account.key().as_ref()],
+        bump = market_config.bump
+    )]
+    pub market_config: Account<'info, MarketConfig>,
+
+    /// The oracle accounts that price the collateral.
+    pub price: PriceAccounts<'info>,
+
+    /// The user's associated USDC token account.
+    #[account(mut, token::mint = pda_account.usdc_mint)]
+    pub user_usdc_account: Account<'info, TokenAccount>,
+
+    /// The Shrub PDA's associated USDC token account.
+    #[account(
+        mut,
+        associated_token::mint = pda_account.usdc_mint,
+        associated_token::authority = pda_account
+    )]
+    pub shrub_usdc_account: Account<'info, TokenAccount>,
+
+    /// Token program.
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(loan_id: u64)]
+pub struct RefinanceLoan<'info> {
+    /// The PDA account holding the collateral.
+    #[account(
+        mut,
+        has_one = admin,
+        seeds = [b"shrub", admin.key().as_ref()],
+        bump = pda_account.bump
+    )]
+    pub pda_account: Account<'info, DataAccount>,
+
+    /// The admin account (used for deriving PDA).
+    /// CHECK: This is not used for data validation; it is only used for PDA derivation.
+    pub admin: AccountInfo<'info>,
+
+    /// The borrower refinancing the loan.
+    #[account(mut)]
+    pub user: Signer<'info>,
+
+    /// The loan being refinanced.
+    #[account(
+        mut,
+        seeds = [b"loan", pda_account.key().as_ref(), loan_id.to_le_bytes().as_ref()],
+        bump = loan.bump
+    )]
+    pub loan: Account<'info, Loan>,
+
+    /// The borrower's position index.
+    #[account(
+        mut,
+        seeds = [b"borrower", pda_account.key().as_ref(), loan.borrower.as_ref()],
+        bump = borrower_index.bump
+    )]
+    pub borrower_index: Account<'info, BorrowerIndex>,
+
+    /// The market config holding the tier table.
+    #[account(
+        seeds = [b"market_config", pda_account.key().as_ref()],
+        bump = market_config.bump
+    )]
+    pub market_config: Account<'info, MarketConfig>,
+
+    /// The oracle accounts that price the collateral.
+    pub price: PriceAccounts<'info>,
+
+    /// System program.
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(loan_id: u64)]
+pub struct ExtendLoan<'info> {
+    /// The PDA account.
+    #[account(
+        mut,
+        has_one = admin,
+        seeds = [b"shrub", admin.key().as_ref()],
+        bump = pda_account.bump
+    )]
+    pub pda_account: Account<'info, DataAccount>,
+
+    /// The admin account (used for deriving PDA).
+    /// CHECK: This is not used for data validation; it is only used for PDA derivation.
+    pub admin: AccountInfo<'info>,
+
+    /// The borrower extending the loan.
+    pub user: Signer<'info>,
+
+    /// The loan being extended.
+    #[account(
+        mut,
+        seeds = [b"loan", pda_account.key().as_ref(), loan_id.to_le_bytes().as_ref()],
+        bump = loan.bump
+    )]
+    pub loan: Account<'info, Loan>,
+
+    /// The borrower's position index.
+    #[account(
+        mut,
+        seeds = [b"borrower", pda_account.key().as_ref(), loan.borrower.as_ref()],
+        bump = borrower_index.bump
+    )]
+    pub borrower_index: Account<'info, BorrowerIndex>,
+
+    /// The market config holding the tier table.
+    #[account(
+        seeds = [b"market_config", pda_account.key().as_ref()],
+        bump = market_config.bump
+    )]
+    pub market_config: Account<'info, MarketConfig>,
+
+    /// The oracle accounts that price the collateral.
+    pub price: PriceAccounts<'info>,
+
+    /// The Shrub PDA's associated USDC token account, whose balance sets the utilization.
+    #[account(
+        associated_token::mint = pda_account.usdc_mint,
+        associated_token::authority = pda_account
+    )]
+    pub shrub_usdc_account: Account<'info, TokenAccount>,
+}
+
+#[derive(Accounts)]
+#[instruction(loan_id: u64)]
+pub struct LiquidateLoan<'info> {
+    /// The PDA account.
+    #[account(
+        mut,
+        has_one = admin,
+        seeds = [b"shrub", admin.key().as_ref()],
+        bump = pda_account.bump
+    )]
+    pub pda_account: Account<'info, DataAccount>,
+
+    /// The admin account (used for deriving PDA).
+    /// CHECK: This is not used for data validation; it is only used for PDA derivation.
+    pub admin: AccountInfo<'info>,
+
+    /// The account liquidating the loan.
+    #[account(mut)]
+    pub liquidator: Signer<'info>,
+
+    /// The loan being liquidated. Closed once fully liquidated, refunding its rent to the borrower.
+    #[account(
+        mut,
+        seeds = [b"loan", pda_account.key().as_ref(), loan_id.to_le_bytes().as_ref()],
+        bump = loan.bump
+    )]
+    pub loan: Account<'info, Loan>,
+
+    /// The borrower, who receives any collateral left after liquidation.
+    /// CHECK: Must be the loan's borrower.
+    #[account(mut, address = loan.borrower)]
+    pub borrower: AccountInfo<'info>,
+
+    /// The borrower's position index.
+    #[account(
+        mut,
+        seeds = [b"borrower", pda_account.key().as_ref(), loan.borrower.as_ref()],
+        bump = borrower_index.bump
+    )]
+    pub borrower_index: Account<'info, BorrowerIndex>,
+
+    /// The oracle accounts that price the collateral.
+    pub price: PriceAccounts<'info>,
+
+    /// The liquidator's USDC token account.
+    #[account(mut, token::mint = pda_account.usdc_mint)]
+    pub liquidator_usdc_account: Account<'info, TokenAccount>,
+
+    /// The Shrub PDA's associated USDC token account.
+    #[account(
+        mut,
+        associated_token::mint = pda_account.usdc_mint,
+        associated_token::authority = pda_account
+    )]
+    pub shrub_usdc_account: Account<'info, TokenAccount>,
+
+    /// Token program.
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(loan_id: u64)]
+pub struct StartAuction<'info> {
+    /// The PDA account.
+    #[account(
+        mut,
+        has_one = admin,
+        seeds = [b"shrub", admin.key().as_ref()],
+        bump = pda_account.bump
+    )]
+    pub pda_account: Account<'info, DataAccount>,
+
+    /// The admin account (used for deriving PDA).
+    /// CHECK: This is not used for data validation; it is only used for PDA derivation.
+    pub admin: AccountInfo<'info>,
+
+    /// The account starting the auction, which pays for and later reclaims the auction account.
+    #[account(mut)]
+    pub starter: Signer<'info>,
+
+    /// The loan being auctioned.
+    #[account(
+        mut,
+        seeds = [b"loan", pda_account.key().as_ref(), loan_id.to_le_bytes().as_ref()],
+        bump = loan.bump
+    )]
+    pub loan: Account<'info, Loan>,
+
+    /// The auction account being created.
+    #[account(
+        init,
+        payer = starter,
+        space = 8 + Auction::INIT_SPACE,
+        seeds = [b"auction", loan.key().as_ref()],
+        bump
+    )]
+    pub auction: Account<'info, Auction>,
+
+    /// The oracle accounts that price the collateral.
+    pub price: PriceAccounts<'info>,
+
+    /// System program.
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+#[instruction(loan_id: u64)]
+pub struct RestartAuction<'info> {
+    /// The PDA account.
+    #[account(
+        has_one = admin,
+        seeds = [b"shrub", admin.key().as_ref()],
+        bump = pda_account.bump
+    )]
+    pub pda_account: Account<'info, DataAccount>,
+
+    /// The admin account (used for deriving PDA).
+    /// CHECK: This is not used for data validation; it is only used for PDA derivation.
+    pub admin: AccountInfo<'info>,
+
+    /// The loan being auctioned.
+    #[account(
+        seeds = [b"loan", pda_account.key().as_ref(), loan_id.to_le_bytes().as_ref()],
+        bump = loan.bump
+    )]
+    pub loan: Account<'info, Loan>,
+
+    /// The auction being restarted.
+    #[account(
+        mut,
+        seeds = [b"auction", loan.key().as_ref()],
+        bump = auction.bump
+    )]
+    pub auction: Account<'info, Auction>,
+
+    /// The oracle accounts that price the collateral.
+    pub price: PriceAccounts<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(loan_id: u64)]
+pub struct FillAuction<'info> {
+    /// The PDA account.
+    #[account(
+        mut,
+        has_one = admin,
+        seeds = [b"shrub", admin.key().as_ref()],
+        bump = pda_account.bump
+    )]
+    pub pda_account: Account<'info, DataAccount>,
+
+    /// The admin account (used for deriving PDA).
+    /// CHECK: This is not used for data validation; it is only used for PDA derivation.
+    pub admin: AccountInfo<'info>,
+
+    /// The account buying collateral.
+    #[account(mut)]
+    pub bidder: Signer<'info>,
+
+    /// The loan being auctioned. Closed when the auction settles, refunding its rent to the borrower.
+    #[account(
+        mut,
+        seeds = [b"loan", pda_account.key().as_ref(), loan_id.to_le_bytes().as_ref()],
+        bump = loan.bump
+    )]
+    pub loan: Account<'info, Loan>,
+
+    /// The loan's auction. Closed when the auction settles, refunding its rent to the starter.
+    #[account(
+        mut,
+        seeds = [b"auction", loan.key().as_ref()],
+        bump = auction.bump
+    )]
+    pub auction: Account<'info, Auction>,
+
+    /// The borrower, who receives unsold collateral when the auction settles.
+    /// CHECK: Must be the loan's borrower.
+    #[account(mut, address = loan.borrower)]
+    pub borrower: AccountInfo<'info>,
+
+    /// The account that started the auction.
+    /// CHECK: Must be the auction's starter.
+    #[account(mut, address = auction.starter)]
+    pub starter: AccountInfo<'info>,
+
+    /// The borrower's position index.
+    #[account(
+        mut,
+        seeds = [b"borrower", pda_account.key().as_ref(), loan.borrower.as_ref()],
+        bump = borrower_index.bump
+    )]
+    pub borrower_index: Account<'info, BorrowerIndex>,
+
+    /// The bidder's USDC token account.
+    #[account(mut, token::mint = pda_account.usdc_mint)]
+    pub bidder_usdc_account: Account<'info, TokenAccount>,
+
+    /// The Shrub PDA's associated USDC token account.
+    #[account(
+        mut,
+        associated_token::mint = pda_account.usdc_mint,
+        associated_token::authority = pda_account
+    )]
+    pub shrub_usdc_account: Account<'info, TokenAccount>,
+
+    /// The borrower's USDC token account, which receives any surplus.
+    #[account(
+        mut,
+        token::mint = pda_account.usdc_mint,
+        token::authority = loan.borrower
+    )]
+    pub borrower_usdc_account: Account<'info, TokenAccount>,
+
+    /// Token program.
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+#[instruction(loan_id: u64)]
+pub struct ExpireLoan<'info> {
+    /// The PDA account.
+    #[account(
+        mut,
+        has_one = admin,
+        seeds = [b"shrub", admin.key().as_ref()],
+        bump = pda_account.bump
+    )]
+    pub pda_account: Account<'info, DataAccount>,
+
+    /// The admin account (used for deriving PDA).
+    /// CHECK: This is not used for data validation; it is only used for PDA derivation.
+    pub admin: AccountInfo<'info>,
+
+    /// The account expiring the loan, which pays for and later reclaims the auction account.
+    #[account(mut)]
+    pub starter: Signer<'info>,
+
+    /// The expired loan.
+    #[account(
+        mut,
+        seeds = [b"loan", pda_account.key().as_ref(), loan_id.to_le_bytes().as_ref()],
+        bump = loan.bump
+    )]
+    pub loan: Account<'info, Loan>,
+
+    /// The auction account being created.
+    #[account(
+        init,
+        payer = starter,
+        space = 8 + Auction::INIT_SPACE,
+        seeds = [b"auction", loan.key().as_ref()],
+        bump
+    )]
+    pub auction: Account<'info, Auction>,
+
+    /// The oracle accounts that price the collateral.
+    pub price: PriceAccounts<'info>,
+
+    /// System program.
+    pub system_program: Program<'info, System>,
+}
+
+#[derive(Accounts)]
+pub struct DepositUsdc<'info> {
+    /// The admin who is depositing USDC.
+    #[account(mut)]
+    pub admin: Signer<'info>,
+
+    /// The PDA account.
+    #[account(
+        mut,
+        has_one = admin,
+        seeds = [b"shrub", admin.key().as_ref()],
+        bump = pda_account.bump
+    )]
+    pub pda_account: Account<'info, DataAccount>,
+
+    /// The admin's USDC token account.
+    #[account(mut, token::mint = pda_account.usdc_mint)]
+    pub admin_usdc_account: Account<'info, TokenAccount>,
+
+    /// The Shrub PDA's associated USDC token account.
+    #[account(
+        mut,
+        associated_token::mint = pda_account.usdc_mint,
+        associated_token::authority = pda_account
+    )]
+    pub shrub_usdc_account: Account<'info, TokenAccount>,
+
+    /// Token program.
+    pub token_program: Program<'info, Token>,
+}
+
+#[derive(Accounts)]
+pub struct QuoteLoan<'info> {
+    /// The PDA account.
+    #[account(
+        has_one = admin,
+        seeds = [b"shrub", admin.key().as_ref()],
+        bump = pda_account.bump
+    )]
+    pub pda_account: Account<'info, DataAccount>,
+
+    /// The market config holding the tier table.
+    #[account(
+        seeds = [b"market_config", pda_account.key().as_ref()],
+        bump = market_config.bump
+    )]
+    pub market_config: Account<'info, MarketConfig>,
+
+    /// The admin account (used for deriving PDA).
+    /// CHECK: This is not used for data validation; it is only used for PDA derivation.
+    pub admin: AccountInfo<'info>,
+
+    /// The oracle accounts that price the collateral.
+    pub price: PriceAccounts<'info>,
+}
+
+#[derive(Accounts)]
+#[instruction(loan_id: u64)]
+pub struct CheckLoanHealth<'info> {
+    /// The PDA account.
+    #[account(
+        has_one = admin,
+        seeds = [b"shrub", admin.key().as_ref()],
+        bump = pda_account.bump
+    )]
     pub pda_account: Account<'info, DataAccount>,
 
-    /// The admin account (used for deriving PDA).
-    /// CHECK: This is not used for data validation; it is only used for PDA derivation.
-    pub admin: AccountInfo<'info>,
+    /// The admin account (used for deriving PDA).
+    /// CHECK: This is not used for data validation; it is only used for PDA derivation.
+    pub admin: AccountInfo<'info>,
+
+    /// The loan being checked.
+    #[account(
+        seeds = [b"loan", pda_account.key().as_ref(), loan_id.to_le_bytes().as_ref()],
+        bump = loan.bump
+    )]
+    pub loan: Account<'info, Loan>,
+
+    /// The oracle accounts that price the collateral.
+    pub price: PriceAccounts<'info>,
+}
+
+/// The PDA account structure.
+#[account]
+pub struct DataAccount {
+    pub admin: Pubkey,                     // Admin of the PDA
+    pub bump: u8,                          // Bump for PDA derivation
+    pub oracle: OracleConfig,              // Primary price source for collateral valuation
+    pub secondary_oracle: OracleConfig,    // Optional secondary price source
+    pub price_guard: PriceGuard,           // Aggregation and sanity checks for prices
+    pub price_mode: PriceMode,             // Spot, TWAP or min(spot, TWAP) collateral pricing
+    pub twap_window: u32,                  // TWAP window in seconds
+    pub next_loan_id: u64,                 // Id assigned to the next loan
+    pub liquidation_bonus_bps: u16,        // Bonus paid to liquidators in basis points
+    pub close_factor_bps: u16,             // Share of a loan's debt one liquidation can repay
+    pub liquidation_mode: LiquidationMode, // Fixed-bonus or Dutch-auction liquidation
+    pub auction_config: AuctionConfig,     // Dutch-auction parameters
+    pub grace_period: u32,                 // Seconds after maturity a loan can still be repaid
+    pub rate_mode: RateMode,               // Fixed-tier or variable-rate interest for new loans
+    pub rate_model: RateModel,             // Kinked curve the variable borrow rate follows
+    pub borrow_index: u128,                // Cumulative variable borrow index, WAD-scaled
+    pub borrow_rate: u32,                  // Variable borrow rate in bps, repriced when USDC moves
+    pub last_index_update: i64,            // Time the borrow index was last updated
+    pub total_principal: u64,              // Outstanding principal across the market's open loans
+    pub usdc_mint: Pubkey,                 // Mint of the USDC the market lends
+}
+
+impl DataAccount {
+    /// Space required for the DataAccount:
+    /// - admin: 32 bytes
+    /// - bump: 1 byte
+    /// - oracle: 69 bytes
+    /// - secondary_oracle: 69 bytes
+    /// - price_guard: 5 bytes
+    /// - price_mode: 1 byte
+    /// - twap_window: 4 bytes
+    /// - next_loan_id: 8 bytes
+    /// - liquidation_bonus_bps: 2 bytes
+    /// - close_factor_bps: 2 bytes
+    /// - liquidation_mode: 1 byte
+    /// - auction_config: 10 bytes
+    /// - grace_period: 4 bytes
+    /// - rate_mode: 1 byte
+    /// - rate_model: 8 bytes
+    /// - borrow_index: 16 bytes
+    /// - borrow_rate: 4 bytes
+    /// - last_index_update: 8 bytes
+    /// - total_principal: 8 bytes
+    /// - usdc_mint: 32 bytes
+    ///
+    /// Total: 32 + 1 + 69 + 69 + 5 + 1 + 4 + 8 + 2 + 2 + 1 + 10 + 4 + 1 + 8 + 16 + 4 + 8 + 8 + 32 = 285 bytes
+    const INIT_SPACE: usize = 32 + 1 + OracleConfig::INIT_SPACE * 2 + PriceGuard::INIT_SPACE
+        + 1 + 4 + 8 + 2 + 2 + 1 + AuctionConfig::INIT_SPACE + 4
+        + 1 + RateModel::INIT_SPACE + 16 + 4 + 8 + 8 + 32;
+
+    /// Reads the SOL price from the primary and, if configured, secondary oracle and
+    /// combines them according to the market's price guard.
+    fn read_sol_price<'info>(
+        &self,
+        oracle_program: &AccountInfo<'info>,
+        price_feed: &AccountInfo<'info>,
+        secondary_oracle_program: Option<&AccountInfo<'info>>,
+        secondary_price_feed: Option<&AccountInfo<'info>>,
+    ) -> Result<Price> {
+        let primary = self.oracle.read_price(oracle_program, price_feed);
+
+        let secondary = if self.secondary_oracle.feed == Pubkey::default() {
+            None
+        } else {
+            match (secondary_oracle_program, secondary_price_feed) {
+                (Some(program), Some(feed)) => Some(self.secondary_oracle.read_price(program, feed)),
+                _ => return Err(ErrorCode::MissingSecondaryOracle.into()),
+            }
+        };
+
+        self.price_guard.combine(primary, secondary)
+    }
+
+    /// Splits a liquidation offering to repay `repay_amount` of `debt` into the amount actually
+    /// repaid and the collateral seized for it, in lamports.
+    ///
+    /// Repayment is capped by the close factor while the loan has not `expired` and the collateral
+    /// still covers the debt plus bonus. The collateral seized is worth the repaid amount plus the liquidation bonus:
+    /// (repaid * (10_000 + bonus) * LAMPORTS_PER_SOL) / (10_000 * sol_price), rounded down and
+    /// capped at `collateral`.
+    pub fn liquidation_quote(
+        &self,
+        debt: u64,
+        collateral: u64,
+        sol_price: u64,
+        repay_amount: u64,
+        expired: bool,
+    ) -> Result<(u64, u64)> {
+        let collateral_for = |amount: u64| {
+            radar_math::collateral_for_value(amount, self.liquidation_bonus_bps, sol_price, Rounding::Down)
+                .map_err(|_| ErrorCode::LiquidationCalculationFailed)
+        };
+
+        let max_repay = if expired || collateral_for(debt)? >= collateral {
+            debt
+        } else {
+            ((debt as u128) * self.close_factor_bps as u128 / 10_000) as u64
+        };
+        let repaid = repay_amount.min(max_repay);
+        if repaid == 0 {
+            return Err(ErrorCode::InvalidLiquidationAmount.into());
+        }
+        let collateral_seized = collateral_for(repaid)?.min(collateral);
+        Ok((repaid, collateral_seized))
+    }
+
+    /// Borrow index at `now`: the stored index grown at the borrow rate since its last update.
+    pub fn borrow_index_at(&self, now: i64) -> Result<u128> {
+        let elapsed = now.saturating_sub(self.last_index_update).max(0) as u64;
+        radar_math::grow_index(self.borrow_index, self.borrow_rate, elapsed, Rounding::Down)
+            .map_err(|_| ErrorCode::InterestCalculationFailed.into())
+    }
+
+    /// Brings the borrow index up to `now` and returns it.
+    fn update_borrow_index(&mut self, now: i64) -> Result<u128> {
+        self.borrow_index = self.borrow_index_at(now)?;
+        self.last_index_update = now;
+        Ok(self.borrow_index)
+    }
+
+    /// Reprices the variable borrow rate from the rate model at the market's utilization, given
+    /// the pool's USDC account once this instruction's transfers have landed.
+    fn refresh_borrow_rate(&mut self, shrub_usdc_account: &mut Account<TokenAccount>) -> Result<()> {
+        shrub_usdc_account.reload()?;
+        let utilization = radar_math::utilization_bps(self.total_principal, shrub_usdc_account.amount);
+        self.borrow_rate = self.rate_model.borrow_rate(utilization)?;
+        Ok(())
+    }
+
+    /// Applies a change in principal to the market's outstanding total.
+    fn adjust_total_principal(&mut self, delta: i128) -> Result<()> {
+        self.total_principal = u64::try_from(self.total_principal as i128 + delta)
+            .map_err(|_| ErrorCode::PositionCalculationFailed)?;
+        Ok(())
+    }
+}
+
+/// Liquidation bonus new markets start with, in basis points.
+const DEFAULT_LIQUIDATION_BONUS_BPS: u16 = 500;
+
+/// Maximum liquidation bonus the admin can set, in basis points.
+const MAX_LIQUIDATION_BONUS_BPS: u16 = 2_000;
+
+/// Close factor new markets start with, in basis points.
+const DEFAULT_CLOSE_FACTOR_BPS: u16 = 5_000;
+
+/// Seconds in a day.
+const SECONDS_IN_DAY: i64 = 86_400;
+
+/// Grace period new markets start with, in seconds.
+const DEFAULT_GRACE_PERIOD: u32 = 3 * SECONDS_IN_DAY as u32;
+
+/// Maximum grace period the admin can set, in seconds.
+const MAX_GRACE_PERIOD: u32 = 30 * SECONDS_IN_DAY as u32;
+
+/// How long before maturity a loan can be extended, in seconds.
+const EXTENSION_WINDOW: i64 = 7 * SECONDS_IN_DAY;
+
+/// Maximum number of tiers in a market's tier table.
+const MAX_TIERS: usize = 8;
+
+/// Maximum early-repayment window the admin can set, in seconds.
+const MAX_EARLY_REPAYMENT_WINDOW: u32 = 365 * SECONDS_IN_DAY as u32;
+
+/// Early-repayment rules the default tiers start with: a 5% penalty APY for the rest of the
+/// first 30 days, as the frontend's `EARLY_REPAYMENT_THRESHOLD` and `EARLY_REPAYMENT_APY`.
+const DEFAULT_EARLY_REPAYMENT: EarlyRepayment = EarlyRepayment::PenaltyApy {
+    window: 30 * SECONDS_IN_DAY as u32,
+    apy: 500,
+};
+
+/// Tiers every market starts with (APY, LTV and liquidation threshold in bps), with the
+/// default early-repayment rules.
+const DEFAULT_TIERS: [Tier; 4] = [
+    Tier::new(800, 5000, 7000), // 50% LTV
+    Tier::new(500, 3300, 5300), // 33% LTV
+    Tier::new(100, 2500, 4500), // 25% LTV
+    Tier::new(0, 2000, 4000),   // 20% LTV
+];
+
+/// An APY/LTV pair loans can be opened at.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy)]
+pub struct Tier {
+    pub apy: u16,                        // Annual Percentage Yield in basis points
+    pub ltv: u16,                        // Maximum loan-to-value at origination in basis points
+    pub liquidation_threshold: u16,      // Loan-to-value at which the loan can be liquidated
+    pub enabled: bool,                   // Whether new loans can be opened in this tier
+    pub early_repayment: EarlyRepayment, // Fee for repaying loans of this tier early
+}
 
-    /// The user taking the loan.
-    #[account(mut)]
-    pub user: Signer<'info>,
+impl Tier {
+    const fn new(apy: u16, ltv: u16, liquidation_threshold: u16) -> Self {
+        Tier {
+            apy,
+            ltv,
+            liquidation_threshold,
+            enabled: true,
+            early_repayment: DEFAULT_EARLY_REPAYMENT,
+        }
+    }
+}
 
-    /// The user's associated USDC token account.
-    #[account(mut)]
-    pub user_usdc_account: Account<'info, TokenAccount>,
+/// What a borrower pays on top of interest for repaying a loan early. Windows are counted
+/// in seconds from origination.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum EarlyRepayment {
+    /// Interest is purely pro-rata.
+    None,
+    /// Interest is charged for at least `period`: repaying within it costs the tier APY on the
+    /// principal for the rest of the period. Variable-rate loans pay the tier APY too, as the
+    /// borrow rate over the rest of the period is unknown and the tier APY is the rate quoted.
+    MinimumInterest { period: u32 },
+    /// Repaying within `window` costs `apy` on the principal for the rest of the window.
+    PenaltyApy { window: u32, apy: u16 },
+    /// Repaying within `window` costs a flat `fee` in micro-USDC.
+    FlatFee { window: u32, fee: u64 },
+}
 
-    /// The Shrub PDA's associated USDC token account.
-    #[account(mut)]
-    pub shrub_usdc_account: Account<'info, TokenAccount>,
+impl EarlyRepayment {
+    /// Space required for the largest variant: 1 (tag) + 4 + 8 = 13 bytes
+    const INIT_SPACE: usize = 1 + 4 + 8;
+
+    fn validate(&self) -> Result<()> {
+        let valid = match *self {
+            EarlyRepayment::None => true,
+            EarlyRepayment::MinimumInterest { period } => period <= MAX_EARLY_REPAYMENT_WINDOW,
+            EarlyRepayment::PenaltyApy { window, apy } => {
+                window <= MAX_EARLY_REPAYMENT_WINDOW && apy as u64 <= radar_math::BPS
+            }
+            EarlyRepayment::FlatFee { window, .. } => window <= MAX_EARLY_REPAYMENT_WINDOW,
+        };
+        if !valid {
+            return Err(ErrorCode::InvalidEarlyRepayment.into());
+        }
+        Ok(())
+    }
+}
 
-    /// The USDC mint.
-    pub usdc_mint: Account<'info, Mint>,
+/// Admin-managed market configuration, seeded by market.
+#[account]
+pub struct MarketConfig {
+    pub market: Pubkey,    // Market PDA
+    pub version: u32,      // Incremented on every tier change
+    pub tiers: Vec<Tier>,  // APY/LTV tier table
+    pub bump: u8,          // Bump for PDA derivation
+}
 
-    /// System program.
-    pub system_program: Program<'info, System>,
+impl MarketConfig {
+    /// Space required for the MarketConfig:
+    /// - market: 32 bytes
+    /// - version: 4 bytes
+    /// - tiers: 4 bytes (vector length) + 20 bytes * 8 tiers
+    /// - bump: 1 byte
+    ///
+    /// Total: 32 + 4 + 4 + 160 + 1 = 201 bytes
+    const INIT_SPACE: usize = 32 + 4 + 4 + (7 + EarlyRepayment::INIT_SPACE) * MAX_TIERS + 1;
 
-    /// Token program.
-    pub token_program: Program<'info, Token>,
+    /// Returns the enabled tier for the given APY.
+    fn tier(&self, apy: u16) -> Option<&Tier> {
+        self.tiers.iter().find(|tier| tier.apy == apy && tier.enabled)
+    }
+}
 
-    /// Associated token program.
-    pub associated_token_program: Program<'info, AssociatedToken>,
+/// Terms a loan can be taken for.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub enum LoanTerm {
+    OneMonth,
+    ThreeMonth,
+    SixMonth,
+    TwelveMonth,
 }
 
-#[derive(Accounts)]
-pub struct RepayLoan<'info> {
-    /// The PDA account.
-    #[account(
-        mut,
-        has_one = admin,
-        seeds = [b"shrub", admin.key().as_ref()],
-        bump = pda_account.bump
-    )]
-    pub pda_account: Account<'info, DataAccount>,
+impl LoanTerm {
+    /// Length of the term in seconds, counting a month as 30 days and a year as 365.
+    pub fn duration(&self) -> i64 {
+        let days = match self {
+            LoanTerm::OneMonth => 30,
+            LoanTerm::ThreeMonth => 90,
+            LoanTerm::SixMonth => 180,
+            LoanTerm::TwelveMonth => 365,
+        };
+        days * SECONDS_IN_DAY
+    }
+}
 
-    /// The admin account (used for deriving PDA).
-    /// CHECK: This is not used for data validation; it is only used for PDA derivation.
-    pub admin: AccountInfo<'info>,
+/// Represents an individual loan, stored in its own PDA seeded by market and loan id.
+/// The account only exists while the loan is open.
+#[account]
+pub struct Loan {
+    pub id: u64,                         // 8 bytes
+    pub market: Pubkey,                  // 32 bytes
+    pub principal: u64,                  // 8 bytes
+    pub apy: u16,                        // 2 bytes
+    pub rate_mode: RateMode,             // 1 byte, fixed tier APY or the market's variable rate
+    pub ltv: u16,                        // 2 bytes, LTV of the tier at origination in bps
+    pub liquidation_threshold: u16,      // 2 bytes, liquidation threshold at origination in bps
+    pub origination_price: u64,          // 8 bytes, SOL price used at origination in micro-USDC
+    pub tier_version: u32,               // 4 bytes, market config version at origination
+    pub early_repayment: EarlyRepayment, // 13 bytes, early-repayment rules at origination
+    pub collateral: u64,                 // 8 bytes
+    pub created_at: i64,                 // 8 bytes
+    pub term: LoanTerm,                  // 1 byte
+    pub matures_at: i64,                 // 8 bytes, end of the term
+    pub accrued_interest: u64,           // 8 bytes, interest settled but not yet paid
+    pub last_accrual: i64,               // 8 bytes, time interest was last settled
+    pub borrow_index: u128,              // 16 bytes, market borrow index when interest was last settled
+    pub borrower: Pubkey,                // 32 bytes
+    pub bump: u8,                        // 1 byte
+    pub in_auction: bool,                // 1 byte, set while the collateral is being auctioned
+}
 
-    /// The user repaying the loan.
-    #[account(mut)]
-    pub user: Signer<'info>,
+impl Loan {
+    /// Space required for the Loan: 8 + 32 + 8 + 2 + 1 + 2 + 2 + 8 + 4 + 13 + 8 + 8 + 1 + 8 + 8 + 8 + 16 + 32 + 1 + 1 = 171 bytes
+    const INIT_SPACE: usize = 8 + 32 + 8 + 2 + 1 + 2 + 2 + 8 + 4 + EarlyRepayment::INIT_SPACE + 8 + 8 + 1 + 8
+        + 8 + 8 + 16 + 32 + 1 + 1;
+
+    /// Interest owed at `now`, when the market's borrow index is `borrow_index`: the settled
+    /// interest plus the interest since the last accrual, rounded down. Fixed-rate loans accrue
+    /// simple interest on the principal, principal * (apy / 10000) * (duration / SECONDS_IN_YEAR);
+    /// variable-rate loans scale their debt by the growth of the borrow index.
+    pub fn interest(&self, now: i64, borrow_index: u128) -> Result<u64> {
+        let interest = match self.rate_mode {
+            RateMode::Fixed => {
+                let duration = now.saturating_sub(self.last_accrual).max(0) as u64;
+                radar_math::simple_interest(self.principal, self.apy, duration, Rounding::Down)
+            }
+            RateMode::Variable => {
+                let debt = self.principal.saturating_add(self.accrued_interest);
+                radar_math::scale_by_index(debt, self.borrow_index, borrow_index, Rounding::Down)
+                    .map(|scaled| scaled.saturating_sub(debt))
+            }
+        };
+        interest
+            .ok()
+            .and_then(|interest| interest.checked_add(self.accrued_interest))
+            .ok_or(ErrorCode::InterestCalculationFailed.into())
+    }
 
-    /// The user's associated USDC token account.
-    #[account(mut)]
-    pub user_usdc_account: Account<'info, TokenAccount>,
+    /// Settles interest up to `now` so the principal can change without losing accrued interest.
+    fn accrue(&mut self, now: i64, borrow_index: u128) -> Result<()> {
+        self.accrued_interest = self.interest(now, borrow_index)?;
+        self.last_accrual = now;
+        self.borrow_index = borrow_index;
+        Ok(())
+    }
 
-    /// The Shrub PDA's associated USDC token account.
-    #[account(mut)]
-    pub shrub_usdc_account: Account<'info, TokenAccount>,
+    /// Settles interest up to `now` and adds it to the principal. Returns the interest capitalized.
+    fn capitalize_interest(&mut self, now: i64, borrow_index: u128) -> Result<u64> {
+        self.accrue(now, borrow_index)?;
+        let interest = self.accrued_interest;
+        self.principal = self
+            .principal
+            .checked_add(interest)
+            .ok_or(ErrorCode::InterestCalculationFailed)?;
+        self.accrued_interest = 0;
+        Ok(interest)
+    }
 
-    /// The USDC mint.
-    pub usdc_mint: Account<'info, Mint>,
+    /// Applies a repayment of less than the total owed to a loan whose interest has just been
+    /// accrued, paying off interest before principal. Returns the principal repaid.
+    fn apply_repayment(&mut self, amount: u64) -> Result<u64> {
+        let interest_repaid = amount.min(self.accrued_interest);
+        let principal_repaid = amount - interest_repaid;
+        self.accrued_interest -= interest_repaid;
+        self.principal = self
+            .principal
+            .checked_sub(principal_repaid)
+            .ok_or(ErrorCode::LiquidationCalculationFailed)?;
+        Ok(principal_repaid)
+    }
 
-    /// System program.
-    pub system_program: Program<'info, System>,
+    /// Fee on top of the interest owed for repaying the loan in full at `now`, under the
+    /// early-repayment rules it was opened with, rounded down.
+    pub fn early_repayment_fee(&self, now: i64) -> Result<u64> {
+        let elapsed = now.saturating_sub(self.created_at).max(0) as u64;
+        let fee = match self.early_repayment {
+            EarlyRepayment::None => Ok(0),
+            EarlyRepayment::MinimumInterest { period } => {
+                // Tops what the principal earned at the tier APY up to what it earns over the
+                // period. This is independent of the interest actually accrued, which for
+                // variable-rate loans follows the borrow index rather than the tier APY.
+                let period = period as u64;
+                let minimum = radar_math::simple_interest(self.principal, self.apy, period, Rounding::Down);
+                let earned =
+                    radar_math::simple_interest(self.principal, self.apy, elapsed.min(period), Rounding::Down);
+                minimum.and_then(|minimum| earned.map(|earned| minimum - earned))
+            }
+            EarlyRepayment::PenaltyApy { window, apy } => {
+                let remaining = (window as u64).saturating_sub(elapsed);
+                radar_math::simple_interest(self.principal, apy, remaining, Rounding::Down)
+            }
+            EarlyRepayment::FlatFee { window, fee } => Ok(if elapsed < window as u64 { fee } else { 0 }),
+        };
+        fee.map_err(|_| ErrorCode::InterestCalculationFailed.into())
+    }
 
-    /// Token program.
-    pub token_program: Program<'info, Token>,
+    /// Principal plus interest accrued up to `now`, in micro-USDC.
+    pub fn total_owed(&self, now: i64, borrow_index: u128) -> Result<u64> {
+        self.principal
+            .checked_add(self.interest(now, borrow_index)?)
+            .ok_or(ErrorCode::InterestCalculationFailed.into())
+    }
 
-    /// Associated token program.
-    pub associated_token_program: Program<'info, AssociatedToken>,
-}
+    /// Whether the loan is past maturity and the market's `grace_period` at `now`.
+    pub fn is_expired(&self, now: i64, grace_period: u32) -> bool {
+        now > self.matures_at.saturating_add(grace_period as i64)
+    }
 
-#[derive(Accounts)]
-pub struct DepositUsdc<'info> {
-    /// The admin who is depositing USDC.
-    #[account(mut)]
-    pub admin: Signer<'info>,
+    /// Whether `debt` exceeds the loan's liquidation threshold of its collateral value at `sol_price`.
+    pub fn is_liquidatable(&self, debt: u64, sol_price: u64) -> Result<bool> {
+        radar_math::is_liquidatable(debt, self.collateral, sol_price, self.liquidation_threshold)
+            .map_err(|_| ErrorCode::LiquidationCalculationFailed.into())
+    }
 
-    /// The admin's USDC token account.
-    #[account(mut)]
-    pub admin_usdc_account: Account<'info, TokenAccount>,
+    /// Debt, collateral value, health factor and liquidation price of the loan at `now`
+    /// when the market's borrow index is `borrow_index` and SOL is worth `sol_price`.
+    pub fn health(&self, now: i64, borrow_index: u128, sol_price: u64) -> Result<LoanHealth> {
+        let debt = self.total_owed(now, borrow_index)?;
+        Ok(LoanHealth {
+            debt,
+            collateral_value: radar_math::collateral_value(self.collateral, sol_price, Rounding::Down)
+                .map_err(|_| ErrorCode::LiquidationCalculationFailed)?,
+            health_factor_bps: radar_math::health_factor_bps(
+                debt,
+                self.collateral,
+                sol_price,
+                self.liquidation_threshold,
+            )
+            .map_err(|_| ErrorCode::LiquidationCalculationFailed)?,
+            liquidation_price: radar_math::liquidation_price(debt, self.collateral, self.liquidation_threshold),
+        })
+    }
+}
 
-    /// The Shrub PDA's USDC token account.
-    #[account(mut)]
-    pub shrub_usdc_account: Account<'info, TokenAccount>,
+/// A loan's health at a given time and price, returned by `loan_health`.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Debug)]
+pub struct LoanHealth {
+    pub debt: u64,              // Principal plus accrued interest, in micro-USDC
+    pub collateral_value: u64,  // Collateral value at the price, in micro-USDC
+    pub health_factor_bps: u64, // Collateral value at the liquidation threshold over debt; liquidatable below 10_000
+    pub liquidation_price: u64, // SOL price below which the loan can be liquidated, in micro-USDC
+}
 
-    /// Token program.
-    pub token_program: Program<'info, Token>,
+/// Collateral in lamports required to borrow `principal` at `ltv` when SOL is worth `sol_price`,
+/// rounded down.
+fn required_collateral(principal: u64, ltv: u16, sol_price: u64) -> Result<u64> {
+    radar_math::required_collateral(principal, ltv, sol_price, Rounding::Down)
+        .map_err(|_| ErrorCode::InsufficientCollateral.into())
 }
 
-/// The PDA account structure.
+/// Maximum number of open loans tracked per borrower.
+///
+/// The borrower index is allocated once, at a fixed size, when the borrower takes their first
+/// loan, so this bounds the rent it costs them and the scan made each time a loan closes. A
+/// borrower at the cap can still borrow more against an open loan with `increase_principal`, or
+/// open new loans as others close.
+const MAX_OPEN_LOANS: usize = 32;
+
+/// Per-borrower index of open loans and position totals, seeded by market and borrower.
 #[account]
-pub struct DataAccount {
-    pub admin: Pubkey,     // Admin of the PDA
-    pub bump: u8,          // Bump for PDA derivation
-    pub loans: Vec<Loan>,  // List of loans
+pub struct BorrowerIndex {
+    pub market: Pubkey,             // Market PDA
+    pub borrower: Pubkey,           // Borrower
+    pub open_loan_ids: Vec<u64>,    // Ids of the borrower's open loans
+    pub outstanding_principal: u64, // Sum of principal across open loans
+    pub total_collateral: u64,      // Sum of collateral across open loans, in lamports
+    pub bump: u8,                   // Bump for PDA derivation
 }
 
-impl DataAccount {
-    /// Space required for the DataAccount:
-    /// - admin: 32 bytes
+impl BorrowerIndex {
+    /// Space required for the BorrowerIndex:
+    /// - market: 32 bytes
+    /// - borrower: 32 bytes
+    /// - open_loan_ids: 4 bytes (vector length) + 8 bytes * 32 loans
+    /// - outstanding_principal: 8 bytes
+    /// - total_collateral: 8 bytes
     /// - bump: 1 byte
-    /// - loans: 4 bytes (vector length) + 67 bytes * 10 loans
-    /// Total: 32 + 1 + 4 + 670 = 707 bytes
-    const INIT_SPACE: usize = 32 + 1 + 4 + 67 * 10;
-}
+    ///
+    /// Total: 32 + 32 + 4 + 256 + 8 + 8 + 1 = 341 bytes
+    const INIT_SPACE: usize = 32 + 32 + 4 + 8 * MAX_OPEN_LOANS + 8 + 8 + 1;
+
+    /// Adds a newly opened loan to the index.
+    fn open_loan(&mut self, loan: &Loan) -> Result<()> {
+        if self.open_loan_ids.len() >= MAX_OPEN_LOANS {
+            return Err(ErrorCode::TooManyOpenLoans.into());
+        }
+        self.open_loan_ids.push(loan.id);
+        self.adjust(loan.principal as i128, loan.collateral as i128)
+    }
 
-/// Represents an individual loan.
-#[derive(AnchorSerialize, AnchorDeserialize, Clone)]
-pub struct Loan {
-    pub id: u64,          // 8 bytes
-    pub principal: u64,   // 8 bytes
-    pub apy: u16,         // 2 bytes
-    pub collateral: u64,  // 8 bytes
-    pub created_at: i64,  // 8 bytes
-    pub borrower: Pubkey, // 32 bytes
-    pub repaid: bool,     // 1 byte
+    /// Removes a closed loan from the index.
+    fn close_loan(&mut self, loan: &Loan) -> Result<()> {
+        let position = self
+            .open_loan_ids
+            .iter()
+            .position(|&id| id == loan.id)
+            .ok_or(ErrorCode::LoanNotFound)?;
+        self.open_loan_ids.swap_remove(position);
+        self.adjust(-(loan.principal as i128), -(loan.collateral as i128))
+    }
+
+    /// Applies a change in principal and collateral to the position totals.
+    fn adjust(&mut self, principal_delta: i128, collateral_delta: i128) -> Result<()> {
+        let apply = |total: u64, delta: i128| {
+            u64::try_from(total as i128 + delta).map_err(|_| ErrorCode::PositionCalculationFailed)
+        };
+        self.outstanding_principal = apply(self.outstanding_principal, principal_delta)?;
+        self.total_collateral = apply(self.total_collateral, collateral_delta)?;
+        Ok(())
+    }
 }
 
 /// Custom error types.
@@ -438,6 +3135,123 @@ pub enum ErrorCode {
 
     #[msg("Invalid loan duration")]
     InvalidLoanDuration,
+
+    #[msg("Price feed not configured")]
+    PriceFeedNotConfigured,
+
+    #[msg("Price feed is stale")]
+    StalePrice,
+
+    #[msg("Invalid price from feed")]
+    InvalidPrice,
+
+    #[msg("Invalid price feed account")]
+    InvalidPriceFeed,
+
+    #[msg("Secondary price feed required")]
+    MissingSecondaryOracle,
+
+    #[msg("Price confidence interval too wide")]
+    PriceConfidenceTooWide,
+
+    #[msg("Oracle prices disagree; borrowing and liquidation are paused")]
+    OracleDeviation,
+
+    #[msg("Price history required for this price mode")]
+    PriceHistoryRequired,
+
+    #[msg("Not enough price history for the TWAP window")]
+    InsufficientPriceHistory,
+
+    #[msg("Price was cranked too recently")]
+    CrankTooSoon,
+
+    #[msg("Price calculation failed")]
+    PriceCalculationFailed,
+
+    #[msg("Invalid TWAP window")]
+    InvalidTwapWindow,
+
+    #[msg("Loan id already used")]
+    LoanIdReused,
+
+    #[msg("Loan id counter overflow")]
+    LoanIdOverflow,
+
+    #[msg("Too many open loans for this borrower")]
+    TooManyOpenLoans,
+
+    #[msg("Position calculation failed")]
+    PositionCalculationFailed,
+
+    #[msg("Tier already exists")]
+    TierAlreadyExists,
+
+    #[msg("Too many tiers")]
+    TooManyTiers,
+
+    #[msg("LTV must be positive and below the liquidation threshold")]
+    InvalidTier,
+
+    #[msg("Loan is not eligible for liquidation")]
+    LoanHealthy,
+
+    #[msg("Invalid liquidation bonus")]
+    InvalidLiquidationBonus,
+
+    #[msg("Liquidation calculation failed")]
+    LiquidationCalculationFailed,
+
+    #[msg("Invalid close factor")]
+    InvalidCloseFactor,
+
+    #[msg("Invalid liquidation amount")]
+    InvalidLiquidationAmount,
+
+    #[msg("Liquidation mode not enabled for this market")]
+    WrongLiquidationMode,
+
+    #[msg("Invalid auction config")]
+    InvalidAuctionConfig,
+
+    #[msg("Loan is being auctioned")]
+    LoanInAuction,
+
+    #[msg("Loan has expired")]
+    LoanExpired,
+
+    #[msg("Loan has not expired")]
+    LoanNotExpired,
+
+    #[msg("Grace period too long")]
+    InvalidGracePeriod,
+
+    #[msg("Invalid early repayment rules")]
+    InvalidEarlyRepayment,
+
+    #[msg("Partial repayment must be positive and below the debt")]
+    InvalidRepaymentAmount,
+
+    #[msg("Collateral amount must be positive")]
+    InvalidCollateralAmount,
+
+    #[msg("Borrow amount must be positive")]
+    InvalidBorrowAmount,
+
+    #[msg("Invalid rate model")]
+    InvalidRateModel,
+
+    #[msg("Invalid price history account")]
+    InvalidPriceHistory,
+
+    #[msg("Loan can only be extended near maturity")]
+    ExtensionTooEarly,
+
+    #[msg("Auction has not reached its maximum discount")]
+    AuctionNotEnded,
+
+    #[msg("Loan has no collateral to auction")]
+    NoCollateralToAuction,
 }
 
 /// Event emitted when a loan is taken.
@@ -447,7 +3261,10 @@ pub struct LoanTaken {
     pub borrower: Pubkey,
     pub principal: u64,
     pub apy: u16,
+    pub rate_mode: RateMode,
     pub collateral: u64,
+    pub term: LoanTerm,
+    pub matures_at: i64,
 }
 
 /// Event emitted when a loan is repaid.
@@ -457,5 +3274,214 @@ pub struct LoanRepaid {
     pub borrower: Pubkey,
     pub principal: u64,
     pub interest: u64,
+    pub early_repayment_fee: u64,
+    pub collateral: u64,
+    pub matures_at: i64,
+}
+
+/// Event emitted when a price is recorded in the price history.
+#[event]
+pub struct PriceCranked {
+    pub market: Pubkey,
+    pub price: u64,
+    pub timestamp: i64,
+}
+
+/// Event emitted when a loan account is closed, carrying its final state.
+#[event]
+pub struct LoanClosed {
+    pub loan: Loan,
+    pub closed_at: i64,
+}
+
+/// Event emitted when a tier is added.
+#[event]
+pub struct TierAdded {
+    pub market: Pubkey,
+    pub apy: u16,
+    pub ltv: u16,
+    pub liquidation_threshold: u16,
+    pub version: u32,
+}
+
+/// Event emitted when a tier is updated.
+#[event]
+pub struct TierUpdated {
+    pub market: Pubkey,
+    pub apy: u16,
+    pub ltv: u16,
+    pub liquidation_threshold: u16,
+    pub version: u32,
+}
+
+/// Event emitted when a tier's early-repayment rules change.
+#[event]
+pub struct EarlyRepaymentUpdated {
+    pub market: Pubkey,
+    pub apy: u16,
+    pub early_repayment: EarlyRepayment,
+    pub version: u32,
+}
+
+/// Event emitted when a tier is disabled.
+#[event]
+pub struct TierDisabled {
+    pub market: Pubkey,
+    pub apy: u16,
+    pub version: u32,
+}
+
+/// Event emitted when a disabled tier is enabled again.
+#[event]
+pub struct TierEnabled {
+    pub market: Pubkey,
+    pub apy: u16,
+    pub version: u32,
+}
+
+/// Event emitted when a loan is liquidated.
+#[event]
+pub struct LoanLiquidated {
+    pub loan_id: u64,
+    pub borrower: Pubkey,
+    pub liquidator: Pubkey,
+    pub debt_repaid: u64,
+    pub collateral_seized: u64,
+    pub collateral_returned: u64,
+    pub remaining_principal: u64,
+    pub remaining_collateral: u64,
+    pub sol_price: u64,
+}
+
+/// Event emitted when a loan's collateral is put up for auction.
+#[event]
+pub struct AuctionStarted {
+    pub loan_id: u64,
+    pub borrower: Pubkey,
+    pub start_price: u64,
+    pub debt: u64,
+    pub penalty: u64,
+    pub collateral: u64,
+    pub started_at: i64,
+}
+
+/// Event emitted when an auction is restarted from a fresh price.
+#[event]
+pub struct AuctionRestarted {
+    pub loan_id: u64,
+    pub borrower: Pubkey,
+    pub start_price: u64,
+    pub collateral_remaining: u64,
+    pub usdc_raised: u64,
+    pub started_at: i64,
+}
+
+/// Event emitted when a bidder buys auctioned collateral.
+#[event]
+pub struct AuctionFilled {
+    pub loan_id: u64,
+    pub bidder: Pubkey,
+    pub collateral: u64,
+    pub usdc_paid: u64,
+    pub price: u64,
+    pub discount_bps: u16,
+}
+
+/// Event emitted when an auction settles and the loan is closed.
+#[event]
+pub struct AuctionSettled {
+    pub loan_id: u64,
+    pub borrower: Pubkey,
+    pub usdc_raised: u64,
+    pub debt: u64,
+    pub penalty: u64,
+    pub surplus: u64,
+    pub collateral_returned: u64,
+}
+
+/// Event emitted when an expired loan's collateral is put up for auction.
+#[event]
+pub struct LoanExpired {
+    pub loan_id: u64,
+    pub borrower: Pubkey,
+    pub matures_at: i64,
+    pub debt: u64,
+    pub collateral_auctioned: u64,
+    pub expired_at: i64,
+}
+
+/// Event emitted when part of a loan's debt is repaid.
+#[event]
+pub struct LoanPartiallyRepaid {
+    pub loan_id: u64,
+    pub borrower: Pubkey,
+    pub amount: u64,
+    pub interest_repaid: u64,
+    pub principal_repaid: u64,
+    pub early_repayment_fee: u64,
+    pub collateral_released: u64,
+    pub remaining_principal: u64,
+    pub remaining_collateral: u64,
+}
+
+/// Event emitted when collateral is added to a loan.
+#[event]
+pub struct CollateralAdded {
+    pub loan_id: u64,
+    pub borrower: Pubkey,
+    pub depositor: Pubkey,
+    pub amount: u64,
+    pub total_collateral: u64,
+}
+
+/// Event emitted when a borrower withdraws collateral from a loan.
+#[event]
+pub struct CollateralWithdrawn {
+    pub loan_id: u64,
+    pub borrower: Pubkey,
+    pub amount: u64,
+    pub total_collateral: u64,
+    pub sol_price: u64,
+}
+
+/// Event emitted when a borrower borrows more against a loan.
+#[event]
+pub struct PrincipalIncreased {
+    pub loan_id: u64,
+    pub borrower: Pubkey,
+    pub amount: u64,
+    pub interest_settled: u64,
+    pub principal: u64,
+    pub collateral: u64,
+    pub sol_price: u64,
+}
+
+/// Event emitted when a loan moves to another tier.
+#[event]
+pub struct LoanRefinanced {
+    pub loan_id: u64,
+    pub borrower: Pubkey,
+    pub old_apy: u16,
+    pub new_apy: u16,
+    pub ltv: u16,
+    pub liquidation_threshold: u16,
+    pub interest_settled: u64,
+    pub collateral_added: u64,
+    pub collateral_released: u64,
     pub collateral: u64,
-}
\ No newline at end of file
+    pub sol_price: u64,
+}
+
+/// Event emitted when a loan is rolled over into a new term.
+#[event]
+pub struct LoanExtended {
+    pub loan_id: u64,
+    pub borrower: Pubkey,
+    pub term: LoanTerm,
+    pub previous_maturity: i64,
+    pub matures_at: i64,
+    pub interest_capitalized: u64,
+    pub principal: u64,
+    pub collateral: u64,
+    pub sol_price: u64,
+}
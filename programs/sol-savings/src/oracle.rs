@@ -0,0 +1,451 @@
+use anchor_lang::prelude::*;
+use chainlink_solana as chainlink;
+
+use crate::ErrorCode;
+
+/// Default maximum age of a price, in seconds.
+pub const DEFAULT_MAX_PRICE_AGE: u32 = 60;
+
+/// Default maximum confidence interval, in bps of the price.
+pub const DEFAULT_MAX_CONF_BPS: u16 = 200;
+
+/// Default maximum disagreement between primary and secondary prices, in bps.
+pub const DEFAULT_MAX_DEVIATION_BPS: u16 = 500;
+
+/// Default TWAP window, in seconds.
+pub const DEFAULT_TWAP_WINDOW: u32 = 1_800;
+
+/// Number of observations kept in a `PriceHistory` ring buffer.
+pub const PRICE_HISTORY_CAPACITY: usize = 32;
+
+/// USDC has 6 decimals; prices are normalized to micro-USDC per SOL.
+const USDC_DECIMALS: i32 = 6;
+
+/// The Pyth receiver program that owns pull-price update accounts.
+pub const PYTH_RECEIVER_PROGRAM_ID: Pubkey = pubkey!("rec5EKMGg6MxZYaMdyBfgwp4d5rB9T1VQH5pJv5LtFJ");
+
+/// Anchor discriminator of the Pyth `PriceUpdateV2` account.
+const PYTH_PRICE_UPDATE_DISCRIMINATOR: [u8; 8] = [34, 241, 35, 99, 157, 126, 244, 205];
+
+/// The backend a market reads its SOL/USD price from.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum OracleSource {
+    /// A Chainlink OCR2 feed, read over CPI to the store program.
+    #[default]
+    Chainlink,
+    /// A Pyth pull-price `PriceUpdateV2` account.
+    Pyth,
+    /// An admin-controlled `MockPrice` account, for localnet.
+    Mock,
+}
+
+/// Oracle configuration stored on the market.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct OracleConfig {
+    pub source: OracleSource, // Backend to read from
+    pub program: Pubkey,      // Program that owns (or serves) the feed
+    pub feed: Pubkey,         // Price feed account
+    pub max_price_age: u32,   // Maximum age of a price in seconds
+}
+
+impl OracleConfig {
+    /// Space required for the OracleConfig:
+    /// - source: 1 byte
+    /// - program: 32 bytes
+    /// - feed: 32 bytes
+    /// - max_price_age: 4 bytes
+    pub const INIT_SPACE: usize = 1 + 32 + 32 + 4;
+
+    /// Reads the configured feed and returns a fresh, positive SOL price.
+    pub fn read_price<'info>(
+        &self,
+        oracle_program: &AccountInfo<'info>,
+        price_feed: &AccountInfo<'info>,
+    ) -> Result<Price> {
+        if self.feed == Pubkey::default() {
+            return Err(ErrorCode::PriceFeedNotConfigured.into());
+        }
+        if price_feed.key() != self.feed || oracle_program.key() != self.program {
+            return Err(ErrorCode::InvalidPriceFeed.into());
+        }
+
+        let price = match self.source {
+            OracleSource::Chainlink => read_chainlink(oracle_program, price_feed)?,
+            OracleSource::Pyth => read_pyth(self.program, price_feed)?,
+            OracleSource::Mock => read_mock(self.program, price_feed)?,
+        };
+
+        self.check_price(price, Clock::get()?.unix_timestamp)
+    }
+
+    /// Rejects zero prices and prices older than the configured maximum age at `now`.
+    pub fn check_price(&self, price: Price, now: i64) -> Result<Price> {
+        if price.price == 0 {
+            return Err(ErrorCode::InvalidPrice.into());
+        }
+        if now.saturating_sub(price.publish_time) > self.max_price_age as i64 {
+            return Err(ErrorCode::StalePrice.into());
+        }
+        Ok(price)
+    }
+}
+
+/// How readings from the primary and secondary oracles are combined.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum Aggregation {
+    /// Use the primary price, falling back to the secondary if it is unusable.
+    #[default]
+    PrimaryWithFallback,
+    /// Use the median of all usable prices.
+    Median,
+}
+
+/// Guards applied to oracle readings before they are used to value collateral.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct PriceGuard {
+    pub aggregation: Aggregation, // How primary and secondary prices are combined
+    pub max_conf_bps: u16,        // Maximum confidence interval in bps of the price
+    pub max_deviation_bps: u16,   // Maximum primary/secondary disagreement in bps
+}
+
+impl PriceGuard {
+    /// Space required for the PriceGuard: 1 + 2 + 2 = 5 bytes
+    pub const INIT_SPACE: usize = 1 + 2 + 2;
+
+    /// Rejects readings whose confidence interval is too wide.
+    pub fn check_confidence(&self, price: Price) -> Result<Price> {
+        let conf_bps = (price.conf as u128) * 10_000 / (price.price as u128);
+        if conf_bps > self.max_conf_bps as u128 {
+            return Err(ErrorCode::PriceConfidenceTooWide.into());
+        }
+        Ok(price)
+    }
+
+    /// Combines the primary and (optional) secondary readings into a single price.
+    ///
+    /// When both readings are usable and disagree by more than `max_deviation_bps`, the lower
+    /// reading is used and the price is flagged as deviated. Instructions that borrow, release
+    /// collateral or liquidate reject a deviated price, leaving repayments and collateral top-ups
+    /// as the only way to act on a loan until the feeds agree again.
+    pub fn combine(&self, primary: Result<Price>, secondary: Option<Result<Price>>) -> Result<Price> {
+        let primary = primary.and_then(|price| self.check_confidence(price));
+        let secondary = secondary.map(|reading| reading.and_then(|price| self.check_confidence(price)));
+
+        match (primary, secondary) {
+            (Ok(primary), Some(Ok(secondary))) => {
+                let (low, high) = if primary.price <= secondary.price {
+                    (primary.price, secondary.price)
+                } else {
+                    (secondary.price, primary.price)
+                };
+                let deviation_bps = ((high - low) as u128) * 10_000 / (low as u128);
+                if deviation_bps > self.max_deviation_bps as u128 {
+                    msg!("Oracle deviation {} bps ({} vs {})", deviation_bps, primary.price, secondary.price);
+                    return Ok(Price {
+                        price: low,
+                        conf: primary.conf.max(secondary.conf),
+                        publish_time: primary.publish_time.min(secondary.publish_time),
+                        deviated: true,
+                    });
+                }
+
+                match self.aggregation {
+                    Aggregation::PrimaryWithFallback => Ok(primary),
+                    // The median of two readings is their midpoint
+                    Aggregation::Median => Ok(Price {
+                        price: low + (high - low) / 2,
+                        conf: primary.conf.max(secondary.conf),
+                        publish_time: primary.publish_time.min(secondary.publish_time),
+                        deviated: false,
+                    }),
+                }
+            }
+            (Ok(primary), _) => Ok(primary),
+            (Err(err), Some(Ok(secondary))) => {
+                msg!("Primary oracle unusable, falling back to secondary: {:?}", err);
+                Ok(secondary)
+            }
+            (Err(err), _) => Err(err),
+        }
+    }
+}
+
+/// Which price is used to value collateral.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum PriceMode {
+    /// The current oracle price.
+    #[default]
+    Spot,
+    /// The time-weighted average price from the market's `PriceHistory`.
+    Twap,
+    /// The lower of the spot price and the TWAP.
+    MinSpotTwap,
+}
+
+impl PriceMode {
+    /// Selects the collateral price for this mode from a spot price and, if needed,
+    /// the TWAP over `window` seconds.
+    pub fn select(
+        &self,
+        spot: u64,
+        price_history: Option<&PriceHistory>,
+        now: i64,
+        window: u32,
+    ) -> Result<u64> {
+        if *self == PriceMode::Spot {
+            return Ok(spot);
+        }
+
+        let twap = price_history
+            .ok_or(ErrorCode::PriceHistoryRequired)?
+            .twap(now, window as i64)?;
+
+        Ok(match self {
+            PriceMode::Twap => twap,
+            _ => spot.min(twap),
+        })
+    }
+}
+
+/// A single price observation.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, Default)]
+pub struct Observation {
+    pub timestamp: i64,   // Unix timestamp of the observation
+    pub price: u64,       // Price in micro-USDC
+    pub cumulative: u128, // Sum of price * seconds up to this observation
+}
+
+/// Ring buffer of cranked prices used to compute a time-weighted average.
+#[account]
+pub struct PriceHistory {
+    pub market: Pubkey,                                       // Market PDA this history belongs to
+    pub head: u8,                                             // Index the next observation is written to
+    pub len: u8,                                              // Number of observations recorded
+    pub observations: [Observation; PRICE_HISTORY_CAPACITY], // Observations, oldest overwritten first
+}
+
+impl PriceHistory {
+    /// Space required for the PriceHistory:
+    /// - market: 32 bytes
+    /// - head: 1 byte
+    /// - len: 1 byte
+    /// - observations: 32 bytes * 32 observations
+    ///
+    /// Total: 32 + 1 + 1 + 1024 = 1058 bytes
+    pub const INIT_SPACE: usize = 32 + 1 + 1 + (8 + 8 + 16) * PRICE_HISTORY_CAPACITY;
+
+    /// Returns the `age`-th most recent observation (0 is the latest).
+    fn get(&self, age: usize) -> Option<&Observation> {
+        if age >= self.len as usize {
+            return None;
+        }
+        let index = (self.head as usize + PRICE_HISTORY_CAPACITY - 1 - age) % PRICE_HISTORY_CAPACITY;
+        Some(&self.observations[index])
+    }
+
+    /// Appends an observation, accumulating the previous price over the elapsed time.
+    pub fn record(&mut self, price: u64, now: i64, min_interval: i64) -> Result<()> {
+        let cumulative = match self.get(0) {
+            Some(last) => {
+                if now - last.timestamp < min_interval {
+                    return Err(ErrorCode::CrankTooSoon.into());
+                }
+                (last.price as u128)
+                    .checked_mul((now - last.timestamp) as u128)
+                    .and_then(|val| val.checked_add(last.cumulative))
+                    .ok_or(ErrorCode::PriceCalculationFailed)?
+            }
+            None => 0,
+        };
+
+        self.observations[self.head as usize] = Observation {
+            timestamp: now,
+            price,
+            cumulative,
+        };
+        self.head = ((self.head as usize + 1) % PRICE_HISTORY_CAPACITY) as u8;
+        self.len = (self.len as usize + 1).min(PRICE_HISTORY_CAPACITY) as u8;
+        Ok(())
+    }
+
+    /// Returns the time-weighted average price over the `window` seconds before `now`.
+    pub fn twap(&self, now: i64, window: i64) -> Result<u64> {
+        let last = self.get(0).ok_or(ErrorCode::InsufficientPriceHistory)?;
+        if window <= 0 || now - last.timestamp > window {
+            return Err(ErrorCode::StalePrice.into());
+        }
+
+        // Find the newest observation at or before the start of the window
+        let start = now - window;
+        let first = (0..self.len as usize)
+            .filter_map(|age| self.get(age))
+            .find(|obs| obs.timestamp <= start)
+            .ok_or(ErrorCode::InsufficientPriceHistory)?;
+
+        let cumulative_at = |obs: &Observation, time: i64| {
+            (obs.price as u128)
+                .checked_mul((time - obs.timestamp) as u128)
+                .and_then(|val| val.checked_add(obs.cumulative))
+        };
+
+        let twap = cumulative_at(last, now)
+            .zip(cumulative_at(first, start))
+            .and_then(|(end, begin)| end.checked_sub(begin))
+            .map(|sum| sum / window as u128)
+            .ok_or(ErrorCode::PriceCalculationFailed)?;
+
+        u64::try_from(twap).map_err(|_| ErrorCode::PriceCalculationFailed.into())
+    }
+}
+
+/// A SOL price normalized to micro-USDC per SOL.
+#[derive(Clone, Copy, Debug)]
+pub struct Price {
+    pub price: u64,        // Price in micro-USDC
+    pub conf: u64,         // Confidence interval in micro-USDC (0 if the source has none)
+    pub publish_time: i64, // Unix timestamp of the observation
+    pub deviated: bool,    // Whether the primary and secondary oracles disagreed
+}
+
+impl Price {
+    /// The price, rejected if the oracles disagreed, for instructions that borrow against it or
+    /// liquidate collateral at it.
+    pub fn undisputed(&self) -> Result<u64> {
+        if self.deviated {
+            return Err(ErrorCode::OracleDeviation.into());
+        }
+        Ok(self.price)
+    }
+}
+
+/// Admin-controlled price account used in place of a real feed on localnet.
+#[account]
+pub struct MockPrice {
+    pub market: Pubkey,    // Market PDA this price belongs to
+    pub price: u64,        // Price in micro-USDC
+    pub conf: u64,         // Confidence interval in micro-USDC
+    pub publish_time: i64, // Unix timestamp of the last update
+}
+
+impl MockPrice {
+    /// Space required for the MockPrice: 32 + 8 + 8 + 8 = 56 bytes
+    pub const INIT_SPACE: usize = 32 + 8 + 8 + 8;
+}
+
+/// Mirror of the Pyth receiver's `VerificationLevel`.
+#[derive(AnchorDeserialize)]
+enum PythVerificationLevel {
+    Partial { _num_signatures: u8 },
+    Full,
+}
+
+/// Mirror of the Pyth receiver's `PriceFeedMessage`.
+#[derive(AnchorDeserialize)]
+struct PythPriceFeedMessage {
+    _feed_id: [u8; 32],
+    price: i64,
+    conf: u64,
+    exponent: i32,
+    publish_time: i64,
+}
+
+/// Scales `value * 10^exponent` USD to micro-USDC.
+fn scale_to_usdc(value: u128, exponent: i32) -> Result<u64> {
+    let shift = exponent + USDC_DECIMALS;
+    let scaled = if shift >= 0 {
+        10u128
+            .checked_pow(shift as u32)
+            .and_then(|factor| value.checked_mul(factor))
+    } else {
+        10u128
+            .checked_pow(shift.unsigned_abs())
+            .map(|factor| value / factor)
+    }
+    .ok_or(ErrorCode::InvalidPrice)?;
+
+    u64::try_from(scaled).map_err(|_| ErrorCode::InvalidPrice.into())
+}
+
+/// Reads the latest round of a Chainlink feed.
+fn read_chainlink<'info>(
+    oracle_program: &AccountInfo<'info>,
+    price_feed: &AccountInfo<'info>,
+) -> Result<Price> {
+    let round = chainlink::latest_round_data(oracle_program.clone(), price_feed.clone())?;
+    let decimals = chainlink::decimals(oracle_program.clone(), price_feed.clone())?;
+    chainlink_price(round.answer, decimals, round.timestamp)
+}
+
+/// Converts a Chainlink round answer with `decimals` decimal places to a `Price`.
+pub fn chainlink_price(answer: i128, decimals: u8, timestamp: u32) -> Result<Price> {
+    if answer <= 0 {
+        return Err(ErrorCode::InvalidPrice.into());
+    }
+
+    Ok(Price {
+        price: scale_to_usdc(answer as u128, -(decimals as i32))?,
+        conf: 0,
+        publish_time: timestamp as i64,
+        deviated: false,
+    })
+}
+
+/// Reads a fully verified Pyth `PriceUpdateV2` account.
+fn read_pyth(program: Pubkey, price_feed: &AccountInfo) -> Result<Price> {
+    if price_feed.owner != &program {
+        return Err(ErrorCode::InvalidPriceFeed.into());
+    }
+
+    parse_pyth(&price_feed.try_borrow_data()?)
+}
+
+/// Parses the data of a fully verified Pyth `PriceUpdateV2` account.
+pub fn parse_pyth(data: &[u8]) -> Result<Price> {
+    if data.len() < 8 + 32 || data[..8] != PYTH_PRICE_UPDATE_DISCRIMINATOR {
+        return Err(ErrorCode::InvalidPriceFeed.into());
+    }
+
+    // Skip the discriminator and write authority
+    let mut rest = &data[8 + 32..];
+    let verification_level = PythVerificationLevel::deserialize(&mut rest)?;
+    if !matches!(verification_level, PythVerificationLevel::Full) {
+        return Err(ErrorCode::InvalidPriceFeed.into());
+    }
+    let message = PythPriceFeedMessage::deserialize(&mut rest)?;
+
+    if message.price <= 0 {
+        return Err(ErrorCode::InvalidPrice.into());
+    }
+
+    Ok(Price {
+        price: scale_to_usdc(message.price as u128, message.exponent)?,
+        conf: scale_to_usdc(message.conf as u128, message.exponent)?,
+        publish_time: message.publish_time,
+        deviated: false,
+    })
+}
+
+/// Reads an admin-set `MockPrice` account.
+fn read_mock(program: Pubkey, price_feed: &AccountInfo) -> Result<Price> {
+    if price_feed.owner != &program {
+        return Err(ErrorCode::InvalidPriceFeed.into());
+    }
+
+    parse_mock(&price_feed.try_borrow_data()?)
+}
+
+/// Parses the data of a `MockPrice` account.
+pub fn parse_mock(mut data: &[u8]) -> Result<Price> {
+    let mock = MockPrice::try_deserialize(&mut data)?;
+
+    if mock.price == 0 {
+        return Err(ErrorCode::InvalidPrice.into());
+    }
+
+    Ok(Price {
+        price: mock.price,
+        conf: mock.conf,
+        publish_time: mock.publish_time,
+        deviated: false,
+    })
+}
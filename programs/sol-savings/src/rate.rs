@@ -0,0 +1,72 @@
+use anchor_lang::prelude::*;
+
+use crate::ErrorCode;
+
+/// Default borrow rate at zero utilization, in bps.
+pub const DEFAULT_BASE_RATE_BPS: u16 = 0;
+
+/// Default rise in the borrow rate from zero utilization to the kink, in bps.
+pub const DEFAULT_SLOPE1_BPS: u16 = 400;
+
+/// Default utilization at which the curve steepens, in bps.
+pub const DEFAULT_KINK_BPS: u16 = 8_000;
+
+/// Default rise in the borrow rate from the kink to full utilization, in bps.
+pub const DEFAULT_SLOPE2_BPS: u16 = 7_500;
+
+/// How a market charges interest on new loans.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Default, Debug)]
+pub enum RateMode {
+    /// Loans accrue simple interest at their tier's fixed APY.
+    #[default]
+    Fixed,
+    /// Loans accrue interest at the market's borrow rate, which follows pool utilization
+    /// through the rate model; debt grows with the market's cumulative borrow index.
+    Variable,
+}
+
+/// Kinked borrow-rate curve over pool utilization, stored on the market.
+#[derive(AnchorSerialize, AnchorDeserialize, Clone, Copy, PartialEq, Eq, Debug)]
+pub struct RateModel {
+    pub base_rate_bps: u16, // Borrow rate at zero utilization
+    pub slope1_bps: u16,    // Rise in the rate from zero utilization to the kink
+    pub kink_bps: u16,      // Utilization at which the second slope takes over
+    pub slope2_bps: u16,    // Rise in the rate from the kink to full utilization
+}
+
+impl Default for RateModel {
+    fn default() -> Self {
+        Self {
+            base_rate_bps: DEFAULT_BASE_RATE_BPS,
+            slope1_bps: DEFAULT_SLOPE1_BPS,
+            kink_bps: DEFAULT_KINK_BPS,
+            slope2_bps: DEFAULT_SLOPE2_BPS,
+        }
+    }
+}
+
+impl RateModel {
+    /// Space required for the RateModel:
+    /// - base_rate_bps, slope1_bps, kink_bps, slope2_bps: 2 bytes each
+    pub const INIT_SPACE: usize = 2 * 4;
+
+    /// Ensures the kink lies within (0%, 100%].
+    pub fn validate(&self) -> Result<()> {
+        if self.kink_bps == 0 || self.kink_bps > 10_000 {
+            return Err(ErrorCode::InvalidRateModel.into());
+        }
+        Ok(())
+    }
+
+    /// Borrow rate at `utilization_bps`, in bps.
+    pub fn borrow_rate(&self, utilization_bps: u16) -> Result<u32> {
+        radar_math::kinked_rate_bps(
+            utilization_bps,
+            self.base_rate_bps,
+            self.slope1_bps,
+            self.kink_bps,
+            self.slope2_bps,
+        )
+        .map_err(|_| ErrorCode::InvalidRateModel.into())
+    }
+}
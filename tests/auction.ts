@@ -0,0 +1,313 @@
+// auction.ts (Dutch-auction liquidation tests)
+//
+// These run against bankrun rather than the local validator so the clock can be warped
+// across the whole auction curve.
+import * as anchor from "@coral-xyz/anchor";
+import { expect } from 'chai';
+import { RadarLend } from "../target/types/radar_lend";
+import { TOKEN_PROGRAM_ID, ASSOCIATED_TOKEN_PROGRAM_ID } from '@solana/spl-token';
+import {
+  Market,
+  SYSTEM_PROGRAM,
+  auctionPda,
+  borrowerIndexPda,
+  createMint,
+  createUsdcAccount,
+  fund,
+  loanPda,
+  rent,
+  setMockPrice,
+  setTime,
+  setupMarket,
+  solBalance,
+  usdcBalance,
+} from "./helpers";
+
+describe('dutch auction liquidation', function () {
+  this.timeout(20000);
+
+  let market: Market;
+  let program: anchor.Program<RadarLend>;
+
+  let adminAccount: anchor.web3.Keypair;
+  let userAccount: anchor.web3.Keypair;
+  let keeper: anchor.web3.Keypair;
+  let bidder: anchor.web3.Keypair;
+  let usdcMint: anchor.web3.PublicKey;
+  let shrubPda: anchor.web3.PublicKey;
+  let marketConfig: anchor.web3.PublicKey;
+  let borrowerIndex: anchor.web3.PublicKey;
+  let mockPrice: anchor.web3.PublicKey;
+  let shrubUsdcAccount: anchor.web3.PublicKey;
+  let userUsdcAccount: anchor.web3.PublicKey;
+  let bidderUsdcAccount: anchor.web3.PublicKey;
+  let loanId: anchor.BN;
+  let startedAt: bigint;
+
+  // Moves the clock to `seconds` after the auction started, on a new slot
+  async function warpTo(seconds: number) {
+    await setTime(market, startedAt + BigInt(seconds));
+  }
+
+  async function startAuction() {
+    await program.methods.startAuction(loanId)
+      .accounts({
+        pdaAccount: shrubPda,
+        admin: adminAccount.publicKey,
+        starter: keeper.publicKey,
+        loan: loanPda(market, loanId),
+        auction: auctionPda(market, loanId),
+        price: {
+          oracleProgram: program.programId,
+          priceFeed: mockPrice,
+        },
+        systemProgram: SYSTEM_PROGRAM,
+      })
+      .signers([keeper])
+      .rpc();
+  }
+
+  async function restartAuction() {
+    await program.methods.restartAuction(loanId)
+      .accounts({
+        pdaAccount: shrubPda,
+        admin: adminAccount.publicKey,
+        loan: loanPda(market, loanId),
+        auction: auctionPda(market, loanId),
+        price: {
+          oracleProgram: program.programId,
+          priceFeed: mockPrice,
+        },
+      })
+      .rpc();
+  }
+
+  async function fillAuction(collateralAmount: number, usdcAccount: anchor.web3.PublicKey = bidderUsdcAccount) {
+    await program.methods.fillAuction(loanId, new anchor.BN(collateralAmount))
+      .accounts({
+        pdaAccount: shrubPda,
+        admin: adminAccount.publicKey,
+        bidder: bidder.publicKey,
+        loan: loanPda(market, loanId),
+        auction: auctionPda(market, loanId),
+        borrower: userAccount.publicKey,
+        starter: keeper.publicKey,
+        borrowerIndex,
+        bidderUsdcAccount: usdcAccount,
+        shrubUsdcAccount,
+        borrowerUsdcAccount: userUsdcAccount,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([bidder])
+      .rpc();
+  }
+
+  before(async function () {
+    market = await setupMarket();
+    ({ program, usdcMint, shrubPda, marketConfig, mockPrice, shrubUsdcAccount } = market);
+    adminAccount = market.admin;
+
+    userAccount = anchor.web3.Keypair.generate();
+    keeper = anchor.web3.Keypair.generate();
+    bidder = anchor.web3.Keypair.generate();
+    for (const keypair of [userAccount, keeper, bidder]) {
+      await fund(market, keypair);
+    }
+    borrowerIndex = borrowerIndexPda(market, userAccount.publicKey);
+    userUsdcAccount = await createUsdcAccount(market, userAccount.publicKey);
+    bidderUsdcAccount = await createUsdcAccount(market, bidder.publicKey, 10_000_000);
+
+    // 1 USDC against 0.02 SOL ($2 at $100/SOL): exactly the 50% LTV of the 8% tier
+    loanId = (await program.account.dataAccount.fetch(shrubPda)).nextLoanId;
+    await program.methods.takeLoan(new anchor.BN(1_000_000), 800, new anchor.BN(20_000_000), { oneMonth: {} })
+      .accounts({
+        pdaAccount: shrubPda,
+        admin: adminAccount.publicKey,
+        user: userAccount.publicKey,
+        loan: loanPda(market, loanId),
+        borrowerIndex,
+        marketConfig,
+        price: {
+          oracleProgram: program.programId,
+          priceFeed: mockPrice,
+        },
+        userUsdcAccount,
+        shrubUsdcAccount,
+        usdcMint,
+        systemProgram: SYSTEM_PROGRAM,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([userAccount])
+      .rpc();
+  });
+
+  it('rejects starting an auction in a fixed-bonus market', async function () {
+    try {
+      await startAuction();
+      expect.fail("Expected error for wrong liquidation mode");
+    } catch (err: any) {
+      expect(err.message).to.include("Liquidation mode not enabled for this market");
+    }
+  });
+
+  it('switches the market to auction liquidation', async function () {
+    await program.methods.setLiquidationMode(
+      { dutchAuction: {} },
+      { startDiscountBps: 0, maxDiscountBps: 2_000, duration: 1_000, penaltyBps: 500 },
+    )
+      .accounts({
+        admin: adminAccount.publicKey,
+        pdaAccount: shrubPda,
+      })
+      .signers([adminAccount])
+      .rpc();
+
+    const pdaAccountData = await program.account.dataAccount.fetch(shrubPda);
+    expect(pdaAccountData.liquidationMode).to.deep.equal({ dutchAuction: {} });
+    expect(pdaAccountData.auctionConfig.duration).to.equal(1_000);
+  });
+
+  it('rejects starting an auction for a healthy loan', async function () {
+    try {
+      await startAuction();
+      expect.fail("Expected error for healthy loan");
+    } catch (err: any) {
+      expect(err.message).to.include("Loan is not eligible for liquidation");
+    }
+  });
+
+  it('starts an auction once the loan is unhealthy', async function () {
+    // At $65/SOL the collateral is worth $1.30, an LTV of ~77% against the 70% threshold
+    await setMockPrice(market, 65_000_000);
+    await startAuction();
+
+    const auction = await program.account.auction.fetch(auctionPda(market, loanId));
+    startedAt = BigInt(auction.startedAt.toString());
+    expect(auction.loanId.toString()).to.equal(loanId.toString());
+    expect(auction.borrower.toString()).to.equal(userAccount.publicKey.toString());
+    expect(auction.starter.toString()).to.equal(keeper.publicKey.toString());
+    expect(auction.startPrice.toNumber()).to.equal(65_000_000);
+    expect(auction.debt.toNumber()).to.equal(1_000_000);
+    expect(auction.penalty.toNumber()).to.equal(50_000);
+    expect(auction.collateralRemaining.toNumber()).to.equal(20_000_000);
+
+    const loan = await program.account.loan.fetch(loanPda(market, loanId));
+    expect(loan.inAuction).to.equal(true);
+  });
+
+  it('prevents the borrower from repaying during the auction', async function () {
+    try {
+      await program.methods.repayLoan(loanId)
+        .accounts({
+          pdaAccount: shrubPda,
+          admin: adminAccount.publicKey,
+          user: userAccount.publicKey,
+          loan: loanPda(market, loanId),
+          borrowerIndex,
+          userUsdcAccount,
+          shrubUsdcAccount,
+          usdcMint,
+          systemProgram: SYSTEM_PROGRAM,
+          tokenProgram: TOKEN_PROGRAM_ID,
+          associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+        })
+        .signers([userAccount])
+        .rpc();
+      expect.fail("Expected error for loan in auction");
+    } catch (err: any) {
+      expect(err.message).to.include("Loan is being auctioned");
+    }
+  });
+
+  it('rejects a bid paid from a token account of another mint', async function () {
+    const otherMint = await createMint(market);
+    const otherUsdcAccount = await createUsdcAccount(market, bidder.publicKey, 10_000_000, otherMint);
+
+    try {
+      await fillAuction(5_000_000, otherUsdcAccount);
+      expect.fail("Expected error for a token account of another mint");
+    } catch (err: any) {
+      expect(err.message).to.include("A token mint constraint was violated");
+    }
+
+    const auction = await program.account.auction.fetch(auctionPda(market, loanId));
+    expect(auction.collateralRemaining.toNumber()).to.equal(20_000_000);
+  });
+
+  it('sells collateral at a growing discount', async function () {
+    // A quarter of the way through, the discount is 5%: $61.75/SOL
+    await warpTo(250);
+    let usdcBefore = await usdcBalance(market, bidderUsdcAccount);
+    let solBefore = await solBalance(market, bidder.publicKey);
+    await fillAuction(5_000_000);
+    expect(usdcBefore - await usdcBalance(market, bidderUsdcAccount)).to.equal(308_750n);
+    expect(await solBalance(market, bidder.publicKey) - solBefore).to.equal(5_000_000n);
+
+    // Halfway through, the discount is 10%: $58.50/SOL
+    await warpTo(500);
+    usdcBefore = await usdcBalance(market, bidderUsdcAccount);
+    await fillAuction(5_000_000);
+    expect(usdcBefore - await usdcBalance(market, bidderUsdcAccount)).to.equal(292_500n);
+
+    const auction = await program.account.auction.fetch(auctionPda(market, loanId));
+    expect(auction.collateralRemaining.toNumber()).to.equal(10_000_000);
+    expect(auction.usdcRaised.toNumber()).to.equal(601_250);
+  });
+
+  it('rejects restarting an auction before it ends', async function () {
+    try {
+      await restartAuction();
+      expect.fail("Expected error for auction not ended");
+    } catch (err: any) {
+      expect(err.message).to.include("Auction has not reached its maximum discount");
+    }
+  });
+
+  it('restarts an ended auction from a fresh price', async function () {
+    // Past the end of the auction, the price has fallen to $60/SOL
+    await warpTo(1_500);
+    await setMockPrice(market, 60_000_000);
+    await restartAuction();
+
+    const auction = await program.account.auction.fetch(auctionPda(market, loanId));
+    expect(BigInt(auction.startedAt.toString())).to.equal(startedAt + 1_500n);
+    expect(auction.startPrice.toNumber()).to.equal(60_000_000);
+    // What has been sold so far carries over
+    expect(auction.debt.toNumber()).to.equal(1_000_000);
+    expect(auction.penalty.toNumber()).to.equal(50_000);
+    expect(auction.collateralRemaining.toNumber()).to.equal(10_000_000);
+    expect(auction.usdcRaised.toNumber()).to.equal(601_250);
+    startedAt = BigInt(auction.startedAt.toString());
+  });
+
+  it('caps the final bid at the rest of the debt plus penalty', async function () {
+    const loanRent = await rent(market, program.account.loan.size);
+    const auctionRent = await rent(market, program.account.auction.size);
+    const indexBefore = await program.account.borrowerIndex.fetch(borrowerIndex);
+    const userSolBefore = await solBalance(market, userAccount.publicKey);
+    const userUsdcBefore = await usdcBalance(market, userUsdcAccount);
+    const keeperSolBefore = await solBalance(market, keeper.publicKey);
+    const bidderUsdcBefore = await usdcBalance(market, bidderUsdcAccount);
+    const bidderSolBefore = await solBalance(market, bidder.publicKey);
+
+    // Past the end of the restarted auction, the discount stays at its 20% maximum: $48/SOL.
+    // The remaining 448,750 of the 1,050,000 target buys only 0.009348959 SOL of the 0.01 bid
+    await warpTo(2_000);
+    await fillAuction(10_000_000);
+    expect(await solBalance(market, bidder.publicKey) - bidderSolBefore).to.equal(9_348_959n);
+    expect(bidderUsdcBefore - await usdcBalance(market, bidderUsdcAccount)).to.equal(448_751n);
+
+    // The rounding surplus and the unsold collateral go back to the borrower, along with
+    // the loan account's rent
+    expect(await usdcBalance(market, userUsdcAccount) - userUsdcBefore).to.equal(1n);
+    expect(await solBalance(market, userAccount.publicKey) - userSolBefore).to.equal(651_041n + loanRent);
+    expect(await solBalance(market, keeper.publicKey) - keeperSolBefore).to.equal(auctionRent);
+
+    expect(await program.account.loan.fetchNullable(loanPda(market, loanId))).to.be.null;
+    expect(await program.account.auction.fetchNullable(auctionPda(market, loanId))).to.be.null;
+    const indexAfter = await program.account.borrowerIndex.fetch(borrowerIndex);
+    expect(indexAfter.openLoanIds).to.have.length(0);
+    expect(indexBefore.totalCollateral.sub(indexAfter.totalCollateral).toNumber()).to.equal(20_000_000);
+  });
+});
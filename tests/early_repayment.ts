@@ -0,0 +1,220 @@
+// early_repayment.ts (early-repayment rule tests)
+//
+// These run against bankrun rather than the local validator so loans can be repaid at
+// exact times after origination.
+import * as anchor from "@coral-xyz/anchor";
+import { expect } from 'chai';
+import { RadarLend } from "../target/types/radar_lend";
+import { TOKEN_PROGRAM_ID, ASSOCIATED_TOKEN_PROGRAM_ID } from '@solana/spl-token';
+import {
+  DAY,
+  Market,
+  SYSTEM_PROGRAM,
+  borrowerIndexPda,
+  createUsdcAccount,
+  fund,
+  loanPda,
+  setMockPrice,
+  setTime,
+  setupMarket,
+  usdcBalance,
+} from "./helpers";
+
+describe('early repayment', function () {
+  this.timeout(20000);
+
+  let market: Market;
+  let program: anchor.Program<RadarLend>;
+
+  let adminAccount: anchor.web3.Keypair;
+  let userAccount: anchor.web3.Keypair;
+  let usdcMint: anchor.web3.PublicKey;
+  let shrubPda: anchor.web3.PublicKey;
+  let marketConfig: anchor.web3.PublicKey;
+  let borrowerIndex: anchor.web3.PublicKey;
+  let mockPrice: anchor.web3.PublicKey;
+  let shrubUsdcAccount: anchor.web3.PublicKey;
+  let userUsdcAccount: anchor.web3.PublicKey;
+  let penaltyLoanId: anchor.BN;
+  let minimumInterestLoanId: anchor.BN;
+  let flatFeeLoanId: anchor.BN;
+  let lateFlatFeeLoanId: anchor.BN;
+  let partialLoanId: anchor.BN;
+  let defaultLoanId: anchor.BN;
+  let createdAt: bigint;
+
+  // Moves the clock to `seconds` after the loans were taken, on a new slot
+  async function warpTo(seconds: number) {
+    await setTime(market, createdAt + BigInt(seconds));
+  }
+
+  async function takeLoan(apy: number, collateral: number): Promise<anchor.BN> {
+    const loanId = (await program.account.dataAccount.fetch(shrubPda)).nextLoanId;
+    await program.methods.takeLoan(new anchor.BN(1_000_000), apy, new anchor.BN(collateral), { oneMonth: {} })
+      .accounts({
+        pdaAccount: shrubPda,
+        admin: adminAccount.publicKey,
+        user: userAccount.publicKey,
+        loan: loanPda(market, loanId),
+        borrowerIndex,
+        marketConfig,
+        price: {
+          oracleProgram: program.programId,
+          priceFeed: mockPrice,
+        },
+        userUsdcAccount,
+        shrubUsdcAccount,
+        usdcMint,
+        systemProgram: SYSTEM_PROGRAM,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([userAccount])
+      .rpc();
+    return loanId;
+  }
+
+  // Repays a loan and returns the USDC it cost
+  async function repayLoan(loanId: anchor.BN): Promise<bigint> {
+    const usdcBefore = await usdcBalance(market, userUsdcAccount);
+    await program.methods.repayLoan(loanId)
+      .accounts({
+        pdaAccount: shrubPda,
+        admin: adminAccount.publicKey,
+        user: userAccount.publicKey,
+        loan: loanPda(market, loanId),
+        borrowerIndex,
+        userUsdcAccount,
+        shrubUsdcAccount,
+        usdcMint,
+        systemProgram: SYSTEM_PROGRAM,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([userAccount])
+      .rpc();
+    return usdcBefore - await usdcBalance(market, userUsdcAccount);
+  }
+
+  // Repays part of a loan and returns the USDC it cost
+  async function repayPartial(loanId: anchor.BN, amount: number): Promise<bigint> {
+    const usdcBefore = await usdcBalance(market, userUsdcAccount);
+    await program.methods.repayPartial(loanId, new anchor.BN(amount))
+      .accounts({
+        pdaAccount: shrubPda,
+        admin: adminAccount.publicKey,
+        user: userAccount.publicKey,
+        loan: loanPda(market, loanId),
+        borrowerIndex,
+        price: {
+          oracleProgram: program.programId,
+          priceFeed: mockPrice,
+        },
+        userUsdcAccount,
+        shrubUsdcAccount,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([userAccount])
+      .rpc();
+    return usdcBefore - await usdcBalance(market, userUsdcAccount);
+  }
+
+  async function setEarlyRepayment(apy: number, earlyRepayment: any) {
+    await program.methods.setEarlyRepayment(apy, earlyRepayment)
+      .accounts({
+        admin: adminAccount.publicKey,
+        pdaAccount: shrubPda,
+        marketConfig,
+      })
+      .signers([adminAccount])
+      .rpc();
+  }
+
+  before(async function () {
+    market = await setupMarket();
+    ({ program, usdcMint, shrubPda, marketConfig, mockPrice, shrubUsdcAccount } = market);
+    adminAccount = market.admin;
+
+    userAccount = anchor.web3.Keypair.generate();
+    await fund(market, userAccount);
+    borrowerIndex = borrowerIndexPda(market, userAccount.publicKey);
+    userUsdcAccount = await createUsdcAccount(market, userAccount.publicKey, 1_000_000);
+
+    // The default 5% penalty APY within 30 days on the 8% and 0% tiers, a 30-day minimum on
+    // the 5% tier and a flat 0.01 USDC fee within 30 days on the 1% tier
+    await setEarlyRepayment(500, { minimumInterest: { period: 30 * DAY } });
+    await setEarlyRepayment(100, { flatFee: { window: 30 * DAY, fee: new anchor.BN(10_000) } });
+
+    penaltyLoanId = await takeLoan(800, 20_000_000);
+    minimumInterestLoanId = await takeLoan(500, 40_000_000);
+    flatFeeLoanId = await takeLoan(100, 40_000_000);
+    lateFlatFeeLoanId = await takeLoan(100, 40_000_000);
+    partialLoanId = await takeLoan(100, 40_000_000);
+    defaultLoanId = await takeLoan(0, 50_000_000);
+    createdAt = BigInt((await program.account.loan.fetch(loanPda(market, penaltyLoanId))).createdAt.toString());
+  });
+
+  it('seeds the default tiers with a penalty APY', async function () {
+    const config = await program.account.marketConfig.fetch(marketConfig);
+    expect(config.tiers[3].earlyRepayment).to.deep.equal({ penaltyApy: { window: 30 * DAY, apy: 500 } });
+  });
+
+  it('rejects a window longer than a year', async function () {
+    try {
+      await setEarlyRepayment(0, { minimumInterest: { period: 366 * DAY } });
+      expect.fail("Expected error for invalid early repayment rules");
+    } catch (err: any) {
+      expect(err.message).to.include("Invalid early repayment rules");
+    }
+  });
+
+  it('keeps the rules a loan was opened with', async function () {
+    await setEarlyRepayment(800, { none: {} });
+
+    const config = await program.account.marketConfig.fetch(marketConfig);
+    expect(config.tiers[0].earlyRepayment).to.deep.equal({ none: {} });
+    const loan = await program.account.loan.fetch(loanPda(market, penaltyLoanId));
+    expect(loan.earlyRepayment).to.deep.equal({ penaltyApy: { window: 30 * DAY, apy: 500 } });
+  });
+
+  it('charges the penalty APY for the rest of the window', async function () {
+    await warpTo(10 * DAY);
+
+    // 10 days of 8% interest on 1 USDC is 2,191, and 20 days of the 5% penalty is 2,739
+    expect(await repayLoan(penaltyLoanId)).to.equal(1_000_000n + 2_191n + 2_739n);
+  });
+
+  it('charges the default penalty APY on a fresh market', async function () {
+    // The 0% tier accrues no interest, but 20 days of the 5% penalty is 2,739
+    expect(await repayLoan(defaultLoanId)).to.equal(1_000_000n + 2_739n);
+  });
+
+  it('charges a flat fee within the window', async function () {
+    // 10 days of 1% interest on 1 USDC is 273
+    expect(await repayLoan(flatFeeLoanId)).to.equal(1_000_000n + 273n + 10_000n);
+  });
+
+  it('charges a share of the fee on a partial repayment', async function () {
+    await setMockPrice(market, 100_000_000);
+
+    // 273 of interest and then half the principal, plus half the 10,000 flat fee
+    expect(await repayPartial(partialLoanId, 500_273)).to.equal(500_273n + 5_000n);
+    const loan = await program.account.loan.fetch(loanPda(market, partialLoanId));
+    expect(loan.principal.toNumber()).to.equal(500_000);
+
+    // The rest of the loan owes the rest of the fee
+    expect(await repayLoan(partialLoanId)).to.equal(500_000n + 5_000n);
+  });
+
+  it('charges interest for the minimum period', async function () {
+    // 10 days of 5% interest on 1 USDC is 1,369, topped up to 4,109 for 30 days
+    expect(await repayLoan(minimumInterestLoanId)).to.equal(1_000_000n + 4_109n);
+  });
+
+  it('charges no fee after the window', async function () {
+    await warpTo(31 * DAY);
+
+    // 31 days of 1% interest on 1 USDC is 849
+    expect(await repayLoan(lateFlatFeeLoanId)).to.equal(1_000_000n + 849n);
+  });
+});
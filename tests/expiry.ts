@@ -0,0 +1,415 @@
+// expiry.ts (fixed-term loan maturity and expiry tests)
+//
+// These run against bankrun rather than the local validator so the clock can be warped
+// past a loan's maturity and grace period.
+import * as anchor from "@coral-xyz/anchor";
+import { expect } from 'chai';
+import { RadarLend } from "../target/types/radar_lend";
+import { TOKEN_PROGRAM_ID, ASSOCIATED_TOKEN_PROGRAM_ID } from '@solana/spl-token';
+import {
+  MAX_REPAY,
+  Market,
+  SYSTEM_PROGRAM,
+  auctionPda,
+  borrowerIndexPda,
+  createUsdcAccount,
+  fund,
+  loanPda,
+  rent,
+  setMockPrice,
+  setTime,
+  setupMarket,
+  solBalance,
+  usdcBalance,
+} from "./helpers";
+
+const GRACE_PERIOD = 86_400;
+
+describe('loan expiry', function () {
+  this.timeout(20000);
+
+  let market: Market;
+  let program: anchor.Program<RadarLend>;
+
+  let adminAccount: anchor.web3.Keypair;
+  let userAccount: anchor.web3.Keypair;
+  let liquidator: anchor.web3.Keypair;
+  let usdcMint: anchor.web3.PublicKey;
+  let shrubPda: anchor.web3.PublicKey;
+  let marketConfig: anchor.web3.PublicKey;
+  let borrowerIndex: anchor.web3.PublicKey;
+  let mockPrice: anchor.web3.PublicKey;
+  let shrubUsdcAccount: anchor.web3.PublicKey;
+  let userUsdcAccount: anchor.web3.PublicKey;
+  let liquidatorUsdcAccount: anchor.web3.PublicKey;
+  let liquidatedLoanId: anchor.BN;
+  let expiredLoanId: anchor.BN;
+  let extendedLoanId: anchor.BN;
+  let maturesAt: bigint;
+
+  // Moves the clock to `seconds` after the loans mature, on a new slot
+  async function warpTo(seconds: number) {
+    await setTime(market, maturesAt + BigInt(seconds));
+  }
+
+  async function takeLoan(): Promise<anchor.BN> {
+    // 1 USDC against 0.02 SOL ($2 at $100/SOL): exactly the 50% LTV of the 8% tier
+    const loanId = (await program.account.dataAccount.fetch(shrubPda)).nextLoanId;
+    await program.methods.takeLoan(new anchor.BN(1_000_000), 800, new anchor.BN(20_000_000), { oneMonth: {} })
+      .accounts({
+        pdaAccount: shrubPda,
+        admin: adminAccount.publicKey,
+        user: userAccount.publicKey,
+        loan: loanPda(market, loanId),
+        borrowerIndex,
+        marketConfig,
+        price: {
+          oracleProgram: program.programId,
+          priceFeed: mockPrice,
+        },
+        userUsdcAccount,
+        shrubUsdcAccount,
+        usdcMint,
+        systemProgram: SYSTEM_PROGRAM,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([userAccount])
+      .rpc();
+    return loanId;
+  }
+
+  async function repayLoan(loanId: anchor.BN) {
+    await program.methods.repayLoan(loanId)
+      .accounts({
+        pdaAccount: shrubPda,
+        admin: adminAccount.publicKey,
+        user: userAccount.publicKey,
+        loan: loanPda(market, loanId),
+        borrowerIndex,
+        userUsdcAccount,
+        shrubUsdcAccount,
+        usdcMint,
+        systemProgram: SYSTEM_PROGRAM,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([userAccount])
+      .rpc();
+  }
+
+  async function expireLoan(loanId: anchor.BN) {
+    await program.methods.expireLoan(loanId)
+      .accounts({
+        pdaAccount: shrubPda,
+        admin: adminAccount.publicKey,
+        starter: liquidator.publicKey,
+        loan: loanPda(market, loanId),
+        auction: auctionPda(market, loanId),
+        price: {
+          oracleProgram: program.programId,
+          priceFeed: mockPrice,
+        },
+        systemProgram: SYSTEM_PROGRAM,
+      })
+      .signers([liquidator])
+      .rpc();
+  }
+
+  async function fillAuction(loanId: anchor.BN, collateralAmount: number) {
+    await program.methods.fillAuction(loanId, new anchor.BN(collateralAmount))
+      .accounts({
+        pdaAccount: shrubPda,
+        admin: adminAccount.publicKey,
+        bidder: liquidator.publicKey,
+        loan: loanPda(market, loanId),
+        auction: auctionPda(market, loanId),
+        borrower: userAccount.publicKey,
+        starter: liquidator.publicKey,
+        borrowerIndex,
+        bidderUsdcAccount: liquidatorUsdcAccount,
+        shrubUsdcAccount,
+        borrowerUsdcAccount: userUsdcAccount,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([liquidator])
+      .rpc();
+  }
+
+  async function addCollateral(loanId: anchor.BN, lamports: number) {
+    await program.methods.addCollateral(loanId, new anchor.BN(lamports))
+      .accounts({
+        pdaAccount: shrubPda,
+        admin: adminAccount.publicKey,
+        depositor: userAccount.publicKey,
+        loan: loanPda(market, loanId),
+        borrowerIndex,
+        systemProgram: SYSTEM_PROGRAM,
+      })
+      .signers([userAccount])
+      .rpc();
+  }
+
+  async function extendLoan(loanId: anchor.BN, term: any) {
+    await program.methods.extendLoan(loanId, term)
+      .accounts({
+        pdaAccount: shrubPda,
+        admin: adminAccount.publicKey,
+        user: userAccount.publicKey,
+        loan: loanPda(market, loanId),
+        borrowerIndex,
+        marketConfig,
+        price: {
+          oracleProgram: program.programId,
+          priceFeed: mockPrice,
+        },
+        shrubUsdcAccount,
+      })
+      .signers([userAccount])
+      .rpc();
+  }
+
+  async function manageTiers(method: any) {
+    await method
+      .accounts({
+        admin: adminAccount.publicKey,
+        pdaAccount: shrubPda,
+        marketConfig,
+      })
+      .signers([adminAccount])
+      .rpc();
+  }
+
+  async function liquidateLoan(loanId: anchor.BN) {
+    await program.methods.liquidateLoan(loanId, MAX_REPAY)
+      .accounts({
+        pdaAccount: shrubPda,
+        admin: adminAccount.publicKey,
+        liquidator: liquidator.publicKey,
+        loan: loanPda(market, loanId),
+        borrower: userAccount.publicKey,
+        borrowerIndex,
+        price: {
+          oracleProgram: program.programId,
+          priceFeed: mockPrice,
+        },
+        liquidatorUsdcAccount,
+        shrubUsdcAccount,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([liquidator])
+      .rpc();
+  }
+
+  before(async function () {
+    market = await setupMarket();
+    ({ program, usdcMint, shrubPda, marketConfig, mockPrice, shrubUsdcAccount } = market);
+    adminAccount = market.admin;
+
+    userAccount = anchor.web3.Keypair.generate();
+    liquidator = anchor.web3.Keypair.generate();
+    for (const keypair of [userAccount, liquidator]) {
+      await fund(market, keypair);
+    }
+    borrowerIndex = borrowerIndexPda(market, userAccount.publicKey);
+    userUsdcAccount = await createUsdcAccount(market, userAccount.publicKey, 1_000_000);
+    liquidatorUsdcAccount = await createUsdcAccount(market, liquidator.publicKey, 10_000_000);
+
+    liquidatedLoanId = await takeLoan();
+    expiredLoanId = await takeLoan();
+    extendedLoanId = await takeLoan();
+    maturesAt = BigInt((await program.account.loan.fetch(loanPda(market, expiredLoanId))).maturesAt.toString());
+  });
+
+  it('sets the maturity from the term', async function () {
+    const loan = await program.account.loan.fetch(loanPda(market, liquidatedLoanId));
+    expect(loan.term).to.deep.equal({ oneMonth: {} });
+    expect(loan.maturesAt.sub(loan.createdAt).toNumber()).to.equal(30 * 86_400);
+  });
+
+  it('rejects a grace period above the maximum', async function () {
+    try {
+      await program.methods.setGracePeriod(31 * 86_400)
+        .accounts({
+          admin: adminAccount.publicKey,
+          pdaAccount: shrubPda,
+        })
+        .signers([adminAccount])
+        .rpc();
+      expect.fail("Expected error for grace period too long");
+    } catch (err: any) {
+      expect(err.message).to.include("Grace period too long");
+    }
+  });
+
+  it('sets the grace period', async function () {
+    expect((await program.account.dataAccount.fetch(shrubPda)).gracePeriod).to.equal(3 * 86_400);
+    await program.methods.setGracePeriod(GRACE_PERIOD)
+      .accounts({
+        admin: adminAccount.publicKey,
+        pdaAccount: shrubPda,
+      })
+      .signers([adminAccount])
+      .rpc();
+    expect((await program.account.dataAccount.fetch(shrubPda)).gracePeriod).to.equal(GRACE_PERIOD);
+  });
+
+  it('rejects expiring a loan before maturity', async function () {
+    try {
+      await expireLoan(expiredLoanId);
+      expect.fail("Expected error for loan not expired");
+    } catch (err: any) {
+      expect(err.message).to.include("Loan has not expired");
+    }
+  });
+
+  it('rejects extending a loan long before maturity', async function () {
+    await warpTo(-8 * 86_400);
+    await setMockPrice(market, 100_000_000);
+    try {
+      await extendLoan(extendedLoanId, { threeMonth: {} });
+      expect.fail("Expected error for extending too early");
+    } catch (err: any) {
+      expect(err.message).to.include("Loan can only be extended near maturity");
+    }
+  });
+
+  it('rejects extending a loan the collateral no longer covers', async function () {
+    await warpTo(0);
+    await setMockPrice(market, 100_000_000);
+    // 30 days of 8% interest on 1 USDC is 6,575, so the capitalized principal of 1,006,575
+    // needs 20,131,500 lamports at the 50% LTV
+    try {
+      await extendLoan(extendedLoanId, { threeMonth: {} });
+      expect.fail("Expected error for insufficient collateral");
+    } catch (err: any) {
+      expect(err.message).to.include("Insufficient collateral provided");
+    }
+  });
+
+  it('rejects extending a loan once its tier is disabled', async function () {
+    await addCollateral(extendedLoanId, 1_000_000);
+    await manageTiers(program.methods.disableTier(800));
+    try {
+      await extendLoan(extendedLoanId, { threeMonth: {} });
+      expect.fail("Expected error for a disabled tier");
+    } catch (err: any) {
+      expect(err.message).to.include("Invalid APY provided");
+    } finally {
+      await manageTiers(program.methods.enableTier(800));
+    }
+  });
+
+  it('rejects extending a loan beyond a tier LTV lowered since it was taken', async function () {
+    // At 40% LTV the capitalized principal of 1,006,575 needs 25,164,375 lamports
+    await manageTiers(program.methods.updateTier(800, 4000, 7000));
+    try {
+      await extendLoan(extendedLoanId, { threeMonth: {} });
+      expect.fail("Expected error for insufficient collateral");
+    } catch (err: any) {
+      expect(err.message).to.include("Insufficient collateral provided");
+    } finally {
+      await manageTiers(program.methods.updateTier(800, 5000, 7000));
+    }
+  });
+
+  it('extends a loan into a new term on its original terms, capitalizing the interest', async function () {
+    const before = await program.account.loan.fetch(loanPda(market, extendedLoanId));
+    await extendLoan(extendedLoanId, { threeMonth: {} });
+
+    const loan = await program.account.loan.fetch(loanPda(market, extendedLoanId));
+    expect(loan.term).to.deep.equal({ threeMonth: {} });
+    expect(loan.ltv).to.equal(5000);
+    expect(loan.originationPrice.toString()).to.equal(before.originationPrice.toString());
+    expect(BigInt(loan.maturesAt.toString())).to.equal(maturesAt + BigInt(90 * 86_400));
+    expect(loan.principal.toNumber()).to.equal(1_006_575);
+    expect(loan.accruedInterest.toNumber()).to.equal(0);
+    expect(loan.collateral.toNumber()).to.equal(21_000_000);
+  });
+
+  it('rejects expiring or liquidating a healthy loan within the grace period', async function () {
+    await warpTo(GRACE_PERIOD);
+    await setMockPrice(market, 100_000_000);
+    try {
+      await expireLoan(expiredLoanId);
+      expect.fail("Expected error for loan not expired");
+    } catch (err: any) {
+      expect(err.message).to.include("Loan has not expired");
+    }
+    try {
+      await liquidateLoan(liquidatedLoanId);
+      expect.fail("Expected error for healthy loan");
+    } catch (err: any) {
+      expect(err.message).to.include("Loan is not eligible for liquidation");
+    }
+  });
+
+  it('rejects repayment after the grace period', async function () {
+    await warpTo(GRACE_PERIOD + 1);
+    try {
+      await repayLoan(expiredLoanId);
+      expect.fail("Expected error for expired loan");
+    } catch (err: any) {
+      expect(err.message).to.include("Loan has expired");
+    }
+  });
+
+  it('does not expire an extended loan at its original maturity', async function () {
+    try {
+      await expireLoan(extendedLoanId);
+      expect.fail("Expected error for loan not expired");
+    } catch (err: any) {
+      expect(err.message).to.include("Loan has not expired");
+    }
+  });
+
+  it('liquidates the whole debt of an expired healthy loan', async function () {
+    await setMockPrice(market, 100_000_000);
+    const liquidatorUsdcBefore = await usdcBalance(market, liquidatorUsdcAccount);
+    const liquidatorSolBefore = await solBalance(market, liquidator.publicKey);
+    await liquidateLoan(liquidatedLoanId);
+
+    // 31 days and a second of 8% interest on 1 USDC is 6,794; the close factor does not apply
+    // and the liquidator receives the debt plus the 5% bonus in SOL at $100
+    expect(liquidatorUsdcBefore - await usdcBalance(market, liquidatorUsdcAccount)).to.equal(1_006_794n);
+    expect(await solBalance(market, liquidator.publicKey) - liquidatorSolBefore).to.equal(10_571_337n);
+    expect(await program.account.loan.fetchNullable(loanPda(market, liquidatedLoanId))).to.be.null;
+  });
+
+  it('auctions the collateral of an expired loan', async function () {
+    await expireLoan(expiredLoanId);
+
+    // The auction raises the 1,006,794 debt plus the default 5% penalty, whatever the
+    // market's liquidation mode
+    const auction = await program.account.auction.fetch(auctionPda(market, expiredLoanId));
+    expect(auction.starter.toString()).to.equal(liquidator.publicKey.toString());
+    expect(auction.startPrice.toNumber()).to.equal(100_000_000);
+    expect(auction.debt.toNumber()).to.equal(1_006_794);
+    expect(auction.penalty.toNumber()).to.equal(50_339);
+    expect(auction.collateralRemaining.toNumber()).to.equal(20_000_000);
+    const loan = await program.account.loan.fetch(loanPda(market, expiredLoanId));
+    expect(loan.inAuction).to.equal(true);
+  });
+
+  it('returns the unsold collateral of an expired loan to the borrower', async function () {
+    const loanRent = await rent(market, program.account.loan.size);
+    const auctionRent = await rent(market, program.account.auction.size);
+    const userSolBefore = await solBalance(market, userAccount.publicKey);
+    const liquidatorSolBefore = await solBalance(market, liquidator.publicKey);
+    const liquidatorUsdcBefore = await usdcBalance(market, liquidatorUsdcAccount);
+    const indexBefore = await program.account.borrowerIndex.fetch(borrowerIndex);
+
+    // At the undiscounted $100, the 1,057,133 target buys 0.01057133 SOL of the 0.02 bid
+    await fillAuction(expiredLoanId, 20_000_000);
+    expect(liquidatorUsdcBefore - await usdcBalance(market, liquidatorUsdcAccount)).to.equal(1_057_133n);
+    expect(await solBalance(market, liquidator.publicKey) - liquidatorSolBefore)
+      .to.equal(10_571_330n + auctionRent);
+
+    // The rest of the collateral goes back to the borrower, along with the loan account's rent
+    expect(await solBalance(market, userAccount.publicKey) - userSolBefore).to.equal(9_428_670n + loanRent);
+    expect(await program.account.loan.fetchNullable(loanPda(market, expiredLoanId))).to.be.null;
+    const indexAfter = await program.account.borrowerIndex.fetch(borrowerIndex);
+    expect(indexAfter.openLoanIds.map((id) => id.toNumber())).to.not.include(expiredLoanId.toNumber());
+    expect(indexBefore.totalCollateral.sub(indexAfter.totalCollateral).toNumber()).to.equal(20_000_000);
+  });
+});
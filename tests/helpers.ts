@@ -0,0 +1,208 @@
+// helpers.ts (shared setup for the bankrun tests)
+//
+// Starts bankrun with a funded market priced by the mock oracle, and wraps the account and
+// clock plumbing the bankrun tests share.
+import * as anchor from "@coral-xyz/anchor";
+import { startAnchor, Clock, ProgramTestContext } from "solana-bankrun";
+import { BankrunProvider } from "anchor-bankrun";
+import { RadarLend } from "../target/types/radar_lend";
+import {
+  TOKEN_PROGRAM_ID,
+  ASSOCIATED_TOKEN_PROGRAM_ID,
+  AccountLayout,
+  MINT_SIZE,
+  createInitializeMint2Instruction,
+  createAssociatedTokenAccountInstruction,
+  createMintToInstruction,
+  getAssociatedTokenAddressSync,
+} from '@solana/spl-token';
+
+const { web3 } = anchor;
+export const SYSTEM_PROGRAM = web3.SystemProgram.programId;
+export const DAY = 86_400;
+export const MAX_REPAY = new anchor.BN("18446744073709551615");
+
+// A market set up on bankrun by `setupMarket`
+export interface Market {
+  context: ProgramTestContext;
+  provider: BankrunProvider;
+  program: anchor.Program<RadarLend>;
+  admin: anchor.web3.Keypair;
+  usdcMint: anchor.web3.PublicKey;
+  shrubPda: anchor.web3.PublicKey;
+  marketConfig: anchor.web3.PublicKey;
+  mockPrice: anchor.web3.PublicKey;
+  shrubUsdcAccount: anchor.web3.PublicKey;
+}
+
+export function pda(market: Market, seeds: Buffer[]): anchor.web3.PublicKey {
+  return anchor.web3.PublicKey.findProgramAddressSync(seeds, market.program.programId)[0];
+}
+
+export function loanPda(market: Market, id: anchor.BN): anchor.web3.PublicKey {
+  return pda(market, [Buffer.from("loan"), market.shrubPda.toBuffer(), id.toArrayLike(Buffer, "le", 8)]);
+}
+
+export function auctionPda(market: Market, id: anchor.BN): anchor.web3.PublicKey {
+  return pda(market, [Buffer.from("auction"), loanPda(market, id).toBuffer()]);
+}
+
+export function borrowerIndexPda(market: Market, borrower: anchor.web3.PublicKey): anchor.web3.PublicKey {
+  return pda(market, [Buffer.from("borrower"), market.shrubPda.toBuffer(), borrower.toBuffer()]);
+}
+
+export async function fund(market: Market, keypair: anchor.web3.Keypair) {
+  market.context.setAccount(keypair.publicKey, {
+    lamports: 10_000_000_000,
+    data: Buffer.alloc(0),
+    owner: SYSTEM_PROGRAM,
+    executable: false,
+  });
+}
+
+export async function rent(market: Market, size: number): Promise<bigint> {
+  return (await market.context.banksClient.getRent()).minimumBalance(BigInt(size));
+}
+
+// Creates a 6-decimal mint with the admin as mint authority
+export async function createMint(market: Market): Promise<anchor.web3.PublicKey> {
+  const mint = anchor.web3.Keypair.generate();
+  await market.provider.sendAndConfirm(new web3.Transaction().add(
+    web3.SystemProgram.createAccount({
+      fromPubkey: market.context.payer.publicKey,
+      newAccountPubkey: mint.publicKey,
+      space: MINT_SIZE,
+      lamports: Number(await rent(market, MINT_SIZE)),
+      programId: TOKEN_PROGRAM_ID,
+    }),
+    createInitializeMint2Instruction(mint.publicKey, 6, market.admin.publicKey, null),
+  ), [mint]);
+  return mint.publicKey;
+}
+
+// Creates `owner`'s associated account for `mint`, USDC by default, holding `amount`
+export async function createUsdcAccount(
+  market: Market,
+  owner: anchor.web3.PublicKey,
+  amount: number = 0,
+  mint: anchor.web3.PublicKey = market.usdcMint
+): Promise<anchor.web3.PublicKey> {
+  const address = getAssociatedTokenAddressSync(mint, owner, true);
+  const tx = new web3.Transaction().add(
+    createAssociatedTokenAccountInstruction(market.context.payer.publicKey, address, owner, mint)
+  );
+  if (amount > 0) {
+    tx.add(createMintToInstruction(mint, address, market.admin.publicKey, amount));
+  }
+  await market.provider.sendAndConfirm(tx, amount > 0 ? [market.admin] : []);
+  return address;
+}
+
+export async function usdcBalance(market: Market, address: anchor.web3.PublicKey): Promise<bigint> {
+  const account = await market.context.banksClient.getAccount(address);
+  return AccountLayout.decode(Buffer.from(account!.data)).amount;
+}
+
+export async function solBalance(market: Market, address: anchor.web3.PublicKey): Promise<bigint> {
+  return market.context.banksClient.getBalance(address);
+}
+
+// Moves the clock to `timestamp`, on a new slot
+export async function setTime(market: Market, timestamp: bigint) {
+  const clock = await market.context.banksClient.getClock();
+  market.context.warpToSlot(clock.slot + 1n);
+  market.context.setClock(new Clock(
+    clock.slot + 1n,
+    clock.epochStartTimestamp,
+    clock.epoch,
+    clock.leaderScheduleEpoch,
+    timestamp,
+  ));
+}
+
+export async function setMockPrice(market: Market, price: number) {
+  await market.program.methods.setMockPrice(new anchor.BN(price), new anchor.BN(0))
+    .accounts({
+      admin: market.admin.publicKey,
+      pdaAccount: market.shrubPda,
+      mockPrice: market.mockPrice,
+      systemProgram: SYSTEM_PROGRAM,
+    })
+    .signers([market.admin])
+    .rpc();
+}
+
+// Starts bankrun and sets up a market holding `deposit` USDC, priced by the mock oracle at
+// $100/SOL
+export async function setupMarket(deposit: number = 1_000_000_000): Promise<Market> {
+  const context = await startAnchor("", [], []);
+  const provider = new BankrunProvider(context);
+  const program = new anchor.Program<RadarLend>(anchor.workspace.RadarLend.idl, provider);
+  const admin = anchor.web3.Keypair.generate();
+  const [shrubPda] = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("shrub"), admin.publicKey.toBuffer()],
+    program.programId
+  );
+  const [marketConfig] = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("market_config"), shrubPda.toBuffer()],
+    program.programId
+  );
+  const [mockPrice] = anchor.web3.PublicKey.findProgramAddressSync(
+    [Buffer.from("mock_price"), shrubPda.toBuffer()],
+    program.programId
+  );
+  // The mint and pool account are filled in once the mint exists
+  const market: Market = {
+    context,
+    provider,
+    program,
+    admin,
+    usdcMint: web3.PublicKey.default,
+    shrubPda,
+    marketConfig,
+    mockPrice,
+    shrubUsdcAccount: web3.PublicKey.default,
+  };
+  await fund(market, admin);
+
+  // Create the USDC mint and token accounts
+  market.usdcMint = await createMint(market);
+  const adminUsdcAccount = await createUsdcAccount(market, admin.publicKey, deposit);
+  market.shrubUsdcAccount = getAssociatedTokenAddressSync(market.usdcMint, market.shrubPda, true);
+
+  await program.methods.initialize()
+    .accounts({
+      admin: admin.publicKey,
+      pdaAccount: market.shrubPda,
+      marketConfig: market.marketConfig,
+      systemProgram: SYSTEM_PROGRAM,
+      shrubUsdcAccount: market.shrubUsdcAccount,
+      usdcMint: market.usdcMint,
+      tokenProgram: TOKEN_PROGRAM_ID,
+      associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+    })
+    .signers([admin])
+    .rpc();
+  await program.methods.depositUsdc(new anchor.BN(deposit))
+    .accounts({
+      admin: admin.publicKey,
+      pdaAccount: market.shrubPda,
+      adminUsdcAccount,
+      shrubUsdcAccount: market.shrubUsdcAccount,
+      tokenProgram: TOKEN_PROGRAM_ID,
+    })
+    .signers([admin])
+    .rpc();
+  await setMockPrice(market, 100_000_000);
+  await program.methods.setOracle({ mock: {} }, 300)
+    .accounts({
+      admin: admin.publicKey,
+      pdaAccount: market.shrubPda,
+      oracleProgram: program.programId,
+      priceFeed: market.mockPrice,
+    })
+    .signers([admin])
+    .rpc();
+
+  return market;
+}
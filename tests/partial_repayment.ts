@@ -0,0 +1,197 @@
+// partial_repayment.ts (partial repayment tests)
+//
+// These run against bankrun rather than the local validator so interest accrues over an
+// exact time.
+import * as anchor from "@coral-xyz/anchor";
+import { expect } from 'chai';
+import { RadarLend } from "../target/types/radar_lend";
+import { TOKEN_PROGRAM_ID, ASSOCIATED_TOKEN_PROGRAM_ID } from '@solana/spl-token';
+import {
+  DAY,
+  Market,
+  SYSTEM_PROGRAM,
+  borrowerIndexPda,
+  createMint,
+  createUsdcAccount,
+  fund,
+  loanPda,
+  setMockPrice,
+  setTime,
+  setupMarket,
+  solBalance,
+  usdcBalance,
+} from "./helpers";
+
+describe('partial repayment', function () {
+  this.timeout(20000);
+
+  let market: Market;
+  let program: anchor.Program<RadarLend>;
+
+  let adminAccount: anchor.web3.Keypair;
+  let userAccount: anchor.web3.Keypair;
+  let usdcMint: anchor.web3.PublicKey;
+  let shrubPda: anchor.web3.PublicKey;
+  let marketConfig: anchor.web3.PublicKey;
+  let borrowerIndex: anchor.web3.PublicKey;
+  let mockPrice: anchor.web3.PublicKey;
+  let shrubUsdcAccount: anchor.web3.PublicKey;
+  let userUsdcAccount: anchor.web3.PublicKey;
+  let loanId: anchor.BN;
+  let createdAt: bigint;
+
+  // Moves the clock to `seconds` after the loan was taken, on a new slot
+  async function warpTo(seconds: number) {
+    await setTime(market, createdAt + BigInt(seconds));
+  }
+
+  async function repayPartial(
+    amount: number,
+    signer: anchor.web3.Keypair = userAccount,
+    usdcAccount: anchor.web3.PublicKey = userUsdcAccount
+  ) {
+    await program.methods.repayPartial(loanId, new anchor.BN(amount))
+      .accounts({
+        pdaAccount: shrubPda,
+        admin: adminAccount.publicKey,
+        user: signer.publicKey,
+        loan: loanPda(market, loanId),
+        borrowerIndex,
+        price: {
+          oracleProgram: program.programId,
+          priceFeed: mockPrice,
+        },
+        userUsdcAccount: usdcAccount,
+        shrubUsdcAccount,
+        tokenProgram: TOKEN_PROGRAM_ID,
+      })
+      .signers([signer])
+      .rpc();
+  }
+
+  before(async function () {
+    market = await setupMarket();
+    ({ program, usdcMint, shrubPda, marketConfig, mockPrice, shrubUsdcAccount } = market);
+    adminAccount = market.admin;
+
+    userAccount = anchor.web3.Keypair.generate();
+    await fund(market, userAccount);
+    borrowerIndex = borrowerIndexPda(market, userAccount.publicKey);
+    userUsdcAccount = await createUsdcAccount(market, userAccount.publicKey, 1_000_000);
+
+    // 1 USDC against 0.02 SOL ($2 at $100/SOL): exactly the 50% LTV of the 8% tier
+    loanId = (await program.account.dataAccount.fetch(shrubPda)).nextLoanId;
+    await program.methods.takeLoan(new anchor.BN(1_000_000), 800, new anchor.BN(20_000_000), { oneMonth: {} })
+      .accounts({
+        pdaAccount: shrubPda,
+        admin: adminAccount.publicKey,
+        user: userAccount.publicKey,
+        loan: loanPda(market, loanId),
+        borrowerIndex,
+        marketConfig,
+        price: {
+          oracleProgram: program.programId,
+          priceFeed: mockPrice,
+        },
+        userUsdcAccount,
+        shrubUsdcAccount,
+        usdcMint,
+        systemProgram: SYSTEM_PROGRAM,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([userAccount])
+      .rpc();
+    createdAt = BigInt((await program.account.loan.fetch(loanPda(market, loanId))).createdAt.toString());
+  });
+
+  it('rejects repaying nothing or the whole debt', async function () {
+    // 20 days of 8% interest on 1 USDC is 4,383
+    await warpTo(20 * DAY);
+    await setMockPrice(market, 100_000_000);
+    for (const amount of [0, 1_004_383]) {
+      try {
+        await repayPartial(amount);
+        expect.fail("Expected error for invalid repayment amount");
+      } catch (err: any) {
+        expect(err.message).to.include("Partial repayment must be positive and below the debt");
+      }
+    }
+  });
+
+  it('prevents non-borrowers from repaying part of a loan', async function () {
+    const other = anchor.web3.Keypair.generate();
+    await fund(market, other);
+    try {
+      await repayPartial(100_000, other);
+      expect.fail("Expected error for unauthorized repayment");
+    } catch (err: any) {
+      expect(err.message).to.include("Unauthorized");
+    }
+  });
+
+  it('rejects repaying from a token account of another mint', async function () {
+    const otherMint = await createMint(market);
+    const otherUsdcAccount = await createUsdcAccount(market, userAccount.publicKey, 1_000_000, otherMint);
+
+    try {
+      await repayPartial(100_000, userAccount, otherUsdcAccount);
+      expect.fail("Expected error for a token account of another mint");
+    } catch (err: any) {
+      expect(err.message).to.include("A token mint constraint was violated");
+    }
+
+    const loan = await program.account.loan.fetch(loanPda(market, loanId));
+    expect(loan.principal.toNumber()).to.equal(1_000_000);
+  });
+
+  it('pays interest first and releases collateral in proportion to the principal', async function () {
+    const usdcBefore = await usdcBalance(market, userUsdcAccount);
+    const solBefore = await solBalance(market, userAccount.publicKey);
+    const indexBefore = await program.account.borrowerIndex.fetch(borrowerIndex);
+
+    // 4,383 of interest and then half the principal, plus half the default 5% penalty APY
+    // of 1,369 for the other 10 days of the early-repayment window
+    await repayPartial(504_383);
+
+    expect(usdcBefore - await usdcBalance(market, userUsdcAccount)).to.equal(504_383n + 685n);
+    expect(await solBalance(market, userAccount.publicKey) - solBefore).to.equal(10_000_000n);
+    const loan = await program.account.loan.fetch(loanPda(market, loanId));
+    expect(loan.principal.toNumber()).to.equal(500_000);
+    expect(loan.accruedInterest.toNumber()).to.equal(0);
+    expect(loan.collateral.toNumber()).to.equal(10_000_000);
+    const indexAfter = await program.account.borrowerIndex.fetch(borrowerIndex);
+    expect(indexBefore.outstandingPrincipal.sub(indexAfter.outstandingPrincipal).toNumber()).to.equal(500_000);
+    expect(indexBefore.totalCollateral.sub(indexAfter.totalCollateral).toNumber()).to.equal(10_000_000);
+  });
+
+  it('keeps the collateral the remaining debt requires', async function () {
+    // At $80/SOL the remaining 0.4 USDC needs all 0.01 SOL at the 50% LTV
+    await setMockPrice(market, 80_000_000);
+    const solBefore = await solBalance(market, userAccount.publicKey);
+
+    await repayPartial(100_000);
+
+    expect(await solBalance(market, userAccount.publicKey)).to.equal(solBefore);
+    const loan = await program.account.loan.fetch(loanPda(market, loanId));
+    expect(loan.principal.toNumber()).to.equal(400_000);
+    expect(loan.collateral.toNumber()).to.equal(10_000_000);
+  });
+
+  it('accepts a repayment without a usable price but releases no collateral', async function () {
+    // The mock price was last set on day 20, so it is stale a day later
+    await warpTo(21 * DAY);
+    const solBefore = await solBalance(market, userAccount.publicKey);
+    const usdcBefore = await usdcBalance(market, userUsdcAccount);
+
+    // A day of interest on 0.4 USDC is 87, leaving 99,913 for principal. The 5% penalty APY on
+    // 0.4 USDC for the other 9 days of the window is 493, of which that principal's share is 124
+    await repayPartial(100_000);
+
+    expect(usdcBefore - await usdcBalance(market, userUsdcAccount)).to.equal(100_000n + 124n);
+    expect(await solBalance(market, userAccount.publicKey)).to.equal(solBefore);
+    const loan = await program.account.loan.fetch(loanPda(market, loanId));
+    expect(loan.principal.toNumber()).to.equal(300_087);
+    expect(loan.collateral.toNumber()).to.equal(10_000_000);
+  });
+});
@@ -0,0 +1,141 @@
+// price_history.ts (price history tests)
+//
+// These run against bankrun rather than the local validator so the price history can be
+// cranked past its capacity at exact intervals.
+import * as anchor from "@coral-xyz/anchor";
+import { expect } from 'chai';
+import { RadarLend } from "../target/types/radar_lend";
+import { TOKEN_PROGRAM_ID, ASSOCIATED_TOKEN_PROGRAM_ID } from '@solana/spl-token';
+import {
+  Market,
+  SYSTEM_PROGRAM,
+  borrowerIndexPda,
+  createUsdcAccount,
+  fund,
+  loanPda,
+  pda,
+  setMockPrice,
+  setTime,
+  setupMarket,
+} from "./helpers";
+
+const PRICE_HISTORY_CAPACITY = 32;
+// Spreads the 31 gaps of a full history over 10 seconds each
+const TWAP_WINDOW = 310;
+
+describe('price history', function () {
+  this.timeout(20000);
+
+  let market: Market;
+  let program: anchor.Program<RadarLend>;
+
+  let adminAccount: anchor.web3.Keypair;
+  let userAccount: anchor.web3.Keypair;
+  let usdcMint: anchor.web3.PublicKey;
+  let shrubPda: anchor.web3.PublicKey;
+  let marketConfig: anchor.web3.PublicKey;
+  let borrowerIndex: anchor.web3.PublicKey;
+  let mockPrice: anchor.web3.PublicKey;
+  let shrubUsdcAccount: anchor.web3.PublicKey;
+  let userUsdcAccount: anchor.web3.PublicKey;
+  let priceHistory: anchor.web3.PublicKey;
+  let startedAt: bigint;
+
+  // Moves the clock to `seconds` after the first crank, on a new slot
+  async function warpTo(seconds: number) {
+    await setTime(market, startedAt + BigInt(seconds));
+  }
+
+  // Refreshes the mock price at the current time and records it
+  async function crankPrice() {
+    await setMockPrice(market, 100_000_000);
+    await program.methods.crankPrice()
+      .accounts({
+        cranker: userAccount.publicKey,
+        pdaAccount: shrubPda,
+        admin: adminAccount.publicKey,
+        priceHistory,
+        oracleProgram: program.programId,
+        priceFeed: mockPrice,
+        systemProgram: SYSTEM_PROGRAM,
+      })
+      .signers([userAccount])
+      .rpc();
+  }
+
+  before(async function () {
+    market = await setupMarket();
+    ({ program, usdcMint, shrubPda, marketConfig, mockPrice, shrubUsdcAccount } = market);
+    adminAccount = market.admin;
+
+    userAccount = anchor.web3.Keypair.generate();
+    await fund(market, userAccount);
+    borrowerIndex = borrowerIndexPda(market, userAccount.publicKey);
+    userUsdcAccount = await createUsdcAccount(market, userAccount.publicKey, 1_000_000);
+    priceHistory = pda(market, [Buffer.from("price_history"), shrubPda.toBuffer()]);
+
+    await program.methods.setPriceMode({ minSpotTwap: {} }, TWAP_WINDOW)
+      .accounts({
+        admin: adminAccount.publicKey,
+        pdaAccount: shrubPda,
+      })
+      .signers([adminAccount])
+      .rpc();
+    startedAt = (await market.context.banksClient.getClock()).unixTimestamp;
+  });
+
+  it('spaces cranks so a full history spans the TWAP window', async function () {
+    await warpTo(0);
+    await crankPrice();
+
+    // A 310-second window over 31 gaps needs at least 10 seconds between cranks
+    await warpTo(9);
+    try {
+      await crankPrice();
+      expect.fail("Expected error for a crank within the minimum interval");
+    } catch (err: any) {
+      expect(err.message).to.include("Price was cranked too recently");
+    }
+
+    // Wrap the buffer, overwriting the oldest observations
+    for (let i = 1; i <= PRICE_HISTORY_CAPACITY + 8; i++) {
+      await warpTo(10 * i);
+      await crankPrice();
+    }
+
+    const history = await program.account.priceHistory.fetch(priceHistory);
+    expect(history.len).to.equal(PRICE_HISTORY_CAPACITY);
+    const timestamps = history.observations.map((obs: any) => obs.timestamp.toNumber());
+    expect(Math.max(...timestamps) - Math.min(...timestamps)).to.be.at.least(TWAP_WINDOW);
+  });
+
+  it('prices loans from the TWAP of a wrapped history', async function () {
+    // 1 USDC against 0.02 SOL ($2 at $100/SOL): exactly the 50% LTV of the 8% tier
+    const loanId = (await program.account.dataAccount.fetch(shrubPda)).nextLoanId;
+    await program.methods.takeLoan(new anchor.BN(1_000_000), 800, new anchor.BN(20_000_000), { oneMonth: {} })
+      .accounts({
+        pdaAccount: shrubPda,
+        admin: adminAccount.publicKey,
+        user: userAccount.publicKey,
+        loan: loanPda(market, loanId),
+        borrowerIndex,
+        marketConfig,
+        price: {
+          oracleProgram: program.programId,
+          priceFeed: mockPrice,
+          priceHistory,
+        },
+        userUsdcAccount,
+        shrubUsdcAccount,
+        usdcMint,
+        systemProgram: SYSTEM_PROGRAM,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([userAccount])
+      .rpc();
+
+    const loan = await program.account.loan.fetch(loanPda(market, loanId));
+    expect(loan.principal.toNumber()).to.equal(1_000_000);
+  });
+});
@@ -2,6 +2,7 @@
 import * as anchor from "@coral-xyz/anchor";
 import { expect } from 'chai';
 import { RadarLend } from "../target/types/radar_lend";
+import { MockChainlink } from "../target/types/mock_chainlink";
 import {
   TOKEN_PROGRAM_ID,
   createMint,
@@ -23,6 +24,10 @@ describe('radar-lend', function () { // Changed to regular function
   anchor.setProvider(provider);
 
   const program = anchor.workspace.RadarLend as anchor.Program<RadarLend>
+  const mockChainlink = anchor.workspace.MockChainlink as anchor.Program<MockChainlink>
+
+  const SOL_PRICE = new anchor.BN(100_00000000); // 100 USD with 8 decimals, as reported by Chainlink
+  const now = () => Math.floor(Date.now() / 1000);
 
   let shrubPda: anchor.web3.PublicKey;
   let shrubBump: number;
@@ -32,6 +37,58 @@ describe('radar-lend', function () { // Changed to regular function
   let adminUsdcAccount: anchor.web3.PublicKey;
   let shrubUsdcAccount: anchor.web3.PublicKey;
   let userUsdcAccount: anchor.web3.PublicKey;
+  let chainlinkFeed: anchor.web3.Keypair;
+  let mockPrice: anchor.web3.PublicKey;
+  let priceHistory: anchor.web3.PublicKey;
+  let borrowerIndex: anchor.web3.PublicKey;
+  let marketConfig: anchor.web3.PublicKey;
+
+  function loanPda(loanId: anchor.BN | number): anchor.web3.PublicKey {
+    return anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("loan"), shrubPda.toBuffer(), new anchor.BN(loanId).toArrayLike(Buffer, "le", 8)],
+      program.programId
+    )[0];
+  }
+
+  async function nextLoanPda(): Promise<anchor.web3.PublicKey> {
+    const pdaAccountData = await program.account.dataAccount.fetch(shrubPda);
+    return loanPda(pdaAccountData.nextLoanId);
+  }
+
+  async function setOracle(source: any, oracleProgram: anchor.web3.PublicKey, priceFeed: anchor.web3.PublicKey) {
+    await program.methods.setOracle(source, 300)
+      .accounts({
+        admin: adminAccount.publicKey,
+        pdaAccount: shrubPda,
+        oracleProgram,
+        priceFeed,
+      })
+      .signers([adminAccount])
+      .rpc();
+  }
+
+  async function setSolPrice(answer: anchor.BN, timestamp: number = now()) {
+    await mockChainlink.methods.setRound(answer, timestamp)
+      .accounts({
+        authority: adminAccount.publicKey,
+        feed: chainlinkFeed.publicKey,
+      })
+      .signers([adminAccount])
+      .rpc();
+  }
+
+  // The LoanClosed event emitted by the confirmed transaction `signature`
+  async function loanClosedEvent(signature: string): Promise<any> {
+    const transaction = await provider.connection.getTransaction(signature, {
+      commitment: "confirmed",
+      maxSupportedTransactionVersion: 0,
+    });
+    const parser = new anchor.EventParser(program.programId, new anchor.BorshCoder(program.idl));
+    const events = [...parser.parseLogs(transaction.meta.logMessages)]
+      .filter((event) => event.name === "loanClosed");
+    expect(events).to.have.lengthOf(1);
+    return events[0].data;
+  }
 
   before(async function () { // Changed to regular function
     this.timeout(20000); // Set timeout to 20 seconds for setup
@@ -63,6 +120,23 @@ describe('radar-lend', function () { // Changed to regular function
     shrubPda = shrubFindAddressArr[0];
     shrubBump = shrubFindAddressArr[1];
 
+    mockPrice = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("mock_price"), shrubPda.toBuffer()],
+      program.programId
+    )[0];
+    priceHistory = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("price_history"), shrubPda.toBuffer()],
+      program.programId
+    )[0];
+    marketConfig = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("market_config"), shrubPda.toBuffer()],
+      program.programId
+    )[0];
+    borrowerIndex = anchor.web3.PublicKey.findProgramAddressSync(
+      [Buffer.from("borrower"), shrubPda.toBuffer(), userAccount.publicKey.toBuffer()],
+      program.programId
+    )[0];
+
     // Create USDC Mint and Associated Token Accounts
     usdcMint = await createMint(
       provider.connection,
@@ -93,6 +167,18 @@ describe('radar-lend', function () { // Changed to regular function
       shrubPda,
       true
     );
+
+    // Create a mock Chainlink SOL/USD feed
+    chainlinkFeed = anchor.web3.Keypair.generate();
+    await mockChainlink.methods.createFeed(8, "SOL / USD")
+      .accounts({
+        authority: adminAccount.publicKey,
+        feed: chainlinkFeed.publicKey,
+        systemProgram: SYSTEM_PROGRAM,
+      })
+      .signers([adminAccount, chainlinkFeed])
+      .rpc();
+    await setSolPrice(SOL_PRICE);
   });
 
   describe('basics', function () { // Changed to regular function
@@ -108,6 +194,7 @@ describe('radar-lend', function () { // Changed to regular function
         .accounts({
           admin: adminAccount.publicKey,
           pdaAccount: shrubPda,
+          marketConfig,
           systemProgram: web3.SystemProgram.programId,
           shrubUsdcAccount,
           usdcMint,
@@ -122,6 +209,117 @@ describe('radar-lend', function () { // Changed to regular function
       expect(pdaUsdcAccount.owner.toString()).to.equal(shrubPda.toString());
       expect(pdaUsdcAccount.mint.toString()).to.equal(usdcMint.toString());
       expect(pdaUsdcAccount.amount.toString()).to.equal("0");
+
+      const pdaAccountData = await program.account.dataAccount.fetch(shrubPda);
+      expect(pdaAccountData.usdcMint.toString()).to.equal(usdcMint.toString());
+    });
+
+    it('sets the oracle', async function () {
+      await program.methods.setOracle({ chainlink: {} }, 300)
+        .accounts({
+          admin: adminAccount.publicKey,
+          pdaAccount: shrubPda,
+          oracleProgram: mockChainlink.programId,
+          priceFeed: chainlinkFeed.publicKey,
+        })
+        .signers([adminAccount])
+        .rpc();
+
+      const pdaAccountData = await program.account.dataAccount.fetch(shrubPda);
+      expect(pdaAccountData.oracle.source).to.deep.equal({ chainlink: {} });
+      expect(pdaAccountData.oracle.feed.toString()).to.equal(chainlinkFeed.publicKey.toString());
+      expect(pdaAccountData.oracle.program.toString()).to.equal(mockChainlink.programId.toString());
+      expect(pdaAccountData.oracle.maxPriceAge).to.equal(300);
+    });
+  })
+
+  describe('market config', function () {
+    // A 5% penalty APY for the rest of the first 30 days
+    const DEFAULT_EARLY_REPAYMENT = { penaltyApy: { window: 30 * 86_400, apy: 500 } };
+
+    async function manageTiers(method: any, signer: anchor.web3.Keypair = adminAccount) {
+      await method
+        .accounts({
+          admin: signer.publicKey,
+          pdaAccount: shrubPda,
+          marketConfig,
+        })
+        .signers([signer])
+        .rpc();
+    }
+
+    it('seeds the default tiers', async function () {
+      const config = await program.account.marketConfig.fetch(marketConfig);
+      expect(config.market.toString()).to.equal(shrubPda.toString());
+      expect(config.version).to.equal(0);
+      expect(config.tiers.map((tier) => [tier.apy, tier.ltv, tier.liquidationThreshold, tier.enabled])).to.deep.equal([
+        [800, 5000, 7000, true],
+        [500, 3300, 5300, true],
+        [100, 2500, 4500, true],
+        [0, 2000, 4000, true],
+      ]);
+    });
+
+    it('adds a tier', async function () {
+      await manageTiers(program.methods.addTier(300, 3000, 5000));
+
+      const config = await program.account.marketConfig.fetch(marketConfig);
+      expect(config.version).to.equal(1);
+      expect(config.tiers).to.have.length(5);
+      expect(config.tiers[4]).to.deep.equal({ apy: 300, ltv: 3000, liquidationThreshold: 5000, enabled: true, earlyRepayment: DEFAULT_EARLY_REPAYMENT });
+    });
+
+    it('rejects a duplicate tier', async function () {
+      try {
+        await manageTiers(program.methods.addTier(300, 3000, 5000));
+        expect.fail("Expected error for duplicate tier");
+      } catch (error: any) {
+        expect(error.message).to.include("Tier already exists");
+      }
+    });
+
+    it('updates a tier', async function () {
+      await manageTiers(program.methods.updateTier(300, 2800, 4800));
+
+      const config = await program.account.marketConfig.fetch(marketConfig);
+      expect(config.version).to.equal(2);
+      expect(config.tiers[4]).to.deep.equal({ apy: 300, ltv: 2800, liquidationThreshold: 4800, enabled: true, earlyRepayment: DEFAULT_EARLY_REPAYMENT });
+    });
+
+    it('rejects an LTV at or above the liquidation threshold', async function () {
+      try {
+        await manageTiers(program.methods.updateTier(300, 5000, 5000));
+        expect.fail("Expected error for invalid tier");
+      } catch (error: any) {
+        expect(error.message).to.include("LTV must be positive and below the liquidation threshold");
+      }
+    });
+
+    it('disables a tier', async function () {
+      await manageTiers(program.methods.disableTier(300));
+
+      const config = await program.account.marketConfig.fetch(marketConfig);
+      expect(config.version).to.equal(3);
+      expect(config.tiers[4].enabled).to.equal(false);
+    });
+
+    it('enables a disabled tier', async function () {
+      await manageTiers(program.methods.enableTier(300));
+
+      const config = await program.account.marketConfig.fetch(marketConfig);
+      expect(config.version).to.equal(4);
+      expect(config.tiers[4].enabled).to.equal(true);
+
+      await manageTiers(program.methods.disableTier(300));
+    });
+
+    it('only the admin can manage tiers', async function () {
+      try {
+        await manageTiers(program.methods.addTier(200, 2200, 4200), userAccount);
+        expect.fail("Expected error for non-admin");
+      } catch (error: any) {
+        expect(error.message).to.not.include("Expected error");
+      }
     });
   })
 
@@ -193,11 +391,18 @@ describe('radar-lend', function () { // Changed to regular function
     describe('take_loan', function () { // Changed to regular function
       it('throws an error when insufficient collateral', async function () { // Changed to regular function
         try {
-          await program.methods.takeLoan(new anchor.BN(1_000_000_000), 800, new anchor.BN(4_000_000_000)) // Attempting loan with insufficient collateral
+          await program.methods.takeLoan(new anchor.BN(1_000_000_000), 800, new anchor.BN(4_000_000_000), { oneMonth: {} }) // Attempting loan with insufficient collateral
             .accounts({
               pdaAccount: shrubPda,
               admin: adminAccount.publicKey,
               user: userAccount.publicKey,
+              loan: await nextLoanPda(),
+              borrowerIndex,
+              marketConfig,
+              price: {
+                oracleProgram: mockChainlink.programId,
+                priceFeed: chainlinkFeed.publicKey,
+              },
               userUsdcAccount,
               shrubUsdcAccount,
               usdcMint,
@@ -215,11 +420,18 @@ describe('radar-lend', function () { // Changed to regular function
 
       it('throws an error when invalid apy specified', async function () { // Changed to regular function
         try {
-          await program.methods.takeLoan(new anchor.BN(1_000_000), 999, new anchor.BN(2_000_000_000)) // Invalid APY
+          await program.methods.takeLoan(new anchor.BN(1_000_000), 999, new anchor.BN(2_000_000_000), { oneMonth: {} }) // Invalid APY
             .accounts({
               pdaAccount: shrubPda,
               admin: adminAccount.publicKey,
               user: userAccount.publicKey,
+              loan: await nextLoanPda(),
+              borrowerIndex,
+              marketConfig,
+              price: {
+                oracleProgram: mockChainlink.programId,
+                priceFeed: chainlinkFeed.publicKey,
+              },
               userUsdcAccount,
               shrubUsdcAccount,
               usdcMint,
@@ -235,6 +447,35 @@ describe('radar-lend', function () { // Changed to regular function
         }
       });
 
+      it('throws an error when the tier is disabled', async function () {
+        try {
+          await program.methods.takeLoan(new anchor.BN(1_000_000), 300, new anchor.BN(4_000_000_000), { oneMonth: {} }) // Disabled tier
+            .accounts({
+              pdaAccount: shrubPda,
+              admin: adminAccount.publicKey,
+              user: userAccount.publicKey,
+              loan: await nextLoanPda(),
+              borrowerIndex,
+              marketConfig,
+              price: {
+                oracleProgram: mockChainlink.programId,
+                priceFeed: chainlinkFeed.publicKey,
+              },
+              userUsdcAccount,
+              shrubUsdcAccount,
+              usdcMint,
+              systemProgram: SYSTEM_PROGRAM,
+              tokenProgram: TOKEN_PROGRAM_ID,
+              associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+            })
+            .signers([userAccount])
+            .rpc();
+          expect.fail("Expected error for disabled tier");
+        } catch (err: any) {
+          expect(err.message).to.include("Invalid APY provided");
+        }
+      });
+
       it('successfully takes a loan with 5% APY', async function () { // Changed to regular function
         // Fetch Shrub's USDC balance before loan
         const shrubUsdcBefore = await getAccount(provider.connection, shrubUsdcAccount);
@@ -243,11 +484,18 @@ describe('radar-lend', function () { // Changed to regular function
         const userBalanceBefore = await provider.connection.getBalance(userAccount.publicKey);
         expect(userAccountInfoBefore.amount).to.equal(1_000_000n); // 1,000,000 already transferred
 
-        await program.methods.takeLoan(new anchor.BN(1_000_000), 500, new anchor.BN(3_300_000_000))
+        await program.methods.takeLoan(new anchor.BN(1_000_000), 500, new anchor.BN(3_300_000_000), { oneMonth: {} })
           .accounts({
             pdaAccount: shrubPda,
             admin: adminAccount.publicKey,
             user: userAccount.publicKey,
+            loan: await nextLoanPda(),
+            borrowerIndex,
+            marketConfig,
+            price: {
+              oracleProgram: mockChainlink.programId,
+              priceFeed: chainlinkFeed.publicKey,
+            },
             userUsdcAccount,
             shrubUsdcAccount,
             usdcMint,
@@ -264,19 +512,147 @@ describe('radar-lend', function () { // Changed to regular function
         const userBalanceAfter = await provider.connection.getBalance(userAccount.publicKey);
         const userAccountInfo = await getAccount(provider.connection, userUsdcAccount);
         console.log(userBalanceBefore, userBalanceAfter)
-        expect(userBalanceBefore - userBalanceAfter).to.equal(3_300_000_000);
+        const loanRent = await provider.connection.getMinimumBalanceForRentExemption(program.account.loan.size);
+        const indexRent = await provider.connection.getMinimumBalanceForRentExemption(program.account.borrowerIndex.size);
+        expect(userBalanceBefore - userBalanceAfter).to.equal(3_300_000_000 + loanRent + indexRent);
         expect(userAccountInfo.amount).to.equal(2_000_000n); // 1,000,000 already transferred + 1,000,000 loan
+
+        // Loan ids come from the market's counter
+        const pdaAccountData = await program.account.dataAccount.fetch(shrubPda);
+        expect(pdaAccountData.nextLoanId.toNumber()).to.equal(2);
+
+        // The loan is recorded in its own account
+        const loan = await program.account.loan.fetch(loanPda(1));
+        expect(loan.id.toNumber()).to.equal(1);
+        expect(loan.market.toString()).to.equal(shrubPda.toString());
+        expect(loan.borrower.toString()).to.equal(userAccount.publicKey.toString());
+        expect(loan.principal.toNumber()).to.equal(1_000_000);
+        expect(loan.collateral.toNumber()).to.equal(3_300_000_000);
+
+        // The origination terms are snapshotted into the loan
+        const config = await program.account.marketConfig.fetch(marketConfig);
+        expect(loan.ltv).to.equal(3300);
+        expect(loan.liquidationThreshold).to.equal(5300);
+        expect(loan.originationPrice.toNumber()).to.equal(100_000_000);
+        expect(loan.tierVersion).to.equal(config.version);
+
+        // The term sets the maturity, 30 days after origination for one month
+        expect(loan.term).to.deep.equal({ oneMonth: {} });
+        expect(loan.maturesAt.toNumber() - loan.createdAt.toNumber()).to.equal(30 * 86_400);
+
+        // The borrower's index tracks the open loan
+        const index = await program.account.borrowerIndex.fetch(borrowerIndex);
+        expect(index.borrower.toString()).to.equal(userAccount.publicKey.toString());
+        expect(index.openLoanIds.map((id) => id.toNumber())).to.deep.equal([1]);
+        expect(index.outstandingPrincipal.toNumber()).to.equal(1_000_000);
+        expect(index.totalCollateral.toNumber()).to.equal(3_300_000_000);
+      });
+
+      it('keeps the origination terms when the tier changes', async function () {
+        const manageTiers = (method: any) => method
+          .accounts({
+            admin: adminAccount.publicKey,
+            pdaAccount: shrubPda,
+            marketConfig,
+          })
+          .signers([adminAccount])
+          .rpc();
+
+        await manageTiers(program.methods.updateTier(500, 3000, 5000));
+        const loan = await program.account.loan.fetch(loanPda(1));
+        expect(loan.ltv).to.equal(3300);
+        expect(loan.liquidationThreshold).to.equal(5300);
+
+        // Restore the tier for the remaining tests
+        await manageTiers(program.methods.updateTier(500, 3300, 5300));
+      });
+
+      it('quotes the collateral required for a loan', async function () {
+        const quote = await program.methods.quoteLoan(new anchor.BN(1_000_000), 500)
+          .accounts({
+            pdaAccount: shrubPda,
+            marketConfig,
+            admin: adminAccount.publicKey,
+            price: {
+              oracleProgram: mockChainlink.programId,
+              priceFeed: chainlinkFeed.publicKey,
+            },
+          })
+          .view();
+        // 1 USDC at 33% LTV with SOL at $100
+        expect(quote.toNumber()).to.equal(30_303_030);
+      });
+
+      it('quotes the largest loan a collateral amount allows', async function () {
+        const maxBorrow = (collateral: number) => program.methods.maxBorrow(new anchor.BN(collateral), 500)
+          .accounts({
+            pdaAccount: shrubPda,
+            marketConfig,
+            admin: adminAccount.publicKey,
+            price: {
+              oracleProgram: mockChainlink.programId,
+              priceFeed: chainlinkFeed.publicKey,
+            },
+          })
+          .view();
+        expect((await maxBorrow(30_303_030)).toNumber()).to.equal(1_000_000);
+        // 1_000_001 needs 30_303_060 lamports
+        expect((await maxBorrow(30_303_059)).toNumber()).to.equal(1_000_000);
+        expect((await maxBorrow(30_303_060)).toNumber()).to.equal(1_000_001);
+
+        try {
+          await program.methods.maxBorrow(new anchor.BN(30_303_030), 999)
+            .accounts({
+              pdaAccount: shrubPda,
+              marketConfig,
+              admin: adminAccount.publicKey,
+              price: {
+                oracleProgram: mockChainlink.programId,
+                priceFeed: chainlinkFeed.publicKey,
+              },
+            })
+            .view();
+          expect.fail("Expected error for invalid APY");
+        } catch (err: any) {
+          expect(err.message).to.include("Invalid APY provided");
+        }
+      });
+
+      it('reports the health of a loan', async function () {
+        const health = await program.methods.loanHealth(new anchor.BN(1))
+          .accounts({
+            pdaAccount: shrubPda,
+            admin: adminAccount.publicKey,
+            loan: loanPda(1),
+            price: {
+              oracleProgram: mockChainlink.programId,
+              priceFeed: chainlinkFeed.publicKey,
+            },
+          })
+          .view();
+        // 1 USDC against 3.3 SOL at $100 with a 53% liquidation threshold
+        expect(health.debt.toNumber()).to.equal(1_000_000);
+        expect(health.collateralValue.toNumber()).to.equal(330_000_000);
+        expect(health.healthFactorBps.toNumber()).to.equal(1_749_000);
+        expect(health.liquidationPrice.toNumber()).to.equal(571_756);
       });
 
       it('successfully takes a loan with 0% APY', async function () { // Changed to regular function
         // Fetch Shrub's USDC balance before loan
         const shrubUsdcBefore = await getAccount(provider.connection, shrubUsdcAccount);
 
-        await program.methods.takeLoan(new anchor.BN(500_000), 0, new anchor.BN(2_000_000_000))
+        await program.methods.takeLoan(new anchor.BN(500_000), 0, new anchor.BN(2_000_000_000), { oneMonth: {} })
           .accounts({
             pdaAccount: shrubPda,
             admin: adminAccount.publicKey,
             user: userAccount.publicKey,
+            loan: await nextLoanPda(),
+            borrowerIndex,
+            marketConfig,
+            price: {
+              oracleProgram: mockChainlink.programId,
+              priceFeed: chainlinkFeed.publicKey,
+            },
             userUsdcAccount,
             shrubUsdcAccount,
             usdcMint,
@@ -293,24 +669,53 @@ describe('radar-lend', function () { // Changed to regular function
         const userAccountInfo = await getAccount(provider.connection, userUsdcAccount);
         expect(userAccountInfo.amount).to.equal(2_500_000n); // Adding 500,000 USDC loan
       });
-    });
 
-    describe('repay_loan', function () { // New describe block for repay_loan
-                                         // Define variables to hold loan details
-      let loanId: anchor.BN;
-      let loanPrincipal: bigint;
-      let loanApy: number;
-      let loanCollateral: bigint;
-      let expectedInterest: number;
-      let totalRepayment: bigint;
+      it('throws an error when the price feed is stale', async function () {
+        await setSolPrice(SOL_PRICE, now() - 3600);
+        try {
+          await program.methods.takeLoan(new anchor.BN(500_000), 0, new anchor.BN(2_000_000_000), { oneMonth: {} })
+            .accounts({
+              pdaAccount: shrubPda,
+              admin: adminAccount.publicKey,
+              user: userAccount.publicKey,
+              loan: await nextLoanPda(),
+              borrowerIndex,
+              marketConfig,
+              price: {
+                oracleProgram: mockChainlink.programId,
+                priceFeed: chainlinkFeed.publicKey,
+              },
+              userUsdcAccount,
+              shrubUsdcAccount,
+              usdcMint,
+              systemProgram: SYSTEM_PROGRAM,
+              tokenProgram: TOKEN_PROGRAM_ID,
+              associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+            })
+            .signers([userAccount])
+            .rpc();
+          expect.fail("Expected error for stale price");
+        } catch (err: any) {
+          expect(err.message).to.include("Price feed is stale");
+        } finally {
+          await setSolPrice(SOL_PRICE);
+        }
+      });
 
-      before(async function () { // Setup a new loan before repay tests
-        // Take a new loan to ensure it's available for repayment
-        await program.methods.takeLoan(new anchor.BN(1_000_000), 500, new anchor.BN(3_300_000_000))
+      it('values collateral at the feed price', async function () {
+        // 100 USDC at 50% LTV needs 2 SOL at $100 but only 0.2 SOL at $1,000
+        const takeLoan = async () => program.methods.takeLoan(new anchor.BN(100_000_000), 800, new anchor.BN(200_000_000), { oneMonth: {} })
           .accounts({
             pdaAccount: shrubPda,
             admin: adminAccount.publicKey,
             user: userAccount.publicKey,
+            loan: await nextLoanPda(),
+            borrowerIndex,
+            marketConfig,
+            price: {
+              oracleProgram: mockChainlink.programId,
+              priceFeed: chainlinkFeed.publicKey,
+            },
             userUsdcAccount,
             shrubUsdcAccount,
             usdcMint,
@@ -321,105 +726,465 @@ describe('radar-lend', function () { // Changed to regular function
           .signers([userAccount])
           .rpc();
 
-        // Fetch the loan details
-        const pdaAccountData = await program.account.dataAccount.fetch(shrubPda);
-        console.log(pdaAccountData);
-        const loan = pdaAccountData.loans.find(l => l.id.toNumber() === 2 && !l.repaid); // Assuming this is the second loan
-        expect(loan).to.exist;
-
-        if (!loan) {
-          throw new Error("Loan not found or already repaid");
+        try {
+          await takeLoan();
+          expect.fail("Expected error for insufficient collateral");
+        } catch (err: any) {
+          expect(err.message).to.include("Insufficient collateral provided");
         }
 
-        loanId = new anchor.BN(2);
-        loanPrincipal = BigInt(loan.principal.toString());
-        loanApy = loan.apy;
-        loanCollateral = BigInt(loan.collateral.toString());
+        await setSolPrice(new anchor.BN(1_000_00000000));
+        try {
+          const userUsdcBefore = await getAccount(provider.connection, userUsdcAccount);
+          await takeLoan();
+          const userUsdcAfter = await getAccount(provider.connection, userUsdcAccount);
+          expect(userUsdcAfter.amount - userUsdcBefore.amount).to.equal(100_000_000n);
+        } finally {
+          await setSolPrice(SOL_PRICE);
+        }
+      });
 
-        // Calculate expected interest (assuming sufficient duration)
-        const currentTime = Math.floor(Date.now() / 1000); // Current Unix timestamp
-        const duration = currentTime - Number(loan.createdAt.toString());
-        expectedInterest = Math.floor(Number(loanPrincipal) * loanApy * duration / (10_000 * 31_536_000));
+      it('values collateral with the admin-set mock price', async function () {
+        await program.methods.setMockPrice(new anchor.BN(1_000_000_000), new anchor.BN(0)) // 1,000 USDC per SOL
+          .accounts({
+            admin: adminAccount.publicKey,
+            pdaAccount: shrubPda,
+            mockPrice,
+            systemProgram: SYSTEM_PROGRAM,
+          })
+          .signers([adminAccount])
+          .rpc();
+        await setOracle({ mock: {} }, program.programId, mockPrice);
 
-        totalRepayment = loanPrincipal + BigInt(expectedInterest);
+        try {
+          const userUsdcBefore = await getAccount(provider.connection, userUsdcAccount);
+          await program.methods.takeLoan(new anchor.BN(100_000_000), 800, new anchor.BN(200_000_000), { oneMonth: {} })
+            .accounts({
+              pdaAccount: shrubPda,
+              admin: adminAccount.publicKey,
+              user: userAccount.publicKey,
+              loan: await nextLoanPda(),
+              borrowerIndex,
+              marketConfig,
+              price: {
+                oracleProgram: program.programId,
+                priceFeed: mockPrice,
+              },
+              userUsdcAccount,
+              shrubUsdcAccount,
+              usdcMint,
+              systemProgram: SYSTEM_PROGRAM,
+              tokenProgram: TOKEN_PROGRAM_ID,
+              associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+            })
+            .signers([userAccount])
+            .rpc();
+          const userUsdcAfter = await getAccount(provider.connection, userUsdcAccount);
+          expect(userUsdcAfter.amount - userUsdcBefore.amount).to.equal(100_000_000n);
+        } finally {
+          await setOracle({ chainlink: {} }, mockChainlink.programId, chainlinkFeed.publicKey);
+        }
+      });
 
-        // Mint enough USDC to the user to repay the loan
-        await mintTo(
+      it('rejects a loan beyond the open-loan cap of its borrower', async function () {
+        const borrower = anchor.web3.Keypair.generate();
+        const latestBlockhash = await provider.connection.getLatestBlockhash();
+        const signature = await provider.connection.requestAirdrop(borrower.publicKey, 2_000_000_000);
+        await provider.connection.confirmTransaction({
+          signature,
+          blockhash: latestBlockhash.blockhash,
+          lastValidBlockHeight: latestBlockhash.lastValidBlockHeight,
+        });
+        const borrowerUsdcAccount = (await getOrCreateAssociatedTokenAccount(
           provider.connection,
-          adminAccount,
+          borrower,
           usdcMint,
-          userUsdcAccount,
-          adminAccount,
-          totalRepayment
-        );
-      });
+          borrower.publicKey
+        )).address;
+        const borrowerIndexAccount = anchor.web3.PublicKey.findProgramAddressSync(
+          [Buffer.from("borrower"), shrubPda.toBuffer(), borrower.publicKey.toBuffer()],
+          program.programId
+        )[0];
 
-      it('successfully repays a loan and receives collateral back', async function () { // New test
-        // Fetch loan details
-        const pdaAccountData = await program.account.dataAccount.fetch(shrubPda);
-        const loan = pdaAccountData.loans.find(l => l.id.toNumber() === loanId.toNumber() && !l.repaid);
-        expect(loan).to.exist;
+        // 0.1 USDC against 0.002 SOL ($0.20 at $100/SOL): the 50% LTV of the 8% tier
+        const takeSmallLoan = async () => program.methods.takeLoan(new anchor.BN(100_000), 800, new anchor.BN(2_000_000), { oneMonth: {} })
+          .accounts({
+            pdaAccount: shrubPda,
+            admin: adminAccount.publicKey,
+            user: borrower.publicKey,
+            loan: await nextLoanPda(),
+            borrowerIndex: borrowerIndexAccount,
+            marketConfig,
+            price: {
+              oracleProgram: mockChainlink.programId,
+              priceFeed: chainlinkFeed.publicKey,
+            },
+            userUsdcAccount: borrowerUsdcAccount,
+            shrubUsdcAccount,
+            usdcMint,
+            systemProgram: SYSTEM_PROGRAM,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          })
+          .signers([borrower])
+          .rpc();
 
-        if (!loan) {
-          throw new Error("Loan not found or already repaid");
+        // The borrower index has room for 32 open loans
+        for (let i = 0; i < 32; i++) {
+          await takeSmallLoan();
+        }
+        try {
+          await takeSmallLoan();
+          expect.fail("Expected error for too many open loans");
+        } catch (err: any) {
+          expect(err.message).to.include("Too many open loans for this borrower");
         }
 
-        // Fetch Shrub's USDC balance before repayment
-        const shrubUsdcBefore = await getAccount(provider.connection, shrubUsdcAccount);
+        const index = await program.account.borrowerIndex.fetch(borrowerIndexAccount);
+        expect(index.openLoanIds.length).to.equal(32);
+      });
+    });
 
-        // Fetch user's USDC balance before repayment
-        const userUsdcBefore = await getAccount(provider.connection, userUsdcAccount);
+    describe('price aggregation', function () {
+      async function setMockPrice(price: number, conf: number = 0) {
+        await program.methods.setMockPrice(new anchor.BN(price), new anchor.BN(conf))
+          .accounts({
+            admin: adminAccount.publicKey,
+            pdaAccount: shrubPda,
+            mockPrice,
+            systemProgram: SYSTEM_PROGRAM,
+          })
+          .signers([adminAccount])
+          .rpc();
+      }
 
-        console.log(`
-            pdaAccount: ${shrubPda},
-            user: ${userAccount.publicKey},
-            userUsdcAccount: ${userUsdcAccount},
-            shrubUsdcAccount: ${shrubUsdcAccount},
-            usdcMint: ${usdcMint},
-            systemProgram: ${SYSTEM_PROGRAM},
-            tokenProgram: ${TOKEN_PROGRAM_ID},
-            associatedTokenProgram: ${ASSOCIATED_TOKEN_PROGRAM_ID},
-        `)
-        const pdaAccountInfo = await provider.connection.getAccountInfo(shrubPda);
-        const userAccountInfo = await provider.connection.getAccountInfo(userAccount.publicKey)
-        console.log("pdaAccountInfo", pdaAccountInfo)
-        console.log("userAccountInfo", userAccountInfo)
-        // console.log(`pdaAccount info: ${(await provider.connection.getAccountInfo(shrubPda))}`);
-        // console.log(`userAccount info: ${(await provider.connection.getAccountInfo(userAccount.publicKey))}`);
-        
-        
-        // Repay the loan
-        await program.methods.repayLoan(loanId)
+      // 1 USDC at 20% LTV needs 0.05 SOL at $100
+      async function takeSmallLoan(withSecondary: boolean = true) {
+        return program.methods.takeLoan(new anchor.BN(1_000_000), 0, new anchor.BN(50_000_000), { oneMonth: {} })
           .accounts({
             pdaAccount: shrubPda,
+            admin: adminAccount.publicKey,
             user: userAccount.publicKey,
-            userUsdcAccount: userUsdcAccount,
-            shrubUsdcAccount: shrubUsdcAccount,
-            usdcMint: usdcMint,
+            loan: await nextLoanPda(),
+            borrowerIndex,
+            marketConfig,
+            price: {
+              oracleProgram: mockChainlink.programId,
+              priceFeed: chainlinkFeed.publicKey,
+              secondaryOracleProgram: withSecondary ? program.programId : null,
+              secondaryPriceFeed: withSecondary ? mockPrice : null,
+            },
+            userUsdcAccount,
+            shrubUsdcAccount,
+            usdcMint,
             systemProgram: SYSTEM_PROGRAM,
             tokenProgram: TOKEN_PROGRAM_ID,
             associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
           })
           .signers([userAccount])
           .rpc();
+      }
 
-        // Fetch Shrub's USDC balance after repayment
-        const shrubUsdcAfter = await getAccount(provider.connection, shrubUsdcAccount);
-        expect(shrubUsdcAfter.amount.toString()).to.equal((shrubUsdcBefore.amount.toBigInt() + totalRepayment).toString());
-
-        // Fetch user's USDC balance after repayment
-        const userUsdcAfter = await getAccount(provider.connection, userUsdcAccount);
-        expect(userUsdcAfter.amount.toString()).to.equal((userUsdcBefore.amount.toBigInt() - totalRepayment).toString());
-
-        // Fetch user's SOL balance after receiving collateral
-        const userSolAfter = await provider.connection.getBalance(userAccount.publicKey);
-        // Optionally, fetch user's SOL balance before repayment to compare
+      before(async function () {
+        await setMockPrice(100_000_000);
+        await program.methods.setSecondaryOracle({ mock: {} }, 300)
+          .accounts({
+            admin: adminAccount.publicKey,
+            pdaAccount: shrubPda,
+            oracleProgram: program.programId,
+            priceFeed: mockPrice,
+          })
+          .signers([adminAccount])
+          .rpc();
+      });
+
+      after(async function () {
+        await program.methods.removeSecondaryOracle()
+          .accounts({
+            admin: adminAccount.publicKey,
+            pdaAccount: shrubPda,
+          })
+          .signers([adminAccount])
+          .rpc();
+        await setSolPrice(SOL_PRICE);
+      });
+
+      it('takes a loan when both oracles agree', async function () {
+        await takeSmallLoan();
+      });
+
+      it('requires the secondary feed once configured', async function () {
+        try {
+          await takeSmallLoan(false);
+          expect.fail("Expected error for missing secondary feed");
+        } catch (err: any) {
+          expect(err.message).to.include("Secondary price feed required");
+        }
+      });
+
+      it('blocks borrowing when the oracles disagree', async function () {
+        await setMockPrice(150_000_000);
+        try {
+          await takeSmallLoan();
+          expect.fail("Expected error for oracle deviation");
+        } catch (err: any) {
+          expect(err.message).to.include("Oracle prices disagree");
+        } finally {
+          await setMockPrice(100_000_000);
+        }
+      });
+
+      it('values collateral at the lower price when the oracles disagree', async function () {
+        await setMockPrice(50_000_000);
+        try {
+          const quote = await program.methods.quoteLoan(new anchor.BN(1_000_000), 0)
+            .accounts({
+              pdaAccount: shrubPda,
+              marketConfig,
+              admin: adminAccount.publicKey,
+              price: {
+                oracleProgram: mockChainlink.programId,
+                priceFeed: chainlinkFeed.publicKey,
+                secondaryOracleProgram: program.programId,
+                secondaryPriceFeed: mockPrice,
+              },
+            })
+            .view();
+          // 1 USDC at 20% LTV with SOL at the secondary's $50
+          expect(quote.toNumber()).to.equal(100_000_000);
+        } finally {
+          await setMockPrice(100_000_000);
+        }
+      });
+
+      it('blocks liquidation when the oracles disagree', async function () {
+        const loanId = (await program.account.dataAccount.fetch(shrubPda)).nextLoanId;
+        await takeSmallLoan();
+        // At the secondary's $15 the loan would be liquidatable
+        await setMockPrice(15_000_000);
+        try {
+          await program.methods.liquidateLoan(loanId, new anchor.BN(1_000_000))
+            .accounts({
+              pdaAccount: shrubPda,
+              admin: adminAccount.publicKey,
+              liquidator: userAccount.publicKey,
+              loan: loanPda(loanId),
+              borrower: userAccount.publicKey,
+              borrowerIndex,
+              price: {
+                oracleProgram: mockChainlink.programId,
+                priceFeed: chainlinkFeed.publicKey,
+                secondaryOracleProgram: program.programId,
+                secondaryPriceFeed: mockPrice,
+              },
+              liquidatorUsdcAccount: userUsdcAccount,
+              shrubUsdcAccount,
+              tokenProgram: TOKEN_PROGRAM_ID,
+            })
+            .signers([userAccount])
+            .rpc();
+          expect.fail("Expected error for oracle deviation");
+        } catch (err: any) {
+          expect(err.message).to.include("Oracle prices disagree");
+        } finally {
+          await setMockPrice(100_000_000);
+        }
+      });
+
+      it('falls back to the secondary when the primary is stale', async function () {
+        await setSolPrice(SOL_PRICE, now() - 3600);
+        await takeSmallLoan();
+        await setSolPrice(SOL_PRICE);
+      });
+
+      it('rejects a secondary price with a wide confidence interval', async function () {
+        // A 10% confidence interval is ignored while the primary is usable...
+        await setMockPrice(100_000_000, 10_000_000);
+        await takeSmallLoan();
+
+        // ...but cannot be used as a fallback
+        await setSolPrice(SOL_PRICE, now() - 3600);
+        try {
+          await takeSmallLoan();
+          expect.fail("Expected error for stale primary and unusable secondary");
+        } catch (err: any) {
+          expect(err.message).to.include("Price feed is stale");
+        } finally {
+          await setMockPrice(100_000_000);
+        }
+      });
+    });
+
+    describe('twap', function () {
+      async function crankPrice() {
+        await program.methods.crankPrice()
+          .accounts({
+            cranker: userAccount.publicKey,
+            pdaAccount: shrubPda,
+            admin: adminAccount.publicKey,
+            priceHistory,
+            oracleProgram: mockChainlink.programId,
+            priceFeed: chainlinkFeed.publicKey,
+            systemProgram: SYSTEM_PROGRAM,
+          })
+          .signers([userAccount])
+          .rpc();
+      }
+
+      async function setPriceMode(priceMode: any, twapWindow: number) {
+        await program.methods.setPriceMode(priceMode, twapWindow)
+          .accounts({
+            admin: adminAccount.publicKey,
+            pdaAccount: shrubPda,
+          })
+          .signers([adminAccount])
+          .rpc();
+      }
+
+      after(async function () {
+        await setPriceMode({ spot: {} }, 1_800);
+        await setSolPrice(SOL_PRICE);
+      });
+
+      it('records cranked prices in the price history', async function () {
+        await setPriceMode({ minSpotTwap: {} }, 4);
+        await crankPrice();
+
+        const history = await program.account.priceHistory.fetch(priceHistory);
+        expect(history.market.toString()).to.equal(shrubPda.toString());
+        expect(history.len).to.equal(1);
+        expect(history.observations[0].price.toNumber()).to.equal(100_000_000);
+      });
+
+      it('values collateral at the lower of spot and TWAP', async function () {
+        // Let the $100 observation cover the whole TWAP window, then jump the spot price
+        await new Promise((resolve) => setTimeout(resolve, 5_000));
+        await setSolPrice(new anchor.BN(1_000_00000000));
+        await crankPrice();
+
+        // 100 USDC at 50% LTV with 0.2 SOL is only enough at the $1,000 spot price
+        try {
+          await program.methods.takeLoan(new anchor.BN(100_000_000), 800, new anchor.BN(200_000_000), { oneMonth: {} })
+            .accounts({
+              pdaAccount: shrubPda,
+              admin: adminAccount.publicKey,
+              user: userAccount.publicKey,
+              loan: await nextLoanPda(),
+              borrowerIndex,
+              marketConfig,
+              price: {
+                oracleProgram: mockChainlink.programId,
+                priceFeed: chainlinkFeed.publicKey,
+                priceHistory,
+              },
+              userUsdcAccount,
+              shrubUsdcAccount,
+              usdcMint,
+              systemProgram: SYSTEM_PROGRAM,
+              tokenProgram: TOKEN_PROGRAM_ID,
+              associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+            })
+            .signers([userAccount])
+            .rpc();
+          expect.fail("Expected error for insufficient collateral");
+        } catch (err: any) {
+          expect(err.message).to.include("Insufficient collateral provided");
+        }
+      });
+
+      it('requires the price history outside of spot mode', async function () {
+        try {
+          await program.methods.takeLoan(new anchor.BN(1_000_000), 0, new anchor.BN(50_000_000), { oneMonth: {} })
+            .accounts({
+              pdaAccount: shrubPda,
+              admin: adminAccount.publicKey,
+              user: userAccount.publicKey,
+              loan: await nextLoanPda(),
+              borrowerIndex,
+              marketConfig,
+              price: {
+                oracleProgram: mockChainlink.programId,
+                priceFeed: chainlinkFeed.publicKey,
+                priceHistory: null,
+              },
+              userUsdcAccount,
+              shrubUsdcAccount,
+              usdcMint,
+              systemProgram: SYSTEM_PROGRAM,
+              tokenProgram: TOKEN_PROGRAM_ID,
+              associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+            })
+            .signers([userAccount])
+            .rpc();
+          expect.fail("Expected error for missing price history");
+        } catch (err: any) {
+          expect(err.message).to.include("Price history required");
+        }
+      });
+    });
+
+    describe('repay_loan', function () { // New describe block for repay_loan
+                                         // Define variables to hold loan details
+      let loanId: anchor.BN;
+      let loanPrincipal: bigint;
+      let loanApy: number;
+      let loanCollateral: bigint;
+      let expectedInterest: number;
+      let totalRepayment: bigint;
+
+      before(async function () { // Setup a new loan before repay tests
+        // Take a new loan to ensure it's available for repayment
+        await program.methods.takeLoan(new anchor.BN(1_000_000), 500, new anchor.BN(3_300_000_000), { oneMonth: {} })
+          .accounts({
+            pdaAccount: shrubPda,
+            admin: adminAccount.publicKey,
+            user: userAccount.publicKey,
+            loan: await nextLoanPda(),
+            borrowerIndex,
+            marketConfig,
+            price: {
+              oracleProgram: mockChainlink.programId,
+              priceFeed: chainlinkFeed.publicKey,
+            },
+            userUsdcAccount,
+            shrubUsdcAccount,
+            usdcMint,
+            systemProgram: SYSTEM_PROGRAM,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          })
+          .signers([userAccount])
+          .rpc();
+
+        // Fetch the loan details
+        loanId = new anchor.BN(2); // Assuming this is the second loan
+        const loan = await program.account.loan.fetch(loanPda(loanId));
+        console.log(loan);
+
+        loanPrincipal = BigInt(loan.principal.toString());
+        loanApy = loan.apy;
+        loanCollateral = BigInt(loan.collateral.toString());
+
+        // Calculate expected interest (assuming sufficient duration)
+        const currentTime = Math.floor(Date.now() / 1000); // Current Unix timestamp
+        const duration = currentTime - Number(loan.createdAt.toString());
+        expectedInterest = Math.floor(Number(loanPrincipal) * loanApy * duration / (10_000 * 31_536_000));
+
+        // Fresh markets charge a penalty APY for the rest of the early-repayment window
+        const { window, apy } = loan.earlyRepayment.penaltyApy;
+        const expectedFee = Math.floor(Number(loanPrincipal) * apy * (window - duration) / (10_000 * 31_536_000));
 
-        // Fetch loan details to ensure it's marked as repaid
-        const updatedPdaAccountData = await program.account.dataAccount.fetch(shrubPda);
-        const updatedLoan = updatedPdaAccountData.loans.find(l => l.id.toNumber() === loanId.toNumber());
-        expect(updatedLoan).to.exist;
-        expect(updatedLoan?.repaid).to.equal(true);
+        totalRepayment = loanPrincipal + BigInt(expectedInterest) + BigInt(expectedFee);
+
+        // Mint enough USDC to the user to repay the loan
+        await mintTo(
+          provider.connection,
+          adminAccount,
+          usdcMint,
+          userUsdcAccount,
+          adminAccount,
+          totalRepayment
+        );
       });
 
       it('prevents non-borrowers from repaying a loan', async function () { // New test
@@ -460,6 +1225,8 @@ describe('radar-lend', function () { // Changed to regular function
             .accounts({
               pdaAccount: shrubPda,
               user: nonBorrower.publicKey,
+              loan: loanPda(loanId),
+              borrowerIndex,
               userUsdcAccount: nonBorrowerUsdcAccount,
               shrubUsdcAccount: shrubUsdcAccount,
               usdcMint: usdcMint,
@@ -475,6 +1242,82 @@ describe('radar-lend', function () { // Changed to regular function
         }
       });
 
+      it('successfully repays a loan and receives collateral back', async function () { // New test
+        // Fetch loan details
+        const loan = await program.account.loan.fetch(loanPda(loanId));
+        const indexBefore = await program.account.borrowerIndex.fetch(borrowerIndex);
+        const userSolBefore = await provider.connection.getBalance(userAccount.publicKey);
+        const loanRent = await provider.connection.getMinimumBalanceForRentExemption(program.account.loan.size);
+
+        // Fetch Shrub's USDC balance before repayment
+        const shrubUsdcBefore = await getAccount(provider.connection, shrubUsdcAccount);
+
+        // Fetch user's USDC balance before repayment
+        const userUsdcBefore = await getAccount(provider.connection, userUsdcAccount);
+
+        console.log(`
+            pdaAccount: ${shrubPda},
+            user: ${userAccount.publicKey},
+            userUsdcAccount: ${userUsdcAccount},
+            shrubUsdcAccount: ${shrubUsdcAccount},
+            usdcMint: ${usdcMint},
+            systemProgram: ${SYSTEM_PROGRAM},
+            tokenProgram: ${TOKEN_PROGRAM_ID},
+            associatedTokenProgram: ${ASSOCIATED_TOKEN_PROGRAM_ID},
+        `)
+        const pdaAccountInfo = await provider.connection.getAccountInfo(shrubPda);
+        const userAccountInfo = await provider.connection.getAccountInfo(userAccount.publicKey)
+        console.log("pdaAccountInfo", pdaAccountInfo)
+        console.log("userAccountInfo", userAccountInfo)
+        // console.log(`pdaAccount info: ${(await provider.connection.getAccountInfo(shrubPda))}`);
+        // console.log(`userAccount info: ${(await provider.connection.getAccountInfo(userAccount.publicKey))}`);
+        
+        
+        // Repay the loan
+        const signature = await program.methods.repayLoan(loanId)
+          .accounts({
+            pdaAccount: shrubPda,
+            user: userAccount.publicKey,
+            loan: loanPda(loanId),
+            borrowerIndex,
+            userUsdcAccount: userUsdcAccount,
+            shrubUsdcAccount: shrubUsdcAccount,
+            usdcMint: usdcMint,
+            systemProgram: SYSTEM_PROGRAM,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          })
+          .signers([userAccount])
+          .rpc({ commitment: "confirmed" });
+
+        // Fetch Shrub's USDC balance after repayment
+        const shrubUsdcAfter = await getAccount(provider.connection, shrubUsdcAccount);
+        expect(shrubUsdcAfter.amount.toString()).to.equal((shrubUsdcBefore.amount.toBigInt() + totalRepayment).toString());
+
+        // Fetch user's USDC balance after repayment
+        const userUsdcAfter = await getAccount(provider.connection, userUsdcAccount);
+        expect(userUsdcAfter.amount.toString()).to.equal((userUsdcBefore.amount.toBigInt() - totalRepayment).toString());
+
+        // Fetch user's SOL balance after receiving collateral and the loan account's rent
+        const userSolAfter = await provider.connection.getBalance(userAccount.publicKey);
+        expect(userSolAfter - userSolBefore).to.equal(loan.collateral.toNumber() + loanRent);
+
+        // The loan account is closed once repaid
+        const loanAccountInfo = await provider.connection.getAccountInfo(loanPda(loanId));
+        expect(loanAccountInfo).to.be.null;
+
+        // The loan is removed from the borrower's index
+        const indexAfter = await program.account.borrowerIndex.fetch(borrowerIndex);
+        expect(indexAfter.openLoanIds.map((id) => id.toNumber())).to.not.include(loanId.toNumber());
+        expect(indexBefore.outstandingPrincipal.sub(indexAfter.outstandingPrincipal).toString()).to.equal(loan.principal.toString());
+        expect(indexBefore.totalCollateral.sub(indexAfter.totalCollateral).toString()).to.equal(loan.collateral.toString());
+
+        // The closing event carries the loan's final record
+        const closed = await loanClosedEvent(signature);
+        expect(JSON.stringify(closed.loan)).to.equal(JSON.stringify(loan));
+        expect(closed.closedAt.toNumber()).to.be.at.least(loan.createdAt.toNumber());
+      });
+
       it('prevents repaying an already repaid loan', async function () { // New test
         // Attempt to repay the same loan again
         try {
@@ -482,6 +1325,8 @@ describe('radar-lend', function () { // Changed to regular function
             .accounts({
               pdaAccount: shrubPda,
               user: userAccount.publicKey,
+              loan: loanPda(loanId),
+              borrowerIndex,
               userUsdcAccount: userUsdcAccount,
               shrubUsdcAccount: shrubUsdcAccount,
               usdcMint: usdcMint,
@@ -493,10 +1338,706 @@ describe('radar-lend', function () { // Changed to regular function
             .rpc();
           expect.fail("Expected error for already repaid loan");
         } catch (err: any) {
-          expect(err.message).to.include("Loan already repaid");
+          expect(err.message).to.include("AccountNotInitialized");
         }
       });
     });
 
+    describe('liquidate_loan', function () {
+      const MAX_REPAY = new anchor.BN("18446744073709551615");
+      let loanId: anchor.BN;
+      let liquidator: anchor.web3.Keypair;
+      let liquidatorUsdcAccount: anchor.web3.PublicKey;
+
+      async function liquidateLoan(
+        repayAmount: anchor.BN = MAX_REPAY,
+        usdcAccount: anchor.web3.PublicKey = liquidatorUsdcAccount
+      ): Promise<string> {
+        return program.methods.liquidateLoan(loanId, repayAmount)
+          .accounts({
+            pdaAccount: shrubPda,
+            admin: adminAccount.publicKey,
+            liquidator: liquidator.publicKey,
+            loan: loanPda(loanId),
+            borrower: userAccount.publicKey,
+            borrowerIndex,
+            price: {
+              oracleProgram: mockChainlink.programId,
+              priceFeed: chainlinkFeed.publicKey,
+            },
+            liquidatorUsdcAccount: usdcAccount,
+            shrubUsdcAccount,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          })
+          .signers([liquidator])
+          .rpc({ commitment: "confirmed" });
+      }
+
+      before(async function () {
+        liquidator = anchor.web3.Keypair.generate();
+        const latestBlockhash = await provider.connection.getLatestBlockhash();
+        const signature = await provider.connection.requestAirdrop(liquidator.publicKey, 1_000_000_000);
+        await provider.connection.confirmTransaction({
+          signature,
+          blockhash: latestBlockhash.blockhash,
+          lastValidBlockHeight: latestBlockhash.lastValidBlockHeight,
+        });
+        liquidatorUsdcAccount = (await getOrCreateAssociatedTokenAccount(
+          provider.connection,
+          liquidator,
+          usdcMint,
+          liquidator.publicKey
+        )).address;
+        await mintTo(provider.connection, adminAccount, usdcMint, liquidatorUsdcAccount, adminAccount, 10_000_000);
+        await takeLoan();
+      });
+
+      // 1 USDC against 0.02 SOL ($2 at $100/SOL): exactly the 50% LTV of the 8% tier
+      async function takeLoan() {
+        const pdaAccountData = await program.account.dataAccount.fetch(shrubPda);
+        loanId = pdaAccountData.nextLoanId;
+        await program.methods.takeLoan(new anchor.BN(1_000_000), 800, new anchor.BN(20_000_000), { oneMonth: {} })
+          .accounts({
+            pdaAccount: shrubPda,
+            admin: adminAccount.publicKey,
+            user: userAccount.publicKey,
+            loan: loanPda(loanId),
+            borrowerIndex,
+            marketConfig,
+            price: {
+              oracleProgram: mockChainlink.programId,
+              priceFeed: chainlinkFeed.publicKey,
+            },
+            userUsdcAccount,
+            shrubUsdcAccount,
+            usdcMint,
+            systemProgram: SYSTEM_PROGRAM,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          })
+          .signers([userAccount])
+          .rpc();
+      }
+
+      after(async function () {
+        await setSolPrice(SOL_PRICE);
+      });
+
+      it('rejects liquidating a healthy loan', async function () {
+        try {
+          await liquidateLoan();
+          expect.fail("Expected error for healthy loan");
+        } catch (err: any) {
+          expect(err.message).to.include("Loan is not eligible for liquidation");
+        }
+      });
+
+      it('rejects a liquidation bonus above the maximum', async function () {
+        try {
+          await program.methods.setLiquidationBonus(10_000)
+            .accounts({
+              admin: adminAccount.publicKey,
+              pdaAccount: shrubPda,
+            })
+            .signers([adminAccount])
+            .rpc();
+          expect.fail("Expected error for invalid liquidation bonus");
+        } catch (err: any) {
+          expect(err.message).to.include("Invalid liquidation bonus");
+        }
+
+        const pdaAccountData = await program.account.dataAccount.fetch(shrubPda);
+        expect(pdaAccountData.liquidationBonusBps).to.equal(500);
+      });
+
+      it('rejects a close factor above 100%', async function () {
+        try {
+          await program.methods.setCloseFactor(10_001)
+            .accounts({
+              admin: adminAccount.publicKey,
+              pdaAccount: shrubPda,
+            })
+            .signers([adminAccount])
+            .rpc();
+          expect.fail("Expected error for invalid close factor");
+        } catch (err: any) {
+          expect(err.message).to.include("Invalid close factor");
+        }
+
+        const pdaAccountData = await program.account.dataAccount.fetch(shrubPda);
+        expect(pdaAccountData.closeFactorBps).to.equal(5000);
+      });
+
+      it('rejects paying with a token account of another mint', async function () {
+        await setSolPrice(new anchor.BN(65_00000000));
+
+        const otherMint = await createMint(provider.connection, adminAccount, adminAccount.publicKey, null, 6);
+        const otherUsdcAccount = (await getOrCreateAssociatedTokenAccount(
+          provider.connection,
+          liquidator,
+          otherMint,
+          liquidator.publicKey
+        )).address;
+        await mintTo(provider.connection, adminAccount, otherMint, otherUsdcAccount, adminAccount, 10_000_000);
+
+        try {
+          await liquidateLoan(MAX_REPAY, otherUsdcAccount);
+          expect.fail("Expected error for a token account of another mint");
+        } catch (err: any) {
+          expect(err.message).to.include("A token mint constraint was violated");
+        }
+
+        const account = await getAccount(provider.connection, otherUsdcAccount);
+        expect(account.amount.toString()).to.equal("10000000");
+      });
+
+      it('partially liquidates up to the close factor', async function () {
+        // At $65/SOL the collateral is worth $1.30, an LTV of ~77% against the 70% threshold
+        await setSolPrice(new anchor.BN(65_00000000));
+
+        const loanBefore = await program.account.loan.fetch(loanPda(loanId));
+        const indexBefore = await program.account.borrowerIndex.fetch(borrowerIndex);
+        const liquidatorUsdcBefore = await getAccount(provider.connection, liquidatorUsdcAccount);
+        const liquidatorSolBefore = await provider.connection.getBalance(liquidator.publicKey);
+
+        await liquidateLoan();
+
+        // Only half of the debt is repaid
+        const liquidatorUsdcAfter = await getAccount(provider.connection, liquidatorUsdcAccount);
+        const repaid = liquidatorUsdcBefore.amount - liquidatorUsdcAfter.amount;
+        expect(Number(repaid)).to.be.gte(500_000);
+        expect(Number(repaid)).to.be.lt(510_000);
+
+        // The liquidator receives the repaid amount's worth of SOL plus the 5% bonus
+        const seized = repaid * 10_500n * 1_000_000_000n / (10_000n * 65_000_000n);
+        const liquidatorSolAfter = await provider.connection.getBalance(liquidator.publicKey);
+        expect(BigInt(liquidatorSolAfter - liquidatorSolBefore)).to.equal(seized);
+
+        // The loan stays open with less principal and collateral
+        const loanAfter = await program.account.loan.fetch(loanPda(loanId));
+        expect(BigInt(loanAfter.collateral.toString())).to.equal(BigInt(loanBefore.collateral.toString()) - seized);
+        expect(loanAfter.principal.toNumber()).to.be.lt(loanBefore.principal.toNumber());
+        expect(loanAfter.principal.toNumber()).to.be.gt(490_000);
+
+        const indexAfter = await program.account.borrowerIndex.fetch(borrowerIndex);
+        expect(indexAfter.openLoanIds.map((id) => id.toNumber())).to.include(loanId.toNumber());
+        expect(indexBefore.totalCollateral.sub(indexAfter.totalCollateral).toString()).to.equal(seized.toString());
+        expect(indexBefore.outstandingPrincipal.sub(indexAfter.outstandingPrincipal).toString())
+          .to.equal(loanBefore.principal.sub(loanAfter.principal).toString());
+      });
+
+      it('rejects liquidating the loan once it is healthy again', async function () {
+        try {
+          await liquidateLoan();
+          expect.fail("Expected error for healthy loan");
+        } catch (err: any) {
+          expect(err.message).to.include("Loan is not eligible for liquidation");
+        }
+      });
+
+      it('fully liquidates a loan whose collateral no longer covers the debt', async function () {
+        // At $40/SOL the remaining collateral is worth less than the debt plus the bonus
+        await setSolPrice(new anchor.BN(40_00000000));
+
+        const loan = await program.account.loan.fetch(loanPda(loanId));
+        const indexBefore = await program.account.borrowerIndex.fetch(borrowerIndex);
+        const liquidatorUsdcBefore = await getAccount(provider.connection, liquidatorUsdcAccount);
+        const liquidatorSolBefore = await provider.connection.getBalance(liquidator.publicKey);
+        const userSolBefore = await provider.connection.getBalance(userAccount.publicKey);
+
+        const signature = await liquidateLoan();
+
+        // The whole debt is repaid and all of the collateral goes to the liquidator
+        const liquidatorUsdcAfter = await getAccount(provider.connection, liquidatorUsdcAccount);
+        const repaid = liquidatorUsdcBefore.amount - liquidatorUsdcAfter.amount;
+        expect(Number(repaid)).to.be.gte(loan.principal.toNumber());
+        const liquidatorSolAfter = await provider.connection.getBalance(liquidator.publicKey);
+        expect(liquidatorSolAfter - liquidatorSolBefore).to.equal(loan.collateral.toNumber());
+
+        // The loan is closed and its rent refunded to the borrower
+        const loanRent = await provider.connection.getMinimumBalanceForRentExemption(program.account.loan.size);
+        const userSolAfter = await provider.connection.getBalance(userAccount.publicKey);
+        expect(userSolAfter - userSolBefore).to.equal(loanRent);
+
+        expect(await provider.connection.getAccountInfo(loanPda(loanId))).to.be.null;
+        const indexAfter = await program.account.borrowerIndex.fetch(borrowerIndex);
+        expect(indexAfter.openLoanIds.map((id) => id.toNumber())).to.not.include(loanId.toNumber());
+        expect(indexBefore.totalCollateral.sub(indexAfter.totalCollateral).toString()).to.equal(loan.collateral.toString());
+        expect(indexBefore.outstandingPrincipal.sub(indexAfter.outstandingPrincipal).toString()).to.equal(loan.principal.toString());
+
+        // The closing event carries the loan's final record, with interest accrued up to the close
+        const closed = await loanClosedEvent(signature);
+        for (const [field, value] of Object.entries(loan)) {
+          if (!["accruedInterest", "lastAccrual", "borrowIndex"].includes(field)) {
+            expect(JSON.stringify(closed.loan[field]), field).to.equal(JSON.stringify(value));
+          }
+        }
+        expect(closed.loan.accruedInterest.toString()).to.equal((repaid - BigInt(loan.principal.toString())).toString());
+        expect(closed.loan.lastAccrual.toString()).to.equal(closed.closedAt.toString());
+      });
+
+      it('closes a loan whose collateral runs out before its debt is repaid', async function () {
+        await setSolPrice(SOL_PRICE);
+        await takeLoan();
+        // At $40/SOL the 0.02 SOL of collateral is worth $0.80 against the 1 USDC debt
+        await setSolPrice(new anchor.BN(40_00000000));
+
+        const loan = await program.account.loan.fetch(loanPda(loanId));
+        const indexBefore = await program.account.borrowerIndex.fetch(borrowerIndex);
+        const marketBefore = await program.account.dataAccount.fetch(shrubPda);
+        const liquidatorUsdcBefore = await getAccount(provider.connection, liquidatorUsdcAccount);
+        const liquidatorSolBefore = await provider.connection.getBalance(liquidator.publicKey);
+
+        // 0.8 USDC plus the 5% bonus is worth 0.021 SOL, more than the loan holds
+        await liquidateLoan(new anchor.BN(800_000));
+
+        const liquidatorUsdcAfter = await getAccount(provider.connection, liquidatorUsdcAccount);
+        expect(liquidatorUsdcBefore.amount - liquidatorUsdcAfter.amount).to.equal(800_000n);
+        const liquidatorSolAfter = await provider.connection.getBalance(liquidator.publicKey);
+        expect(liquidatorSolAfter - liquidatorSolBefore).to.equal(20_000_000);
+
+        // The loan is closed and the unpaid debt written off the market and the borrower's index
+        expect(await provider.connection.getAccountInfo(loanPda(loanId))).to.be.null;
+        const indexAfter = await program.account.borrowerIndex.fetch(borrowerIndex);
+        expect(indexAfter.openLoanIds.map((id) => id.toNumber())).to.not.include(loanId.toNumber());
+        expect(indexBefore.totalCollateral.sub(indexAfter.totalCollateral).toString()).to.equal(loan.collateral.toString());
+        expect(indexBefore.outstandingPrincipal.sub(indexAfter.outstandingPrincipal).toString()).to.equal(loan.principal.toString());
+        const marketAfter = await program.account.dataAccount.fetch(shrubPda);
+        expect(marketBefore.totalPrincipal.sub(marketAfter.totalPrincipal).toString()).to.equal(loan.principal.toString());
+      });
+    });
+
+    describe('add_collateral', function () {
+      let loanId: anchor.BN;
+      let bot: anchor.web3.Keypair;
+
+      async function addCollateral(lamports: number) {
+        await program.methods.addCollateral(loanId, new anchor.BN(lamports))
+          .accounts({
+            pdaAccount: shrubPda,
+            admin: adminAccount.publicKey,
+            depositor: bot.publicKey,
+            loan: loanPda(loanId),
+            borrowerIndex,
+            systemProgram: SYSTEM_PROGRAM,
+          })
+          .signers([bot])
+          .rpc();
+      }
+
+      async function healthFactorBps(): Promise<number> {
+        const health = await program.methods.loanHealth(loanId)
+          .accounts({
+            pdaAccount: shrubPda,
+            admin: adminAccount.publicKey,
+            loan: loanPda(loanId),
+            price: {
+              oracleProgram: mockChainlink.programId,
+              priceFeed: chainlinkFeed.publicKey,
+            },
+          })
+          .view();
+        return health.healthFactorBps.toNumber();
+      }
+
+      before(async function () {
+        bot = anchor.web3.Keypair.generate();
+        const latestBlockhash = await provider.connection.getLatestBlockhash();
+        const signature = await provider.connection.requestAirdrop(bot.publicKey, 1_000_000_000);
+        await provider.connection.confirmTransaction({
+          signature,
+          blockhash: latestBlockhash.blockhash,
+          lastValidBlockHeight: latestBlockhash.lastValidBlockHeight,
+        });
+
+        // 1 USDC against 0.02 SOL ($2 at $100/SOL): exactly the 50% LTV of the 8% tier
+        loanId = (await program.account.dataAccount.fetch(shrubPda)).nextLoanId;
+        await program.methods.takeLoan(new anchor.BN(1_000_000), 800, new anchor.BN(20_000_000), { oneMonth: {} })
+          .accounts({
+            pdaAccount: shrubPda,
+            admin: adminAccount.publicKey,
+            user: userAccount.publicKey,
+            loan: loanPda(loanId),
+            borrowerIndex,
+            marketConfig,
+            price: {
+              oracleProgram: mockChainlink.programId,
+              priceFeed: chainlinkFeed.publicKey,
+            },
+            userUsdcAccount,
+            shrubUsdcAccount,
+            usdcMint,
+            systemProgram: SYSTEM_PROGRAM,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          })
+          .signers([userAccount])
+          .rpc();
+      });
+
+      after(async function () {
+        await setSolPrice(SOL_PRICE);
+      });
+
+      it('rejects adding no collateral', async function () {
+        try {
+          await addCollateral(0);
+          expect.fail("Expected error for zero collateral");
+        } catch (err: any) {
+          expect(err.message).to.include("Collateral amount must be positive");
+        }
+      });
+
+      it('lets anyone top up a loan that is about to be liquidated', async function () {
+        // At $65/SOL the collateral is worth $1.30, an LTV of ~77% against the 70% threshold
+        await setSolPrice(new anchor.BN(65_00000000));
+        expect(await healthFactorBps()).to.be.lt(10_000);
+
+        const indexBefore = await program.account.borrowerIndex.fetch(borrowerIndex);
+        const shrubSolBefore = await provider.connection.getBalance(shrubPda);
+
+        await addCollateral(10_000_000);
+
+        // The SOL moves into the PDA and the loan is healthy again
+        expect(await provider.connection.getBalance(shrubPda) - shrubSolBefore).to.equal(10_000_000);
+        const loan = await program.account.loan.fetch(loanPda(loanId));
+        expect(loan.collateral.toNumber()).to.equal(30_000_000);
+        const indexAfter = await program.account.borrowerIndex.fetch(borrowerIndex);
+        expect(indexAfter.totalCollateral.sub(indexBefore.totalCollateral).toNumber()).to.equal(10_000_000);
+        expect(await healthFactorBps()).to.be.gte(10_000);
+      });
+    });
+
+    describe('withdraw_collateral', function () {
+      let loanId: anchor.BN;
+
+      async function withdrawCollateral(lamports: number, signer: anchor.web3.Keypair = userAccount) {
+        await program.methods.withdrawCollateral(loanId, new anchor.BN(lamports))
+          .accounts({
+            pdaAccount: shrubPda,
+            admin: adminAccount.publicKey,
+            user: signer.publicKey,
+            loan: loanPda(loanId),
+            borrowerIndex,
+            price: {
+              oracleProgram: mockChainlink.programId,
+              priceFeed: chainlinkFeed.publicKey,
+            },
+          })
+          .signers([signer])
+          .rpc();
+      }
+
+      before(async function () {
+        // 1 USDC against 0.03 SOL ($3 at $100/SOL): 0.01 SOL above the 50% LTV of the 8% tier
+        loanId = (await program.account.dataAccount.fetch(shrubPda)).nextLoanId;
+        await program.methods.takeLoan(new anchor.BN(1_000_000), 800, new anchor.BN(30_000_000), { oneMonth: {} })
+          .accounts({
+            pdaAccount: shrubPda,
+            admin: adminAccount.publicKey,
+            user: userAccount.publicKey,
+            loan: loanPda(loanId),
+            borrowerIndex,
+            marketConfig,
+            price: {
+              oracleProgram: mockChainlink.programId,
+              priceFeed: chainlinkFeed.publicKey,
+            },
+            userUsdcAccount,
+            shrubUsdcAccount,
+            usdcMint,
+            systemProgram: SYSTEM_PROGRAM,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          })
+          .signers([userAccount])
+          .rpc();
+      });
+
+      it('prevents non-borrowers from withdrawing collateral', async function () {
+        try {
+          await withdrawCollateral(1_000_000, adminAccount);
+          expect.fail("Expected error for unauthorized withdrawal");
+        } catch (err: any) {
+          expect(err.message).to.include("Unauthorized");
+        }
+      });
+
+      it('withdraws collateral above the origination LTV', async function () {
+        const indexBefore = await program.account.borrowerIndex.fetch(borrowerIndex);
+        const userSolBefore = await provider.connection.getBalance(userAccount.publicKey);
+
+        await withdrawCollateral(9_000_000);
+
+        // The borrower pays the transaction fee out of the withdrawn SOL
+        const userSolAfter = await provider.connection.getBalance(userAccount.publicKey);
+        expect(userSolAfter - userSolBefore).to.be.gt(8_990_000);
+        const loan = await program.account.loan.fetch(loanPda(loanId));
+        expect(loan.collateral.toNumber()).to.equal(21_000_000);
+        const indexAfter = await program.account.borrowerIndex.fetch(borrowerIndex);
+        expect(indexBefore.totalCollateral.sub(indexAfter.totalCollateral).toNumber()).to.equal(9_000_000);
+      });
+
+      it('rejects a withdrawal that would break the origination LTV', async function () {
+        // Anything below 0.02 SOL breaks the 50% LTV
+        try {
+          await withdrawCollateral(1_000_001);
+          expect.fail("Expected error for insufficient collateral");
+        } catch (err: any) {
+          expect(err.message).to.include("Insufficient collateral provided");
+        }
+      });
+    });
+
+    describe('increase_principal', function () {
+      let loanId: anchor.BN;
+
+      async function increasePrincipal(amount: number, id: anchor.BN = loanId) {
+        await program.methods.increasePrincipal(id, new anchor.BN(amount))
+          .accounts({
+            pdaAccount: shrubPda,
+            admin: adminAccount.publicKey,
+            user: userAccount.publicKey,
+            loan: loanPda(id),
+            borrowerIndex,
+            marketConfig,
+            price: {
+              oracleProgram: mockChainlink.programId,
+              priceFeed: chainlinkFeed.publicKey,
+            },
+            userUsdcAccount,
+            shrubUsdcAccount,
+            tokenProgram: TOKEN_PROGRAM_ID,
+          })
+          .signers([userAccount])
+          .rpc();
+      }
+
+      before(async function () {
+        // 1 USDC against 0.03 SOL ($3 at $100/SOL), enough for 1.5 USDC at the 50% LTV of the 8% tier
+        loanId = (await program.account.dataAccount.fetch(shrubPda)).nextLoanId;
+        await program.methods.takeLoan(new anchor.BN(1_000_000), 800, new anchor.BN(30_000_000), { oneMonth: {} })
+          .accounts({
+            pdaAccount: shrubPda,
+            admin: adminAccount.publicKey,
+            user: userAccount.publicKey,
+            loan: loanPda(loanId),
+            borrowerIndex,
+            marketConfig,
+            price: {
+              oracleProgram: mockChainlink.programId,
+              priceFeed: chainlinkFeed.publicKey,
+            },
+            userUsdcAccount,
+            shrubUsdcAccount,
+            usdcMint,
+            systemProgram: SYSTEM_PROGRAM,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          })
+          .signers([userAccount])
+          .rpc();
+      });
+
+      it('borrows more against the same loan', async function () {
+        const indexBefore = await program.account.borrowerIndex.fetch(borrowerIndex);
+        const userUsdcBefore = await getAccount(provider.connection, userUsdcAccount);
+        const shrubUsdcBefore = await getAccount(provider.connection, shrubUsdcAccount);
+
+        await increasePrincipal(500_000);
+
+        const userUsdcAfter = await getAccount(provider.connection, userUsdcAccount);
+        expect(userUsdcAfter.amount - userUsdcBefore.amount).to.equal(500_000n);
+        const shrubUsdcAfter = await getAccount(provider.connection, shrubUsdcAccount);
+        expect(shrubUsdcBefore.amount - shrubUsdcAfter.amount).to.equal(500_000n);
+
+        // The position stays a single loan
+        const loan = await program.account.loan.fetch(loanPda(loanId));
+        expect(loan.principal.toNumber()).to.equal(1_500_000);
+        const indexAfter = await program.account.borrowerIndex.fetch(borrowerIndex);
+        expect(indexAfter.openLoanIds.length).to.equal(indexBefore.openLoanIds.length);
+        expect(indexAfter.outstandingPrincipal.sub(indexBefore.outstandingPrincipal).toNumber()).to.equal(500_000);
+      });
+
+      it('rejects borrowing beyond the tier LTV', async function () {
+        try {
+          await increasePrincipal(1);
+          expect.fail("Expected error for insufficient collateral");
+        } catch (err: any) {
+          expect(err.message).to.include("Insufficient collateral provided");
+        }
+      });
+
+      it('rejects borrowing more once the tier is disabled', async function () {
+        const manageTiers = (method: any) => method
+          .accounts({ admin: adminAccount.publicKey, pdaAccount: shrubPda, marketConfig })
+          .signers([adminAccount])
+          .rpc();
+        await manageTiers(program.methods.addTier(400, 3000, 5000));
+
+        // 1 USDC against 0.06 SOL ($6 at $100/SOL), enough for 1.8 USDC at the 30% LTV
+        const disabledLoanId = (await program.account.dataAccount.fetch(shrubPda)).nextLoanId;
+        await program.methods.takeLoan(new anchor.BN(1_000_000), 400, new anchor.BN(60_000_000), { oneMonth: {} })
+          .accounts({
+            pdaAccount: shrubPda,
+            admin: adminAccount.publicKey,
+            user: userAccount.publicKey,
+            loan: loanPda(disabledLoanId),
+            borrowerIndex,
+            marketConfig,
+            price: {
+              oracleProgram: mockChainlink.programId,
+              priceFeed: chainlinkFeed.publicKey,
+            },
+            userUsdcAccount,
+            shrubUsdcAccount,
+            usdcMint,
+            systemProgram: SYSTEM_PROGRAM,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          })
+          .signers([userAccount])
+          .rpc();
+        await manageTiers(program.methods.disableTier(400));
+
+        try {
+          await increasePrincipal(100_000, disabledLoanId);
+          expect.fail("Expected error for a disabled tier");
+        } catch (err: any) {
+          expect(err.message).to.include("Invalid APY provided");
+        }
+      });
+
+      it('rejects borrowing beyond a tier LTV lowered since the loan was taken', async function () {
+        const manageTiers = (method: any) => method
+          .accounts({ admin: adminAccount.publicKey, pdaAccount: shrubPda, marketConfig })
+          .signers([adminAccount])
+          .rpc();
+        await manageTiers(program.methods.addTier(450, 3000, 5000));
+
+        // 1 USDC against 0.06 SOL ($6 at $100/SOL), enough for 1.8 USDC at the 30% LTV
+        const loweredLoanId = (await program.account.dataAccount.fetch(shrubPda)).nextLoanId;
+        await program.methods.takeLoan(new anchor.BN(1_000_000), 450, new anchor.BN(60_000_000), { oneMonth: {} })
+          .accounts({
+            pdaAccount: shrubPda,
+            admin: adminAccount.publicKey,
+            user: userAccount.publicKey,
+            loan: loanPda(loweredLoanId),
+            borrowerIndex,
+            marketConfig,
+            price: {
+              oracleProgram: mockChainlink.programId,
+              priceFeed: chainlinkFeed.publicKey,
+            },
+            userUsdcAccount,
+            shrubUsdcAccount,
+            usdcMint,
+            systemProgram: SYSTEM_PROGRAM,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          })
+          .signers([userAccount])
+          .rpc();
+        // At 20% LTV the collateral covers only 1.2 USDC
+        await manageTiers(program.methods.updateTier(450, 2000, 5000));
+
+        try {
+          await increasePrincipal(300_000, loweredLoanId);
+          expect.fail("Expected error for insufficient collateral");
+        } catch (err: any) {
+          expect(err.message).to.include("Insufficient collateral provided");
+        } finally {
+          await manageTiers(program.methods.disableTier(450));
+        }
+      });
+    });
+
+    describe('refinance_loan', function () {
+      let loanId: anchor.BN;
+
+      async function refinanceLoan(newApy: number) {
+        await program.methods.refinanceLoan(loanId, newApy)
+          .accounts({
+            pdaAccount: shrubPda,
+            admin: adminAccount.publicKey,
+            user: userAccount.publicKey,
+            loan: loanPda(loanId),
+            borrowerIndex,
+            marketConfig,
+            price: {
+              oracleProgram: mockChainlink.programId,
+              priceFeed: chainlinkFeed.publicKey,
+            },
+            systemProgram: SYSTEM_PROGRAM,
+          })
+          .signers([userAccount])
+          .rpc();
+      }
+
+      before(async function () {
+        // 1 USDC against 0.05 SOL ($5 at $100/SOL): exactly the 20% LTV of the 0% tier
+        loanId = (await program.account.dataAccount.fetch(shrubPda)).nextLoanId;
+        await program.methods.takeLoan(new anchor.BN(1_000_000), 0, new anchor.BN(50_000_000), { oneMonth: {} })
+          .accounts({
+            pdaAccount: shrubPda,
+            admin: adminAccount.publicKey,
+            user: userAccount.publicKey,
+            loan: loanPda(loanId),
+            borrowerIndex,
+            marketConfig,
+            price: {
+              oracleProgram: mockChainlink.programId,
+              priceFeed: chainlinkFeed.publicKey,
+            },
+            userUsdcAccount,
+            shrubUsdcAccount,
+            usdcMint,
+            systemProgram: SYSTEM_PROGRAM,
+            tokenProgram: TOKEN_PROGRAM_ID,
+            associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+          })
+          .signers([userAccount])
+          .rpc();
+      });
+
+      it('rejects refinancing into a disabled tier', async function () {
+        try {
+          await refinanceLoan(300);
+          expect.fail("Expected error for disabled tier");
+        } catch (err: any) {
+          expect(err.message).to.include("Invalid APY provided");
+        }
+      });
+
+      it('releases collateral when moving to a higher LTV tier', async function () {
+        const indexBefore = await program.account.borrowerIndex.fetch(borrowerIndex);
+        const shrubSolBefore = await provider.connection.getBalance(shrubPda);
+
+        await refinanceLoan(800);
+
+        // At 50% LTV the loan needs 0.02 SOL instead of 0.05
+        expect(shrubSolBefore - await provider.connection.getBalance(shrubPda)).to.equal(30_000_000);
+        const loan = await program.account.loan.fetch(loanPda(loanId));
+        expect(loan.apy).to.equal(800);
+        expect(loan.ltv).to.equal(5000);
+        expect(loan.liquidationThreshold).to.equal(7000);
+        expect(loan.collateral.toNumber()).to.equal(20_000_000);
+        const indexAfter = await program.account.borrowerIndex.fetch(borrowerIndex);
+        expect(indexBefore.totalCollateral.sub(indexAfter.totalCollateral).toNumber()).to.equal(30_000_000);
+      });
+
+      it('requires collateral when moving to a lower LTV tier', async function () {
+        const shrubSolBefore = await provider.connection.getBalance(shrubPda);
+
+        await refinanceLoan(100);
+
+        // At 25% LTV the loan needs 0.04 SOL
+        expect(await provider.connection.getBalance(shrubPda) - shrubSolBefore).to.equal(20_000_000);
+        const loan = await program.account.loan.fetch(loanPda(loanId));
+        expect(loan.apy).to.equal(100);
+        expect(loan.ltv).to.equal(2500);
+        expect(loan.collateral.toNumber()).to.equal(40_000_000);
+      });
+    });
   });
 });
\ No newline at end of file
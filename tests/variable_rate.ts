@@ -0,0 +1,280 @@
+// variable_rate.ts (variable-rate lending tests)
+//
+// These run against bankrun rather than the local validator so the borrow index grows over an
+// exact time.
+import * as anchor from "@coral-xyz/anchor";
+import { expect } from 'chai';
+import { RadarLend } from "../target/types/radar_lend";
+import {
+  TOKEN_PROGRAM_ID,
+  ASSOCIATED_TOKEN_PROGRAM_ID,
+  ACCOUNT_SIZE,
+  createInitializeAccount3Instruction,
+} from '@solana/spl-token';
+import {
+  DAY,
+  Market,
+  SYSTEM_PROGRAM,
+  borrowerIndexPda,
+  createUsdcAccount,
+  fund,
+  loanPda,
+  rent,
+  setMockPrice,
+  setTime,
+  setupMarket,
+  usdcBalance,
+} from "./helpers";
+
+const { web3 } = anchor;
+const YEAR = 365 * DAY;
+const WAD = "1000000000000000000";
+
+describe('variable-rate lending', function () {
+  this.timeout(20000);
+
+  let market: Market;
+  let program: anchor.Program<RadarLend>;
+
+  let adminAccount: anchor.web3.Keypair;
+  let userAccount: anchor.web3.Keypair;
+  let usdcMint: anchor.web3.PublicKey;
+  let shrubPda: anchor.web3.PublicKey;
+  let marketConfig: anchor.web3.PublicKey;
+  let borrowerIndex: anchor.web3.PublicKey;
+  let mockPrice: anchor.web3.PublicKey;
+  let shrubUsdcAccount: anchor.web3.PublicKey;
+  let userUsdcAccount: anchor.web3.PublicKey;
+  let loanId: anchor.BN;
+  let createdAt: bigint;
+
+  // 2% base, +4% up to 80% utilization, then +75% up to 100%
+  const RATE_MODEL = { baseRateBps: 200, slope1Bps: 400, kinkBps: 8_000, slope2Bps: 7_500 };
+
+  // Moves the clock to `seconds` after the first loan was taken, on a new slot
+  async function warpTo(seconds: number) {
+    await setTime(market, createdAt + BigInt(seconds));
+  }
+
+  async function setRateModel(
+    rateMode: any,
+    rateModel: typeof RATE_MODEL,
+    poolAccount: anchor.web3.PublicKey = shrubUsdcAccount
+  ) {
+    await program.methods.setRateModel(rateMode, rateModel)
+      .accounts({
+        admin: adminAccount.publicKey,
+        pdaAccount: shrubPda,
+        shrubUsdcAccount: poolAccount,
+      })
+      .signers([adminAccount])
+      .rpc();
+  }
+
+  async function takeLoan(principal: number, collateral: number): Promise<anchor.BN> {
+    const id = (await program.account.dataAccount.fetch(shrubPda)).nextLoanId;
+    await program.methods.takeLoan(new anchor.BN(principal), 800, new anchor.BN(collateral), { twelveMonth: {} })
+      .accounts({
+        pdaAccount: shrubPda,
+        admin: adminAccount.publicKey,
+        user: userAccount.publicKey,
+        loan: loanPda(market, id),
+        borrowerIndex,
+        marketConfig,
+        price: {
+          oracleProgram: program.programId,
+          priceFeed: mockPrice,
+        },
+        userUsdcAccount,
+        shrubUsdcAccount,
+        usdcMint,
+        systemProgram: SYSTEM_PROGRAM,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([userAccount])
+      .rpc();
+    return id;
+  }
+
+  before(async function () {
+    // A market with a 4 USDC pool
+    market = await setupMarket(4_000_000);
+    ({ program, usdcMint, shrubPda, marketConfig, mockPrice, shrubUsdcAccount } = market);
+    adminAccount = market.admin;
+
+    userAccount = anchor.web3.Keypair.generate();
+    await fund(market, userAccount);
+    borrowerIndex = borrowerIndexPda(market, userAccount.publicKey);
+    userUsdcAccount = await createUsdcAccount(market, userAccount.publicKey, 1_000_000);
+  });
+
+  it('starts markets at fixed rates with a unit borrow index', async function () {
+    const marketData = await program.account.dataAccount.fetch(shrubPda);
+    expect(marketData.rateMode).to.deep.equal({ fixed: {} });
+    expect(marketData.borrowIndex.toString()).to.equal(WAD);
+    expect(marketData.totalPrincipal.toNumber()).to.equal(0);
+  });
+
+  it('rejects a rate model without a kink', async function () {
+    try {
+      await setRateModel({ variable: {} }, { ...RATE_MODEL, kinkBps: 0 });
+      expect.fail("Expected error for invalid rate model");
+    } catch (err: any) {
+      expect(err.message).to.include("Invalid rate model");
+    }
+  });
+
+  it('prices utilization only from the pool account', async function () {
+    // An empty token account the PDA owns, but not its associated account
+    const other = anchor.web3.Keypair.generate();
+    await market.provider.sendAndConfirm(new web3.Transaction().add(
+      web3.SystemProgram.createAccount({
+        fromPubkey: market.context.payer.publicKey,
+        newAccountPubkey: other.publicKey,
+        space: ACCOUNT_SIZE,
+        lamports: Number(await rent(market, ACCOUNT_SIZE)),
+        programId: TOKEN_PROGRAM_ID,
+      }),
+      createInitializeAccount3Instruction(other.publicKey, usdcMint, shrubPda),
+    ), [other]);
+
+    try {
+      await setRateModel({ variable: {} }, RATE_MODEL, other.publicKey);
+      expect.fail("Expected error for a token account other than the pool");
+    } catch (err: any) {
+      expect(err.message).to.include("An associated constraint was violated");
+    }
+
+    const marketData = await program.account.dataAccount.fetch(shrubPda);
+    expect(marketData.rateMode).to.deep.equal({ fixed: {} });
+  });
+
+  it('switches the market to variable rates', async function () {
+    await setRateModel({ variable: {} }, RATE_MODEL);
+
+    const marketData = await program.account.dataAccount.fetch(shrubPda);
+    expect(marketData.rateMode).to.deep.equal({ variable: {} });
+    expect(marketData.rateModel).to.deep.equal(RATE_MODEL);
+    // Nothing is borrowed, so the rate is the base rate
+    expect(marketData.borrowRate).to.equal(200);
+  });
+
+  it('prices the borrow rate from utilization', async function () {
+    // 1 USDC against 0.02 SOL: 1 of 4 USDC borrowed is 25% utilization, 2% + 4% * 25/80
+    loanId = await takeLoan(1_000_000, 20_000_000);
+    const loan = await program.account.loan.fetch(loanPda(market, loanId));
+    createdAt = BigInt(loan.createdAt.toString());
+    expect(loan.rateMode).to.deep.equal({ variable: {} });
+    expect(loan.borrowIndex.toString()).to.equal(WAD);
+
+    const marketData = await program.account.dataAccount.fetch(shrubPda);
+    expect(marketData.totalPrincipal.toNumber()).to.equal(1_000_000);
+    expect(marketData.borrowRate).to.equal(325);
+  });
+
+  it('steepens the rate above the kink', async function () {
+    // 3.6 of 4 USDC borrowed is 90% utilization, 2% + 4% + 75% * 10/20
+    await takeLoan(2_600_000, 52_000_000);
+
+    const marketData = await program.account.dataAccount.fetch(shrubPda);
+    expect(marketData.totalPrincipal.toNumber()).to.equal(3_600_000);
+    expect(marketData.borrowRate).to.equal(4_350);
+  });
+
+  it('grows variable-rate debt with the borrow index', async function () {
+    // A year at 43.5% grows the index, and the first loan's debt with it, by 43.5%
+    await warpTo(YEAR);
+    const usdcBefore = await usdcBalance(market, userUsdcAccount);
+
+    await program.methods.repayLoan(loanId)
+      .accounts({
+        pdaAccount: shrubPda,
+        admin: adminAccount.publicKey,
+        user: userAccount.publicKey,
+        loan: loanPda(market, loanId),
+        borrowerIndex,
+        userUsdcAccount,
+        shrubUsdcAccount,
+        usdcMint,
+        systemProgram: SYSTEM_PROGRAM,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([userAccount])
+      .rpc();
+
+    expect(usdcBefore - await usdcBalance(market, userUsdcAccount)).to.equal(1_435_000n);
+    const marketData = await program.account.dataAccount.fetch(shrubPda);
+    expect(marketData.borrowIndex.toString()).to.equal("1435000000000000000");
+    expect(marketData.totalPrincipal.toNumber()).to.equal(2_600_000);
+    // 2.6 of 4.435 USDC borrowed is 58.62% utilization, 2% + 4% * 58.62/80
+    expect(marketData.borrowRate).to.equal(493);
+  });
+
+  it('reprices the rate when an extension capitalizes interest', async function () {
+    // The second loan's 1.131 USDC of interest becomes principal
+    await setMockPrice(market, 100_000_000);
+    const secondLoanId = loanId.addn(1);
+    await program.methods.extendLoan(secondLoanId, { twelveMonth: {} })
+      .accounts({
+        pdaAccount: shrubPda,
+        admin: adminAccount.publicKey,
+        user: userAccount.publicKey,
+        loan: loanPda(market, secondLoanId),
+        borrowerIndex,
+        marketConfig,
+        price: {
+          oracleProgram: program.programId,
+          priceFeed: mockPrice,
+        },
+        shrubUsdcAccount,
+      })
+      .signers([userAccount])
+      .rpc();
+
+    const loan = await program.account.loan.fetch(loanPda(market, secondLoanId));
+    expect(loan.principal.toNumber()).to.equal(3_731_000);
+    const marketData = await program.account.dataAccount.fetch(shrubPda);
+    expect(marketData.totalPrincipal.toNumber()).to.equal(3_731_000);
+    // 3.731 of 5.566 USDC borrowed is 67.03% utilization, 2% + 4% * 67.03/80
+    expect(marketData.borrowRate).to.equal(535);
+  });
+
+  it('charges the minimum interest at the tier APY', async function () {
+    await program.methods.setEarlyRepayment(800, { minimumInterest: { period: 30 * DAY } })
+      .accounts({
+        admin: adminAccount.publicKey,
+        pdaAccount: shrubPda,
+        marketConfig,
+      })
+      .signers([adminAccount])
+      .rpc();
+    // 3.831 of 5.566 USDC borrowed is 68.82% utilization, 2% + 4% * 68.82/80
+    const minimumLoanId = await takeLoan(100_000, 2_000_000);
+    expect((await program.account.dataAccount.fetch(shrubPda)).borrowRate).to.equal(544);
+
+    await warpTo(YEAR + 10 * DAY);
+    const usdcBefore = await usdcBalance(market, userUsdcAccount);
+    await program.methods.repayLoan(minimumLoanId)
+      .accounts({
+        pdaAccount: shrubPda,
+        admin: adminAccount.publicKey,
+        user: userAccount.publicKey,
+        loan: loanPda(market, minimumLoanId),
+        borrowerIndex,
+        userUsdcAccount,
+        shrubUsdcAccount,
+        usdcMint,
+        systemProgram: SYSTEM_PROGRAM,
+        tokenProgram: TOKEN_PROGRAM_ID,
+        associatedTokenProgram: ASSOCIATED_TOKEN_PROGRAM_ID,
+      })
+      .signers([userAccount])
+      .rpc();
+
+    // 10 days at 5.44% through the borrow index is 149 of interest. The fee is the 8% tier APY
+    // for the other 20 days of the period, 657 - 219, whatever the variable rate was
+    expect(usdcBefore - await usdcBalance(market, userUsdcAccount)).to.equal(100_000n + 149n + 438n);
+  });
+});